@@ -12,3 +12,23 @@ pub struct LoginReq {
 pub struct RefreshReq {
     pub refresh_token: String,
 }
+
+#[derive(Debug, Deserialize, Validate, ToSchema)]
+pub struct MfaCodeReq {
+    /// A 6-digit TOTP code, or a recovery code
+    #[validate(length(min = 6, max = 32))]
+    pub code: String,
+}
+
+#[derive(Debug, Deserialize, Validate, ToSchema)]
+pub struct MfaChallengeReq {
+    pub challenge_token: String,
+}
+
+#[derive(Debug, Deserialize, Validate, ToSchema)]
+pub struct MfaLoginReq {
+    pub challenge_token: String,
+    /// A 6-digit TOTP code, or a recovery code
+    #[validate(length(min = 6, max = 32))]
+    pub code: String,
+}
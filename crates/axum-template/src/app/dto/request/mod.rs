@@ -2,10 +2,12 @@ pub mod auth;
 pub mod chore;
 pub mod common;
 pub mod pagination;
+pub mod role;
 pub mod user;
 
 pub use auth::*;
 pub use chore::*;
 pub use common::*;
 pub use pagination::*;
+pub use role::*;
 pub use user::*;
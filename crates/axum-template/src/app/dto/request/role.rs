@@ -0,0 +1,58 @@
+use serde::Deserialize;
+use utoipa::ToSchema;
+use validator::Validate;
+
+use crate::domain::{db::Pk, model::Perm};
+
+#[derive(Debug, Deserialize, Validate, ToSchema)]
+pub struct CreateRoleReq {
+    #[validate(length(
+        min = 1,
+        max = 50,
+        message = "Role name must be between 1 and 50 characters"
+    ))]
+    pub name: String,
+    pub description: Option<String>,
+    #[serde(default)]
+    pub permissions: Vec<Perm>,
+}
+
+#[derive(Debug, Deserialize, Validate, ToSchema)]
+/// Omitted fields are left unchanged.
+pub struct UpdateRoleReq {
+    #[validate(length(
+        min = 1,
+        max = 50,
+        message = "Role name must be between 1 and 50 characters"
+    ))]
+    pub name: Option<String>,
+    /// `null` clears the description.
+    #[serde(default, with = "serde_with::rust::double_option")]
+    #[schema(value_type = Option<String>)]
+    pub description: Option<Option<String>>,
+}
+
+#[derive(Debug, Deserialize, Validate, ToSchema)]
+pub struct SetRolePermsReq {
+    pub permissions: Vec<Perm>,
+}
+
+#[derive(Debug, Deserialize, Validate, ToSchema)]
+pub struct SetRoleMfaReq {
+    /// Require members to log in with a second factor
+    pub required: bool,
+}
+
+#[derive(Debug, Deserialize, Validate, ToSchema)]
+pub struct AssignRoleReq {
+    #[validate(range(min = 1))]
+    pub role_id: Pk,
+}
+
+#[derive(Debug, Deserialize, Validate)]
+pub struct UserRolePath {
+    #[validate(range(min = 1))]
+    pub id: Pk,
+    #[validate(range(min = 1))]
+    pub role_id: Pk,
+}
@@ -31,5 +31,13 @@ pub struct ChangePasswordReq {
 
 #[derive(Debug, Deserialize, Validate, ToSchema)]
 pub struct ResetPasswordReq {
+    #[validate(length(
+        min = 8,
+        max = 32,
+        message = "Password must be between 8 and 32 characters"
+    ))]
     pub new_password: String,
+    /// Require the user to change the password after their next login
+    #[serde(default)]
+    pub force_change: bool,
 }
@@ -2,7 +2,11 @@ use serde::Serialize;
 use utoipa::ToSchema;
 
 use super::UserResp;
-use crate::domain::model::Perm;
+use crate::domain::{
+    MfaStatus, PendingMfa, TotpEnrollment,
+    db::Pk,
+    model::{Perm, Session},
+};
 
 #[derive(Debug, Serialize, ToSchema)]
 pub struct LoginResp {
@@ -22,3 +26,103 @@ pub struct AuthStateResp {
     pub user: UserResp,
     pub permissions: Vec<Perm>,
 }
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct CsrfResp {
+    pub token: String,
+    /// Header to send the token in on state-changing requests.
+    pub header_name: String,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct SessionResp {
+    pub id: Pk,
+    pub user_agent: Option<String>,
+    pub ip: Option<String>,
+    /// Whether this is the session the request was made with.
+    pub current: bool,
+    #[schema(value_type = String)]
+    pub created_at: jiff::Timestamp,
+    #[schema(value_type = String)]
+    pub last_seen_at: jiff::Timestamp,
+    #[schema(value_type = String)]
+    pub expires_at: jiff::Timestamp,
+}
+
+impl SessionResp {
+    pub fn new(session: Session, current_session_id: Option<&str>) -> Self {
+        Self {
+            id: session.id,
+            current: current_session_id.is_some_and(|id| session.matches(id)),
+            user_agent: session.user_agent,
+            ip: session.ip,
+            created_at: session.created_at,
+            last_seen_at: session.last_seen_at,
+            expires_at: session.expires_at,
+        }
+    }
+}
+
+/// Returned with `202 Accepted` by the login endpoints when a second factor
+/// is needed.
+#[derive(Debug, Serialize, ToSchema)]
+pub struct MfaChallengeResp {
+    pub challenge_token: String,
+    /// Enroll an authenticator with this challenge before completing login.
+    pub enrollment_required: bool,
+    #[schema(value_type = String)]
+    pub expires_at: jiff::Timestamp,
+}
+
+impl From<PendingMfa> for MfaChallengeResp {
+    fn from(pending: PendingMfa) -> Self {
+        Self {
+            challenge_token: pending.challenge_token,
+            enrollment_required: pending.enrollment_required,
+            expires_at: pending.expires_at,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct TotpEnrollmentResp {
+    /// Base32 secret, for entering into the authenticator by hand.
+    pub secret: String,
+    /// `otpauth://` URI to render as a QR code.
+    pub otpauth_uri: String,
+    /// One-time codes for when the authenticator is lost; shown only once.
+    pub recovery_codes: Vec<String>,
+}
+
+impl From<TotpEnrollment> for TotpEnrollmentResp {
+    fn from(enrollment: TotpEnrollment) -> Self {
+        Self {
+            secret: enrollment.secret,
+            otpauth_uri: enrollment.otpauth_uri,
+            recovery_codes: enrollment.recovery_codes,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct RecoveryCodesResp {
+    pub recovery_codes: Vec<String>,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct MfaStatusResp {
+    pub enabled: bool,
+    /// One of the user's roles requires a second factor.
+    pub required: bool,
+    pub recovery_codes_remaining: usize,
+}
+
+impl From<MfaStatus> for MfaStatusResp {
+    fn from(status: MfaStatus) -> Self {
+        Self {
+            enabled: status.enabled,
+            required: status.required,
+            recovery_codes_remaining: status.recovery_codes_remaining,
+        }
+    }
+}
@@ -1,7 +1,11 @@
 pub mod auth;
 pub mod chore;
+pub mod health;
+pub mod role;
 pub mod user;
 
 pub use auth::*;
 pub use chore::*;
+pub use health::*;
+pub use role::*;
 pub use user::*;
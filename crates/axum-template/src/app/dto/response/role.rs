@@ -0,0 +1,43 @@
+use serde::Serialize;
+use utoipa::ToSchema;
+
+use crate::domain::{
+    db::Pk,
+    model::{Perm, Role},
+};
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct RoleResp {
+    pub id: Pk,
+    pub name: String,
+    pub description: Option<String>,
+    pub permissions: Vec<Perm>,
+    /// Members must log in with a second factor.
+    pub require_mfa: bool,
+    #[schema(value_type = String)]
+    pub created_at: jiff::Timestamp,
+    #[schema(value_type = String)]
+    pub updated_at: jiff::Timestamp,
+}
+
+impl From<Role> for RoleResp {
+    fn from(role: Role) -> Self {
+        Self {
+            id: role.id,
+            permissions: role.parse_perms(),
+            name: role.name,
+            description: role.description,
+            require_mfa: role.require_mfa,
+            created_at: role.created_at,
+            updated_at: role.updated_at,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct RoleListResp {
+    pub roles: Vec<RoleResp>,
+    pub total: u64,
+    pub page: u64,
+    pub per_page: u64,
+}
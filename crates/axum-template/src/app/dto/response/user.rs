@@ -7,6 +7,7 @@ use crate::domain::{db::Pk, model::User};
 pub struct UserResp {
     pub id: Pk,
     pub username: String,
+    pub must_change_password: bool,
     #[schema(value_type = String)]
     pub created_at: jiff::Timestamp,
     #[schema(value_type = String)]
@@ -18,6 +19,7 @@ impl From<User> for UserResp {
         Self {
             id: user.id,
             username: user.username,
+            must_change_password: user.must_change_password,
             created_at: user.created_at,
             updated_at: user.updated_at,
         }
@@ -2,7 +2,7 @@ use std::fmt::Debug;
 
 use axum::{
     Json,
-    http::StatusCode,
+    http::{HeaderValue, StatusCode, header},
     response::{IntoResponse, Response},
 };
 use serde::Serialize;
@@ -38,11 +38,16 @@ fn error_status_code(kind: &ErrorKind) -> StatusCode {
         | ErrorKind::InvalidParameter
         | ErrorKind::ValidationFailed => StatusCode::BAD_REQUEST,
         ErrorKind::Unauthorized => StatusCode::UNAUTHORIZED,
-        ErrorKind::Forbidden | ErrorKind::PermissionDenied | ErrorKind::InvalidCredentials => {
-            StatusCode::FORBIDDEN
-        }
+        ErrorKind::Forbidden
+        | ErrorKind::PermissionDenied
+        | ErrorKind::InvalidCredentials
+        | ErrorKind::CsrfFailed
+        | ErrorKind::PasswordChangeRequired => StatusCode::FORBIDDEN,
         ErrorKind::NotFound => StatusCode::NOT_FOUND,
         ErrorKind::AlreadyExists => StatusCode::CONFLICT,
+        ErrorKind::TooManyAttempts => StatusCode::TOO_MANY_REQUESTS,
+        ErrorKind::AccountLocked => StatusCode::LOCKED,
+        ErrorKind::Unavailable => StatusCode::SERVICE_UNAVAILABLE,
         _ => StatusCode::INTERNAL_SERVER_ERROR,
     }
 }
@@ -52,6 +57,12 @@ impl IntoResponse for AppError {
         self.trace_source();
         let status_code = error_status_code(self.kind());
         let response = ErrorResp::from_error(&self);
-        (status_code, Json(response)).into_response()
+        let mut response = (status_code, Json(response)).into_response();
+        if let Some(seconds) = self.retry_after() {
+            response
+                .headers_mut()
+                .insert(header::RETRY_AFTER, HeaderValue::from(seconds));
+        }
+        response
     }
 }
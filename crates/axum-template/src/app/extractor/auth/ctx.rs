@@ -0,0 +1,152 @@
+use axum::{
+    extract::{FromRequestParts, OptionalFromRequestParts},
+    http::request::Parts,
+};
+
+use super::{JwtCtx, SessionCtx, bearer_token, session_cookie};
+use crate::{
+    app::AppState,
+    bail,
+    domain::{Services, db::Pk, model::User},
+    error::{AppError, ErrorKind},
+};
+
+/// Request extension set while [`AllowPasswordChange`] extracts its context.
+#[derive(Debug, Clone, Copy)]
+struct PasswordChangeAllowed;
+
+/// Accepts the wrapped context even while the user must change their
+/// password, which every other route refuses with `PasswordChangeRequired`.
+///
+/// Only for the routes that let them do so: changing the password, fetching
+/// the CSRF token that needs, and logging out.
+#[derive(Debug)]
+pub struct AllowPasswordChange<T>(pub T);
+
+impl<T> FromRequestParts<AppState> for AllowPasswordChange<T>
+where
+    T: FromRequestParts<AppState, Rejection = AppError>,
+{
+    type Rejection = AppError;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &AppState,
+    ) -> Result<Self, Self::Rejection> {
+        parts.extensions.insert(PasswordChangeAllowed);
+        let ctx = T::from_request_parts(parts, state).await;
+        parts.extensions.remove::<PasswordChangeAllowed>();
+        ctx.map(Self)
+    }
+}
+
+/// Refuse a user flagged to change their password, unless the route allows
+/// it through [`AllowPasswordChange`].
+pub(super) async fn check_password_change(
+    parts: &Parts,
+    state: &AppState,
+    user_id: Pk,
+) -> Result<(), AppError> {
+    if parts.extensions.get::<PasswordChangeAllowed>().is_none()
+        && state.srv().token.must_change_password(user_id).await?
+    {
+        bail!(
+            ErrorKind::PasswordChangeRequired,
+            "Change your password to continue"
+        );
+    }
+    Ok(())
+}
+
+/// The mechanism that authenticated a request.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AuthMethod {
+    Jwt,
+    Session,
+}
+
+/// Authentication context accepting either a JWT Bearer token or the
+/// session cookie.
+///
+/// The `Authorization` header is tried first; the session cookie is only
+/// consulted when no Bearer token is present. Use `Option<AuthCtx>` for
+/// routes that also allow anonymous access.
+#[derive(Debug)]
+pub struct AuthCtx {
+    pub user_id: Pk,
+    pub method: AuthMethod,
+    /// The session id when authenticated by the session cookie.
+    pub session_id: Option<String>,
+}
+
+impl AuthCtx {
+    pub fn is_jwt(&self) -> bool {
+        self.method == AuthMethod::Jwt
+    }
+
+    pub fn is_session(&self) -> bool {
+        self.method == AuthMethod::Session
+    }
+
+    pub async fn user(&self, services: &Services) -> Result<User, AppError> {
+        services.user.get_by_id(self.user_id).await
+    }
+}
+
+impl From<JwtCtx> for AuthCtx {
+    fn from(ctx: JwtCtx) -> Self {
+        Self {
+            user_id: ctx.user_id,
+            method: AuthMethod::Jwt,
+            session_id: None,
+        }
+    }
+}
+
+impl From<SessionCtx> for AuthCtx {
+    fn from(ctx: SessionCtx) -> Self {
+        Self {
+            user_id: ctx.user_id,
+            method: AuthMethod::Session,
+            session_id: Some(ctx.session_id),
+        }
+    }
+}
+
+impl FromRequestParts<AppState> for AuthCtx {
+    type Rejection = AppError;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &AppState,
+    ) -> Result<Self, Self::Rejection> {
+        if let Some(token) = bearer_token(parts) {
+            return Ok(JwtCtx::from_token(parts, state, token).await?.into());
+        }
+
+        if let Some(session_id) = session_cookie(parts, state) {
+            return Ok(SessionCtx::from_session_id(parts, state, &session_id)
+                .await?
+                .into());
+        }
+
+        Err(ErrorKind::Unauthorized.into())
+    }
+}
+
+/// Anonymous requests and requests with stale credentials extract as `None`;
+/// any other failure is still a rejection.
+impl OptionalFromRequestParts<AppState> for AuthCtx {
+    type Rejection = AppError;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &AppState,
+    ) -> Result<Option<Self>, Self::Rejection> {
+        match <Self as FromRequestParts<AppState>>::from_request_parts(parts, state).await {
+            Ok(ctx) => Ok(Some(ctx)),
+            Err(e) if *e.kind() == ErrorKind::Unauthorized => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+}
@@ -0,0 +1,90 @@
+use std::{marker::PhantomData, ops::Deref};
+
+use axum::{extract::FromRequestParts, http::request::Parts};
+
+use super::AuthCtx;
+pub use crate::domain::model::permission::{PermMarker, perm};
+use crate::{app::AppState, domain::model::Perm, error::AppError};
+
+/// OpenAPI operation extension recording the permission a route requires.
+///
+/// Set by [`perm_route!`] from the handler's [`RequirePerm`] argument.
+pub const PERM_EXTENSION: &str = "x-permission";
+
+/// Wraps a handler whose first argument is `RequirePerm<perm::X>`, adding
+/// `X` to its `#[utoipa::path]` as the [`PERM_EXTENSION`], so the
+/// documented permission is always the enforced one:
+///
+/// ```ignore
+/// perm_route! {
+///     #[utoipa::path(get, path = "/", responses((status = 200, body = UserResp)))]
+///     pub async fn get(_: RequirePerm<perm::UserRead>, ...) -> Result<Json<UserResp>, AppError> {
+///         ...
+///     }
+/// }
+/// ```
+macro_rules! perm_route {
+    (
+        $(#[doc = $doc:tt])*
+        #[utoipa::path($($path:tt)*)]
+        $vis:vis async fn $name:ident(
+            $guard:tt: RequirePerm<perm::$perm:ident>
+            $($args:tt)*
+        ) -> $ret:ty $body:block
+    ) => {
+        $(#[doc = $doc])*
+        #[utoipa::path($($path)*, extensions(
+            ("x-permission" = json!(<perm::$perm as $crate::app::extractor::PermMarker>::PERM))
+        ))]
+        $vis async fn $name($guard: RequirePerm<perm::$perm> $($args)*) -> $ret $body
+    };
+}
+pub(crate) use perm_route;
+
+/// Authenticates the caller (see [`AuthCtx`]) and rejects with
+/// `PermissionDenied` unless one of their roles grants `P::PERM`.
+///
+/// Derefs to the underlying [`AuthCtx`].
+#[derive(Debug)]
+pub struct RequirePerm<P: PermMarker> {
+    ctx: AuthCtx,
+    _perm: PhantomData<P>,
+}
+
+impl<P: PermMarker> RequirePerm<P> {
+    pub const PERM: Perm = P::PERM;
+
+    pub fn into_inner(self) -> AuthCtx {
+        self.ctx
+    }
+}
+
+impl<P: PermMarker> Deref for RequirePerm<P> {
+    type Target = AuthCtx;
+
+    fn deref(&self) -> &Self::Target {
+        &self.ctx
+    }
+}
+
+impl<P: PermMarker> FromRequestParts<AppState> for RequirePerm<P> {
+    type Rejection = AppError;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &AppState,
+    ) -> Result<Self, Self::Rejection> {
+        let ctx = <AuthCtx as FromRequestParts<AppState>>::from_request_parts(parts, state).await?;
+
+        state
+            .srv()
+            .auth
+            .require_permission(ctx.user_id, P::PERM)
+            .await?;
+
+        Ok(Self {
+            ctx,
+            _perm: PhantomData,
+        })
+    }
+}
@@ -1,27 +1,65 @@
 use axum::{extract::FromRequestParts, http::request::Parts};
+use serde::de::DeserializeOwned;
+use serde_json::{Map, Value};
 
+use super::check_password_change;
 use crate::{
     app::AppState,
     domain::{Services, db::Pk, model::User},
     error::{AppError, ErrorKind},
 };
 
-/// Authentication context extracted from the request.
+/// Extract the token from an `Authorization: Bearer <token>` header.
+pub fn bearer_token(parts: &Parts) -> Option<&str> {
+    parts
+        .headers
+        .get("Authorization")
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "))
+}
+
+/// Authentication context extracted from a JWT Bearer token.
 ///
-/// Currently supports JWT Bearer tokens; future iterations will
-/// also support session cookie auth, trying JWT first then falling
-/// back to session.
+/// Use [`AuthCtx`](super::AuthCtx) for routes that should also accept
+/// the session cookie.
 #[derive(Debug)]
 pub struct JwtCtx {
     pub user_id: Pk,
     username: String,
+    claims: Map<String, Value>,
 }
 
 impl JwtCtx {
+    pub async fn from_token(
+        parts: &Parts,
+        state: &AppState,
+        token: &str,
+    ) -> Result<Self, AppError> {
+        let claims = state.srv().token.verify_access_token(token).await?;
+        check_password_change(parts, state, claims.sub).await?;
+
+        Ok(JwtCtx {
+            user_id: claims.sub,
+            username: claims.username,
+            claims: claims.custom,
+        })
+    }
+
     pub fn username(&self) -> &str {
         &self.username
     }
 
+    /// Custom claims added by the token service's
+    /// [`ClaimsHook`](crate::domain::ClaimsHook).
+    pub fn claims(&self) -> &Map<String, Value> {
+        &self.claims
+    }
+
+    /// A custom claim, or `None` if it is absent or not a `T`.
+    pub fn claim<T: DeserializeOwned>(&self, name: &str) -> Option<T> {
+        T::deserialize(self.claims.get(name)?).ok()
+    }
+
     pub async fn user(&self, services: &Services) -> Result<User, AppError> {
         services.user.get_by_id(self.user_id).await
     }
@@ -34,18 +72,7 @@ impl FromRequestParts<AppState> for JwtCtx {
         parts: &mut Parts,
         state: &AppState,
     ) -> Result<Self, Self::Rejection> {
-        let token = parts
-            .headers
-            .get("Authorization")
-            .and_then(|v| v.to_str().ok())
-            .and_then(|v| v.strip_prefix("Bearer "))
-            .ok_or(ErrorKind::Unauthorized)?;
-
-        let claims = state.srv().token.decode_access_token(token)?;
-
-        Ok(JwtCtx {
-            user_id: claims.sub,
-            username: claims.username,
-        })
+        let token = bearer_token(parts).ok_or(ErrorKind::Unauthorized)?;
+        JwtCtx::from_token(parts, state, token).await
     }
 }
@@ -1,5 +1,9 @@
+pub mod ctx;
+pub mod guard;
 pub mod jwt;
 pub mod session;
 
+pub use ctx::*;
+pub use guard::*;
 pub use jwt::*;
 pub use session::*;
@@ -1,75 +1,144 @@
 use axum::{extract::FromRequestParts, http::request::Parts};
-use axum_extra::extract::cookie::{Cookie, CookieJar};
+use axum_extra::extract::cookie::{Cookie, CookieJar, SameSite};
+use jiff::Timestamp;
 
+use super::{bearer_token, check_password_change};
 use crate::{
-    app::AppState,
+    app::{AppState, middleware::session::SessionRefresh},
     bail,
+    config::{CookieSameSite, SessionConfig},
     domain::{Services, db::Pk, model::User},
     error::{AppError, ErrorKind},
 };
 
-pub fn set_session_cookie(jar: CookieJar, state: &AppState, session_id: &str) -> CookieJar {
-    let name = state.cfg().auth.session.cookie_name.clone();
-    let mut cookie = Cookie::new(name, session_id.to_owned());
-    cookie.set_path("/");
-    jar.add(cookie)
+/// Build the session cookie with the configured attributes; `Max-Age`
+/// runs until `expires_at`, the session's idle deadline capped by its
+/// absolute one.
+pub fn build_session_cookie(
+    cfg: &SessionConfig,
+    session_id: &str,
+    expires_at: Timestamp,
+) -> Cookie<'static> {
+    let remaining = expires_at.duration_since(Timestamp::now());
+    let max_age = (remaining.as_secs_f64().ceil() as i64).max(0);
+    let mut cookie = Cookie::build((cfg.cookie_name.clone(), session_id.to_owned()))
+        .path(cfg.cookie_path.clone())
+        .http_only(cfg.cookie_http_only)
+        .secure(cfg.cookie_secure)
+        .same_site(match cfg.cookie_same_site {
+            CookieSameSite::Strict => SameSite::Strict,
+            CookieSameSite::Lax => SameSite::Lax,
+            CookieSameSite::None => SameSite::None,
+        })
+        .max_age(time::Duration::seconds(max_age))
+        .build();
+    if let Some(domain) = &cfg.cookie_domain {
+        cookie.set_domain(domain.clone());
+    }
+    cookie
+}
+
+pub fn set_session_cookie(
+    jar: CookieJar,
+    state: &AppState,
+    session_id: &str,
+    expires_at: Timestamp,
+) -> CookieJar {
+    jar.add(build_session_cookie(
+        &state.cfg().auth.session,
+        session_id,
+        expires_at,
+    ))
 }
 
 pub fn remove_session_cookie(jar: CookieJar, state: &AppState) -> CookieJar {
-    let name = state.cfg().auth.session.cookie_name.clone();
-    let mut cookie = Cookie::new(name, "");
-    cookie.set_path("/");
-    jar.remove(cookie)
+    // Path and domain must match for the browser to drop the cookie.
+    jar.remove(build_session_cookie(
+        &state.cfg().auth.session,
+        "",
+        Timestamp::now(),
+    ))
+}
+
+/// Extract the session id from the session cookie.
+pub fn session_cookie(parts: &Parts, state: &AppState) -> Option<String> {
+    CookieJar::from_headers(&parts.headers)
+        .get(&state.cfg().auth.session.cookie_name)
+        .map(|c| c.value().to_owned())
 }
 
 /// Authentication context extracted from session cookie.
+///
+/// Requests that also carry a Bearer token are refused: they bypass the CSRF
+/// check, which must not let the cookie through.
 #[derive(Debug)]
 pub struct SessionCtx {
     pub user_id: Pk,
+    pub session_id: String,
+    /// When the session expires unless extended again.
+    pub expires_at: Timestamp,
 }
 
 impl SessionCtx {
-    pub async fn user(&self, services: &Services) -> Result<User, AppError> {
-        services.user.get_by_id(self.user_id).await
-    }
-}
-
-impl FromRequestParts<AppState> for SessionCtx {
-    type Rejection = AppError;
-
-    async fn from_request_parts(
-        parts: &mut Parts,
+    /// Validate `session_id`, extending the session when it is due.
+    ///
+    /// An extension is flagged on the request's [`SessionRefresh`] so the
+    /// cookie's `Max-Age` is renewed along with the server-side expiry.
+    pub async fn from_session_id(
+        parts: &Parts,
         state: &AppState,
-    ) -> Result<Self, Self::Rejection> {
-        let jar = CookieJar::from_request_parts(parts, state)
-            .await
-            .map_err(|_| ErrorKind::Unauthorized)?;
-
-        let config = state.cfg();
-        let session_id = jar
-            .get(&config.auth.session.cookie_name)
-            .ok_or(ErrorKind::Unauthorized)?
-            .value()
-            .to_owned();
-
+        session_id: &str,
+    ) -> Result<Self, AppError> {
         let session = state
             .srv()
             .session
-            .find(&session_id)
+            .find(session_id)
             .await?
             .ok_or(ErrorKind::Unauthorized)?;
 
-        if session.expires_at < jiff::Timestamp::now() {
-            state.srv().session.delete(&session_id).await?;
+        if state.srv().session.is_expired(&session) {
+            state.srv().session.delete(session_id).await?;
             bail!(ErrorKind::Unauthorized, "Session expired");
         }
+        check_password_change(parts, state, session.user_id).await?;
+
+        let mut ctx = SessionCtx {
+            user_id: session.user_id,
+            session_id: session_id.to_owned(),
+            expires_at: session.expires_at,
+        };
 
         if state.srv().session.should_extend(&session) {
-            state.srv().session.extend(&session_id).await?;
+            ctx.expires_at = state.srv().session.extend(session).await?;
+            if let Some(refresh) = parts.extensions.get::<SessionRefresh>() {
+                refresh.set(session_id, ctx.expires_at);
+            }
         }
 
-        Ok(SessionCtx {
-            user_id: session.user_id,
-        })
+        Ok(ctx)
+    }
+
+    pub async fn user(&self, services: &Services) -> Result<User, AppError> {
+        services.user.get_by_id(self.user_id).await
+    }
+}
+
+impl FromRequestParts<AppState> for SessionCtx {
+    type Rejection = AppError;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &AppState,
+    ) -> Result<Self, Self::Rejection> {
+        // The CSRF check is skipped for Bearer requests, so a cookie must not
+        // authenticate one.
+        if bearer_token(parts).is_some() {
+            bail!(
+                ErrorKind::Unauthorized,
+                "Session routes do not accept a Bearer token"
+            );
+        }
+        let session_id = session_cookie(parts, state).ok_or(ErrorKind::Unauthorized)?;
+        SessionCtx::from_session_id(parts, state, &session_id).await
     }
 }
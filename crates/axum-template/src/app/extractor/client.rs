@@ -0,0 +1,129 @@
+use std::{
+    convert::Infallible,
+    net::{IpAddr, SocketAddr},
+};
+
+use axum::{
+    extract::{ConnectInfo, FromRequestParts},
+    http::{HeaderMap, header, request::Parts},
+};
+
+use crate::app::state::AppState;
+
+/// Longest user agent kept; longer values are truncated.
+const MAX_USER_AGENT_LEN: usize = 512;
+
+const X_FORWARDED_FOR: &str = "x-forwarded-for";
+
+/// Describes the client making a request, for recording on sessions and
+/// throttling logins.
+///
+/// The IP is the peer address of the connection, or, when the peer is one of
+/// `server.trusted-proxies`, the address the proxies forwarded for. It is
+/// absent when the router is not served with connect info (e.g. in tests).
+#[derive(Debug, Clone, Default)]
+pub struct ClientInfo {
+    pub user_agent: Option<String>,
+    pub ip: Option<String>,
+}
+
+impl FromRequestParts<AppState> for ClientInfo {
+    type Rejection = Infallible;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &AppState,
+    ) -> Result<Self, Self::Rejection> {
+        let user_agent = parts
+            .headers
+            .get(header::USER_AGENT)
+            .and_then(|v| v.to_str().ok())
+            .map(|ua| ua.chars().take(MAX_USER_AGENT_LEN).collect());
+        let ip = parts
+            .extensions
+            .get::<ConnectInfo<SocketAddr>>()
+            .map(|ConnectInfo(addr)| {
+                client_ip(
+                    addr.ip(),
+                    &parts.headers,
+                    &state.cfg().server.trusted_proxies,
+                )
+                .to_string()
+            });
+        Ok(Self { user_agent, ip })
+    }
+}
+
+/// The client behind `peer`. `X-Forwarded-For` is read right to left, each
+/// hop vouching for the one before it, so the result is the nearest address
+/// not in `trusted`; anything further left could be forged by the client.
+fn client_ip(peer: IpAddr, headers: &HeaderMap, trusted: &[IpAddr]) -> IpAddr {
+    let mut client = peer;
+    if !trusted.contains(&client) {
+        return client;
+    }
+    let forwarded: Vec<&str> = headers
+        .get_all(X_FORWARDED_FOR)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .collect();
+    for hop in forwarded.iter().rev() {
+        let Ok(ip) = hop.trim().parse::<IpAddr>() else {
+            break;
+        };
+        client = ip;
+        if !trusted.contains(&client) {
+            break;
+        }
+    }
+    client
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ip(s: &str) -> IpAddr {
+        s.parse().unwrap()
+    }
+
+    fn forwarded_for(values: &[&str]) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        for value in values {
+            headers.append(X_FORWARDED_FOR, value.parse().unwrap());
+        }
+        headers
+    }
+
+    #[test]
+    fn test_untrusted_peer_is_the_client() {
+        let headers = forwarded_for(&["203.0.113.7"]);
+        assert_eq!(
+            client_ip(ip("198.51.100.1"), &headers, &[]),
+            ip("198.51.100.1")
+        );
+    }
+
+    #[test]
+    fn test_trusted_proxy_chain() {
+        let trusted = [ip("10.0.0.1"), ip("10.0.0.2")];
+        // The client prepended a forged hop; the proxies appended the rest.
+        let headers = forwarded_for(&["1.1.1.1, 203.0.113.7", "10.0.0.2"]);
+        assert_eq!(
+            client_ip(ip("10.0.0.1"), &headers, &trusted),
+            ip("203.0.113.7")
+        );
+
+        // Nothing forwarded, or garbage: the last proxy is all we know.
+        assert_eq!(
+            client_ip(ip("10.0.0.1"), &HeaderMap::new(), &trusted),
+            ip("10.0.0.1")
+        );
+        let headers = forwarded_for(&["unknown"]);
+        assert_eq!(
+            client_ip(ip("10.0.0.1"), &headers, &trusted),
+            ip("10.0.0.1")
+        );
+    }
+}
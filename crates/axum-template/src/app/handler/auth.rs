@@ -1,4 +1,9 @@
-use axum::{Json, extract::State, response::IntoResponse};
+use axum::{
+    Json,
+    extract::State,
+    http::StatusCode,
+    response::{IntoResponse, Response},
+};
 use axum_extra::extract::cookie::CookieJar;
 use utoipa_axum::{router::OpenApiRouter, routes};
 
@@ -7,30 +12,79 @@ use crate::{
         AppState,
         dto::{request::*, response::*},
         error::ErrorResp,
-        extractor::{AppJson, SessionCtx, set_session_cookie},
+        extractor::{
+            AllowPasswordChange, AppJson, AppPath, AuthCtx, AuthMethod, ClientInfo, SessionCtx,
+            remove_session_cookie, set_session_cookie,
+        },
+        middleware::csrf,
     },
+    domain::{db::Pk, service::auth::AuthUser},
     error::{AppError, ErrorKind},
     ext::{EndpointRouter, EndpointRouterT, OpenApiRouterExt},
 };
 
+/// Log in with a password. Users with a second factor get an MFA challenge
+/// (`202`) to complete at `/auth/login/mfa` instead of a session.
 #[utoipa::path(post, path="/login", request_body = LoginReq, responses(
     (status = 200, body = AuthStateResp),
+    (status = 202, body = MfaChallengeResp),
     (status = 400, body = ErrorResp),
+    (status = 423, body = ErrorResp, description = "Account locked; see `Retry-After`"),
+    (status = 429, body = ErrorResp, description = "Too many failed logins; see `Retry-After`"),
+    (status = 503, body = ErrorResp, description = "Password hashing overloaded; see `Retry-After`"),
 ))]
 pub async fn login(
     State(state): State<AppState>,
     jar: CookieJar,
+    client: ClientInfo,
     AppJson(payload): AppJson<LoginReq>,
-) -> Result<impl IntoResponse, AppError> {
+) -> Result<Response, AppError> {
     let auth_user = state
         .srv()
         .auth
-        .authenticate(&payload.username, &payload.password)
+        .authenticate(&payload.username, &payload.password, client.ip.as_deref())
         .await?
         .ok_or(ErrorKind::InvalidCredentials)?;
 
-    let session_id = state.srv().session.create(auth_user.user.id).await?;
-    let jar = set_session_cookie(jar, &state, &session_id);
+    if let Some(pending) = state.srv().mfa.challenge(&auth_user.user).await? {
+        return Ok((StatusCode::ACCEPTED, Json(MfaChallengeResp::from(pending))).into_response());
+    }
+    start_session(&state, jar, client, auth_user).await
+}
+
+/// Complete a challenged login with a TOTP or recovery code.
+#[utoipa::path(post, path="/login/mfa", request_body = MfaLoginReq, responses(
+    (status = 200, body = AuthStateResp),
+    (status = 400, body = ErrorResp),
+    (status = 401, body = ErrorResp),
+))]
+pub async fn login_mfa(
+    State(state): State<AppState>,
+    jar: CookieJar,
+    client: ClientInfo,
+    AppJson(payload): AppJson<MfaLoginReq>,
+) -> Result<Response, AppError> {
+    let user_id = state
+        .srv()
+        .mfa
+        .complete_challenge(&payload.challenge_token, &payload.code)
+        .await?;
+    let auth_user = state.srv().auth.get_auth_user(user_id).await?;
+    start_session(&state, jar, client, auth_user).await
+}
+
+async fn start_session(
+    state: &AppState,
+    jar: CookieJar,
+    client: ClientInfo,
+    auth_user: AuthUser,
+) -> Result<Response, AppError> {
+    let (session_id, expires_at) = state
+        .srv()
+        .session
+        .create(auth_user.user.id, client.user_agent, client.ip)
+        .await?;
+    let jar = set_session_cookie(jar, state, &session_id, expires_at);
 
     Ok((
         jar,
@@ -38,7 +92,8 @@ pub async fn login(
             user: UserResp::from(auth_user.user),
             permissions: auth_user.permissions,
         }),
-    ))
+    )
+        .into_response())
 }
 
 #[utoipa::path(post, path="/logout", responses(
@@ -47,11 +102,23 @@ pub async fn login(
 ))]
 pub async fn logout(
     State(state): State<AppState>,
-    ctx: SessionCtx,
+    jar: CookieJar,
+    AllowPasswordChange(ctx): AllowPasswordChange<AuthCtx>,
 ) -> Result<impl IntoResponse, AppError> {
-    state.srv().session.delete_by_user_id(ctx.user_id).await?;
+    let jar = match ctx.method {
+        AuthMethod::Session => {
+            if let Some(session_id) = &ctx.session_id {
+                state.srv().session.delete(session_id).await?;
+            }
+            remove_session_cookie(jar, &state)
+        }
+        AuthMethod::Jwt => {
+            state.srv().token.revoke_all(ctx.user_id).await?;
+            jar
+        }
+    };
 
-    Ok(Json(serde_json::json!({"message": "Logged out"})))
+    Ok((jar, Json(serde_json::json!({"message": "Logged out"}))))
 }
 
 #[utoipa::path(get, path="/me", responses(
@@ -60,7 +127,7 @@ pub async fn logout(
 ))]
 pub async fn me(
     State(state): State<AppState>,
-    ctx: SessionCtx,
+    ctx: AuthCtx,
 ) -> Result<impl IntoResponse, AppError> {
     let auth_user = state.srv().auth.get_auth_user(ctx.user_id).await?;
 
@@ -70,11 +137,106 @@ pub async fn me(
     }))
 }
 
+#[utoipa::path(get, path="/csrf", responses(
+    (status = 200, body = CsrfResp),
+    (status = 401, body = ErrorResp),
+))]
+pub async fn csrf_token(
+    State(state): State<AppState>,
+    jar: CookieJar,
+    AllowPasswordChange(ctx): AllowPasswordChange<SessionCtx>,
+) -> Result<impl IntoResponse, AppError> {
+    let (token, cookie) = csrf::issue_token(&state, &ctx.session_id, ctx.expires_at).await?;
+    let jar = match cookie {
+        Some(cookie) => jar.add(cookie),
+        None => jar,
+    };
+
+    Ok((
+        jar,
+        Json(CsrfResp {
+            token,
+            header_name: state.cfg().auth.csrf.header_name.clone(),
+        }),
+    ))
+}
+
+#[utoipa::path(get, path="/sessions", responses(
+    (status = 200, body = Vec<SessionResp>),
+    (status = 401, body = ErrorResp),
+))]
+pub async fn list_sessions(
+    State(state): State<AppState>,
+    ctx: AuthCtx,
+) -> Result<impl IntoResponse, AppError> {
+    let sessions = state.srv().session.list_by_user_id(ctx.user_id).await?;
+    let response: Vec<_> = sessions
+        .into_iter()
+        .map(|s| SessionResp::new(s, ctx.session_id.as_deref()))
+        .collect();
+    Ok(Json(response))
+}
+
+/// Log out everywhere else: revoke every session except the current one,
+/// every refresh token and every access token.
+#[utoipa::path(delete, path="/sessions", responses(
+    (status = 200, body = MessageResp),
+    (status = 401, body = ErrorResp),
+))]
+pub async fn revoke_other_sessions(
+    State(state): State<AppState>,
+    ctx: AuthCtx,
+) -> Result<impl IntoResponse, AppError> {
+    state
+        .srv()
+        .session
+        .delete_others(ctx.user_id, ctx.session_id.as_deref())
+        .await?;
+    Ok(Json(MessageResp {
+        message: "Other sessions revoked".to_string(),
+    }))
+}
+
+#[utoipa::path(delete, path="/sessions/{id}", params(
+    ("id" = Pk, Path)
+), responses(
+    (status = 200, body = MessageResp),
+    (status = 401, body = ErrorResp),
+    (status = 404, body = ErrorResp),
+))]
+pub async fn revoke_session(
+    State(state): State<AppState>,
+    jar: CookieJar,
+    ctx: AuthCtx,
+    AppPath(PkPath { id }): AppPath<PkPath>,
+) -> Result<impl IntoResponse, AppError> {
+    let session = state.srv().session.delete_for_user(ctx.user_id, id).await?;
+    let jar = if ctx
+        .session_id
+        .as_deref()
+        .is_some_and(|id| session.matches(id))
+    {
+        remove_session_cookie(jar, &state)
+    } else {
+        jar
+    };
+    Ok((
+        jar,
+        Json(MessageResp {
+            message: "Session revoked".to_string(),
+        }),
+    ))
+}
+
 pub fn router() -> EndpointRouter<AppState> {
     OpenApiRouter::new()
         .routes(routes![login])
+        .routes(routes![login_mfa])
         .routes(routes![logout])
         .routes(routes![me])
+        .routes(routes![csrf_token])
+        .routes(routes![list_sessions, revoke_other_sessions])
+        .routes(routes![revoke_session])
         .with_tags(["auth"])
         .endpoint("/auth")
 }
@@ -0,0 +1,130 @@
+use std::{
+    sync::atomic::Ordering,
+    time::{Duration, Instant},
+};
+
+use axum::{Json, extract::State, http::StatusCode, response::IntoResponse};
+use serde_json::{Value, json};
+use utoipa_axum::{router::OpenApiRouter, routes};
+
+use crate::{
+    app::{AppState, dto::response::*},
+    domain::db,
+    error::Result,
+    ext::{EndpointRouter, EndpointRouterT, OpenApiRouterExt},
+    infra::worker::WorkerState,
+};
+
+/// Upper bound for a single readiness check, so a hung dependency fails the
+/// probe instead of stalling it.
+const CHECK_TIMEOUT: Duration = Duration::from_secs(3);
+
+#[utoipa::path(get, path="/live", responses(
+    (status = 200, body = HealthResp),
+))]
+pub async fn live() -> impl IntoResponse {
+    Json(HealthResp {
+        status: HealthStatus::Up,
+        checks: Vec::new(),
+    })
+}
+
+#[utoipa::path(get, path="/ready", responses(
+    (status = 200, body = HealthResp),
+    (status = 503, body = HealthResp),
+))]
+pub async fn ready(State(state): State<AppState>) -> impl IntoResponse {
+    let db = state.db().clone();
+    let checks = vec![
+        check("database", async move {
+            db::ping(&db).await?;
+            Ok((HealthStatus::Up, None))
+        })
+        .await,
+        check("migrations", check_migrations(state.clone())).await,
+        check("workers", check_workers(state.clone())).await,
+    ];
+
+    let status = if checks.iter().all(|c| c.status == HealthStatus::Up) {
+        HealthStatus::Up
+    } else {
+        HealthStatus::Down
+    };
+    let code = match status {
+        HealthStatus::Up => StatusCode::OK,
+        HealthStatus::Down => StatusCode::SERVICE_UNAVAILABLE,
+    };
+
+    (code, Json(HealthResp { status, checks }))
+}
+
+async fn check_migrations(state: AppState) -> Result<(HealthStatus, Option<Value>)> {
+    // Migrations never get un-applied, so stop asking once they all are.
+    if state.migrations_applied.load(Ordering::Relaxed) {
+        return Ok((HealthStatus::Up, None));
+    }
+
+    let pending = db::pending_migrations(state.db()).await?;
+    if pending.is_empty() {
+        state.migrations_applied.store(true, Ordering::Relaxed);
+        Ok((HealthStatus::Up, None))
+    } else {
+        Ok((HealthStatus::Down, Some(json!({ "pending": pending }))))
+    }
+}
+
+async fn check_workers(state: AppState) -> Result<(HealthStatus, Option<Value>)> {
+    let workers = state.workers().states();
+    let status = if workers
+        .values()
+        .any(|w| matches!(w, WorkerState::Failed(_)))
+    {
+        HealthStatus::Down
+    } else {
+        HealthStatus::Up
+    };
+    let detail = (!workers.is_empty()).then(|| json!(workers));
+
+    Ok((status, detail))
+}
+
+/// Run `fut` on its own task so a panicking driver also reports as down.
+async fn check(
+    name: &str,
+    fut: impl Future<Output = Result<(HealthStatus, Option<Value>)>> + Send + 'static,
+) -> HealthCheckResp {
+    let start = Instant::now();
+    let (status, detail) = match tokio::time::timeout(CHECK_TIMEOUT, tokio::spawn(fut)).await {
+        Ok(Ok(Ok(res))) => res,
+        Ok(Ok(Err(err))) => {
+            tracing::warn!("Health check {} failed: {}", name, err);
+            (HealthStatus::Down, Some(json!({ "error": err.message() })))
+        }
+        Ok(Err(err)) => {
+            tracing::error!("Health check {} panicked: {}", name, err);
+            (
+                HealthStatus::Down,
+                Some(json!({ "error": "check panicked" })),
+            )
+        }
+        Err(_) => {
+            tracing::warn!("Health check {} timed out", name);
+            (HealthStatus::Down, Some(json!({ "error": "timed out" })))
+        }
+    };
+
+    HealthCheckResp {
+        name: name.to_owned(),
+        status,
+        latency_ms: start.elapsed().as_secs_f64() * 1000.0,
+        detail,
+    }
+}
+
+pub fn router() -> EndpointRouter<AppState> {
+    OpenApiRouter::new()
+        .routes(routes![live])
+        .routes(routes![ready])
+        .with_tags(["health"])
+        .endpoint("/health")
+}
@@ -1,4 +1,9 @@
-use axum::{Json, extract::State, response::IntoResponse};
+use axum::{
+    Json,
+    extract::State,
+    http::StatusCode,
+    response::{IntoResponse, Response},
+};
 use utoipa_axum::{router::OpenApiRouter, routes};
 
 use crate::{
@@ -6,28 +11,66 @@ use crate::{
         AppState,
         dto::{request::*, response::*},
         error::ErrorResp,
-        extractor::{AppJson, JwtCtx},
+        extractor::{AllowPasswordChange, AppJson, ClientInfo, JwtCtx},
     },
+    domain::service::auth::AuthUser,
     error::{AppError, ErrorKind},
     ext::{EndpointRouter, EndpointRouterT, OpenApiRouterExt},
 };
 
+/// Log in with a password. Users with a second factor get an MFA challenge
+/// (`202`) to complete at `/auth/jwt/login/mfa` instead of tokens.
 #[utoipa::path(post, path="/jwt/login", request_body = LoginReq, responses(
     (status = 200, body = LoginResp),
+    (status = 202, body = MfaChallengeResp),
     (status = 400, body = ErrorResp),
+    (status = 423, body = ErrorResp, description = "Account locked; see `Retry-After`"),
+    (status = 429, body = ErrorResp, description = "Too many failed logins; see `Retry-After`"),
+    (status = 503, body = ErrorResp, description = "Password hashing overloaded; see `Retry-After`"),
 ))]
 pub async fn login(
     State(state): State<AppState>,
+    client: ClientInfo,
     AppJson(payload): AppJson<LoginReq>,
-) -> Result<impl IntoResponse, AppError> {
+) -> Result<Response, AppError> {
     let auth_user = state
         .srv()
         .auth
-        .authenticate(&payload.username, &payload.password)
+        .authenticate(&payload.username, &payload.password, client.ip.as_deref())
         .await?
         .ok_or(ErrorKind::InvalidCredentials)?;
 
-    let access_token = state.srv().token.encode_access_token(&auth_user.user)?;
+    if let Some(pending) = state.srv().mfa.challenge(&auth_user.user).await? {
+        return Ok((StatusCode::ACCEPTED, Json(MfaChallengeResp::from(pending))).into_response());
+    }
+    issue_tokens(&state, auth_user).await
+}
+
+/// Complete a challenged login with a TOTP or recovery code.
+#[utoipa::path(post, path="/jwt/login/mfa", request_body = MfaLoginReq, responses(
+    (status = 200, body = LoginResp),
+    (status = 400, body = ErrorResp),
+    (status = 401, body = ErrorResp),
+))]
+pub async fn login_mfa(
+    State(state): State<AppState>,
+    AppJson(payload): AppJson<MfaLoginReq>,
+) -> Result<Response, AppError> {
+    let user_id = state
+        .srv()
+        .mfa
+        .complete_challenge(&payload.challenge_token, &payload.code)
+        .await?;
+    let auth_user = state.srv().auth.get_auth_user(user_id).await?;
+    issue_tokens(&state, auth_user).await
+}
+
+async fn issue_tokens(state: &AppState, auth_user: AuthUser) -> Result<Response, AppError> {
+    let access_token = state
+        .srv()
+        .token
+        .encode_access_token(&auth_user.user)
+        .await?;
     let refresh_token = state
         .srv()
         .token
@@ -43,7 +86,7 @@ pub async fn login(
         },
     };
 
-    Ok(Json(response))
+    Ok(Json(response).into_response())
 }
 
 #[utoipa::path(post, path="/jwt/refresh", request_body = RefreshReq, responses(
@@ -66,19 +109,16 @@ pub async fn refresh(
     }))
 }
 
+/// Log out everywhere: revoke every refresh token and access token.
 #[utoipa::path(post, path="/jwt/logout", responses(
     (status = 200),
     (status = 401, body = ErrorResp),
 ))]
 pub async fn logout(
     State(state): State<AppState>,
-    ctx: JwtCtx,
+    AllowPasswordChange(ctx): AllowPasswordChange<JwtCtx>,
 ) -> Result<impl IntoResponse, AppError> {
-    state
-        .srv()
-        .token
-        .delete_all_refresh_tokens(ctx.user_id)
-        .await?;
+    state.srv().token.revoke_all(ctx.user_id).await?;
 
     Ok(Json(serde_json::json!({"message": "Logged out"})))
 }
@@ -105,12 +145,14 @@ pub async fn echo(ctx: JwtCtx) -> Result<impl IntoResponse, AppError> {
         "message": "Authenticated",
         "user_id": ctx.user_id,
         "username": ctx.username(),
+        "claims": ctx.claims(),
     })))
 }
 
 pub fn router() -> EndpointRouter<AppState> {
     OpenApiRouter::new()
         .routes(routes![login])
+        .routes(routes![login_mfa])
         .routes(routes![refresh])
         .routes(routes![logout])
         .routes(routes![me])
@@ -0,0 +1,121 @@
+use axum::{Json, extract::State, response::IntoResponse};
+use utoipa_axum::{router::OpenApiRouter, routes};
+
+use crate::{
+    app::{
+        AppState,
+        dto::{request::*, response::*},
+        error::ErrorResp,
+        extractor::{AppJson, AuthCtx},
+    },
+    error::AppError,
+    ext::{EndpointRouter, EndpointRouterT, OpenApiRouterExt},
+};
+
+#[utoipa::path(get, path="/mfa", responses(
+    (status = 200, body = MfaStatusResp),
+    (status = 401, body = ErrorResp),
+))]
+pub async fn status(
+    State(state): State<AppState>,
+    ctx: AuthCtx,
+) -> Result<impl IntoResponse, AppError> {
+    let status = state.srv().mfa.status(ctx.user_id).await?;
+    Ok(Json(MfaStatusResp::from(status)))
+}
+
+/// Start TOTP enrollment. Nothing changes at login until the first code is
+/// confirmed; starting again replaces the secret and recovery codes.
+#[utoipa::path(post, path="/mfa/totp", responses(
+    (status = 200, body = TotpEnrollmentResp),
+    (status = 401, body = ErrorResp),
+    (status = 409, body = ErrorResp),
+))]
+pub async fn enroll(
+    State(state): State<AppState>,
+    ctx: AuthCtx,
+) -> Result<impl IntoResponse, AppError> {
+    let enrollment = state.srv().mfa.enroll(ctx.user_id).await?;
+    Ok(Json(TotpEnrollmentResp::from(enrollment)))
+}
+
+#[utoipa::path(post, path="/mfa/totp/confirm", request_body = MfaCodeReq, responses(
+    (status = 200, body = MessageResp),
+    (status = 400, body = ErrorResp),
+    (status = 401, body = ErrorResp),
+))]
+pub async fn confirm(
+    State(state): State<AppState>,
+    ctx: AuthCtx,
+    AppJson(payload): AppJson<MfaCodeReq>,
+) -> Result<impl IntoResponse, AppError> {
+    state.srv().mfa.confirm(ctx.user_id, &payload.code).await?;
+    Ok(Json(MessageResp {
+        message: "Two-factor authentication enabled".to_string(),
+    }))
+}
+
+#[utoipa::path(delete, path="/mfa/totp", request_body = MfaCodeReq, responses(
+    (status = 200, body = MessageResp),
+    (status = 400, body = ErrorResp),
+    (status = 401, body = ErrorResp),
+    (status = 403, body = ErrorResp),
+))]
+pub async fn disable(
+    State(state): State<AppState>,
+    ctx: AuthCtx,
+    AppJson(payload): AppJson<MfaCodeReq>,
+) -> Result<impl IntoResponse, AppError> {
+    state.srv().mfa.disable(ctx.user_id, &payload.code).await?;
+    Ok(Json(MessageResp {
+        message: "Two-factor authentication disabled".to_string(),
+    }))
+}
+
+#[utoipa::path(post, path="/mfa/recovery-codes", request_body = MfaCodeReq, responses(
+    (status = 200, body = RecoveryCodesResp),
+    (status = 400, body = ErrorResp),
+    (status = 401, body = ErrorResp),
+))]
+pub async fn regenerate_recovery_codes(
+    State(state): State<AppState>,
+    ctx: AuthCtx,
+    AppJson(payload): AppJson<MfaCodeReq>,
+) -> Result<impl IntoResponse, AppError> {
+    let recovery_codes = state
+        .srv()
+        .mfa
+        .regenerate_recovery_codes(ctx.user_id, &payload.code)
+        .await?;
+    Ok(Json(RecoveryCodesResp { recovery_codes }))
+}
+
+/// Enroll during a login whose challenge has `enrollment_required`. The
+/// first code from the new authenticator then completes the login.
+#[utoipa::path(post, path="/mfa/challenge/totp", request_body = MfaChallengeReq, responses(
+    (status = 200, body = TotpEnrollmentResp),
+    (status = 401, body = ErrorResp),
+    (status = 409, body = ErrorResp),
+))]
+pub async fn enroll_for_challenge(
+    State(state): State<AppState>,
+    AppJson(payload): AppJson<MfaChallengeReq>,
+) -> Result<impl IntoResponse, AppError> {
+    let enrollment = state
+        .srv()
+        .mfa
+        .enroll_for_challenge(&payload.challenge_token)
+        .await?;
+    Ok(Json(TotpEnrollmentResp::from(enrollment)))
+}
+
+pub fn router() -> EndpointRouter<AppState> {
+    OpenApiRouter::new()
+        .routes(routes![status])
+        .routes(routes![enroll, disable])
+        .routes(routes![confirm])
+        .routes(routes![regenerate_recovery_codes])
+        .routes(routes![enroll_for_challenge])
+        .with_tags(["mfa"])
+        .endpoint("/auth")
+}
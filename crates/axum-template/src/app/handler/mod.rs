@@ -1,5 +1,9 @@
 pub mod auth;
 pub mod chore;
+pub mod health;
 pub mod jwt_demo;
+pub mod mfa;
+pub mod role;
 pub mod user;
+pub mod well_known;
 pub mod ws;
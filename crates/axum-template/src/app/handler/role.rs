@@ -0,0 +1,207 @@
+use axum::{Json, extract::State, response::IntoResponse};
+use utoipa_axum::{router::OpenApiRouter, routes};
+
+use crate::{
+    app::{
+        AppState,
+        dto::{request::*, response::*},
+        error::ErrorResp,
+        extractor::{AppJson, AppPath, AppQuery, RequirePerm, perm, perm_route},
+    },
+    domain::db::Pk,
+    error::AppError,
+    ext::{EndpointRouter, EndpointRouterT, OpenApiRouterExt},
+};
+
+perm_route! {
+    #[utoipa::path(get, path="/", params(
+        ("page" = Option<u64>, Query, description = "Page number"),
+        ("per_page" = Option<u64>, Query, description = "Items per page")
+    ), responses(
+        (status = 200, body = RoleListResp),
+        (status = 400, body = ErrorResp),
+    ))]
+    pub async fn list(
+        _: RequirePerm<perm::RoleRead>,
+        State(state): State<AppState>,
+        AppQuery(pagination): AppQuery<PaginationReq>,
+    ) -> Result<impl IntoResponse, AppError> {
+        let page = pagination.page;
+        let per_page = pagination.per_page;
+
+        let roles = state.srv().role.list(page, per_page).await?;
+        let total = state.srv().role.count().await?;
+
+        let response = RoleListResp {
+            roles: roles.into_iter().map(RoleResp::from).collect(),
+            total,
+            page,
+            per_page,
+        };
+
+        Ok(Json(response))
+    }
+}
+
+perm_route! {
+    #[utoipa::path(post, path="/", request_body = CreateRoleReq, responses(
+        (status = 200, body = RoleResp),
+        (status = 400, body = ErrorResp),
+    ))]
+    pub async fn create(
+        ctx: RequirePerm<perm::RoleWrite>,
+        State(state): State<AppState>,
+        AppJson(payload): AppJson<CreateRoleReq>,
+    ) -> Result<impl IntoResponse, AppError> {
+        state
+            .srv()
+            .auth
+            .require_permissions(ctx.user_id, &payload.permissions)
+            .await?;
+        let role = state
+            .srv()
+            .role
+            .create(payload.name, payload.description, &payload.permissions)
+            .await?;
+        Ok(Json(RoleResp::from(role)))
+    }
+}
+
+perm_route! {
+    #[utoipa::path(get, path="/{id}", params(
+        ("id" = Pk, Path)
+    ), responses(
+        (status = 200, body = RoleResp),
+        (status = 400, body = ErrorResp),
+    ))]
+    pub async fn get(
+        _: RequirePerm<perm::RoleRead>,
+        State(state): State<AppState>,
+        AppPath(PkPath { id }): AppPath<PkPath>,
+    ) -> Result<impl IntoResponse, AppError> {
+        let role = state.srv().role.get_by_id(id).await?;
+        Ok(Json(RoleResp::from(role)))
+    }
+}
+
+perm_route! {
+    #[utoipa::path(put, path="/{id}", params(
+        ("id" = Pk, Path)
+    ), request_body = UpdateRoleReq, responses(
+        (status = 200, body = RoleResp),
+        (status = 400, body = ErrorResp),
+    ))]
+    pub async fn update(
+        _: RequirePerm<perm::RoleWrite>,
+        State(state): State<AppState>,
+        AppPath(PkPath { id }): AppPath<PkPath>,
+        AppJson(payload): AppJson<UpdateRoleReq>,
+    ) -> Result<impl IntoResponse, AppError> {
+        let role = state
+            .srv()
+            .role
+            .update(id, payload.name, payload.description)
+            .await?;
+        Ok(Json(RoleResp::from(role)))
+    }
+}
+
+perm_route! {
+    #[utoipa::path(put, path="/{id}/permissions", params(
+        ("id" = Pk, Path)
+    ), request_body = SetRolePermsReq, responses(
+        (status = 200, body = RoleResp),
+        (status = 400, body = ErrorResp),
+    ))]
+    pub async fn set_permissions(
+        ctx: RequirePerm<perm::RoleWrite>,
+        State(state): State<AppState>,
+        AppPath(PkPath { id }): AppPath<PkPath>,
+        AppJson(payload): AppJson<SetRolePermsReq>,
+    ) -> Result<impl IntoResponse, AppError> {
+        // Neither widen a role beyond, nor strip one above, the caller's own.
+        let current = state.srv().role.get_by_id(id).await?.parse_perms();
+        for perms in [&current, &payload.permissions] {
+            state
+                .srv()
+                .auth
+                .require_permissions(ctx.user_id, perms)
+                .await?;
+        }
+        let role = state
+            .srv()
+            .role
+            .update_permissions(id, &payload.permissions)
+            .await?;
+        Ok(Json(RoleResp::from(role)))
+    }
+}
+
+perm_route! {
+    #[utoipa::path(put, path="/{id}/mfa", params(
+        ("id" = Pk, Path)
+    ), request_body = SetRoleMfaReq, responses(
+        (status = 200, body = RoleResp),
+        (status = 400, body = ErrorResp),
+    ))]
+    pub async fn set_mfa(
+        ctx: RequirePerm<perm::RoleWrite>,
+        State(state): State<AppState>,
+        AppPath(PkPath { id }): AppPath<PkPath>,
+        AppJson(payload): AppJson<SetRoleMfaReq>,
+    ) -> Result<impl IntoResponse, AppError> {
+        let role = state.srv().role.get_by_id(id).await?;
+        state
+            .srv()
+            .auth
+            .require_permissions(ctx.user_id, &role.parse_perms())
+            .await?;
+        let role = state
+            .srv()
+            .role
+            .update_require_mfa(id, payload.required)
+            .await?;
+        Ok(Json(RoleResp::from(role)))
+    }
+}
+
+perm_route! {
+    #[utoipa::path(delete, path="/{id}", params(
+        ("id" = Pk, Path)
+    ), responses(
+        (status = 200, body = MessageResp),
+        (status = 400, body = ErrorResp),
+    ))]
+    pub async fn delete(
+        ctx: RequirePerm<perm::RoleDelete>,
+        State(state): State<AppState>,
+        AppPath(PkPath { id }): AppPath<PkPath>,
+    ) -> Result<impl IntoResponse, AppError> {
+        // Deleting a role strips its members, so it is held to the same
+        // bound as narrowing its permissions.
+        let role = state.srv().role.get_by_id(id).await?;
+        state
+            .srv()
+            .auth
+            .require_permissions(ctx.user_id, &role.parse_perms())
+            .await?;
+        state.srv().role.delete(id).await?;
+        let response = MessageResp {
+            message: "Role deleted successfully".to_string(),
+        };
+        Ok(Json(response))
+    }
+}
+
+pub fn router() -> EndpointRouter<AppState> {
+    OpenApiRouter::new()
+        .routes(routes![list])
+        .routes(routes![create])
+        .routes(routes![get])
+        .routes(routes![update])
+        .routes(routes![delete])
+        .routes(routes![set_permissions])
+        .routes(routes![set_mfa])
+        .with_tags(["role"])
+        .endpoint("/roles")
+}
@@ -6,121 +6,107 @@ use crate::{
         AppState,
         dto::{request::*, response::*},
         error::ErrorResp,
-        extractor::{AppJson, AppPath, AppQuery, SessionCtx},
+        extractor::{
+            AllowPasswordChange, AppJson, AppPath, AppQuery, AuthCtx, RequirePerm, perm, perm_route,
+        },
     },
     bail,
-    domain::{db::Pk, model::Perm},
+    domain::db::Pk,
     error::{AppError, ErrorKind},
     ext::{EndpointRouter, EndpointRouterT, OpenApiRouterExt},
 };
 
-#[utoipa::path(get, path="/", params(
-    ("page" = Option<u64>, Query, description = "Page number"),
-    ("per_page" = Option<u64>, Query, description = "Items per page")
-), responses(
-    (status = 200, body = UserListResp),
-    (status = 400, body = ErrorResp),
-))]
-pub async fn list(
-    ctx: SessionCtx,
-    State(state): State<AppState>,
-    AppQuery(pagination): AppQuery<PaginationReq>,
-) -> Result<impl IntoResponse, AppError> {
-    state
-        .srv()
-        .auth
-        .require_permission(ctx.user_id, Perm::UserRead)
-        .await?;
-
-    let page = pagination.page;
-    let per_page = pagination.per_page;
+perm_route! {
+    #[utoipa::path(get, path="/", params(
+        ("page" = Option<u64>, Query, description = "Page number"),
+        ("per_page" = Option<u64>, Query, description = "Items per page")
+    ), responses(
+        (status = 200, body = UserListResp),
+        (status = 400, body = ErrorResp),
+    ))]
+    pub async fn list(
+        _: RequirePerm<perm::UserRead>,
+        State(state): State<AppState>,
+        AppQuery(pagination): AppQuery<PaginationReq>,
+    ) -> Result<impl IntoResponse, AppError> {
+        let page = pagination.page;
+        let per_page = pagination.per_page;
 
-    let users = state.srv().user.list(page, per_page).await?;
-    let total = state.srv().user.count().await?;
+        let users = state.srv().user.list(page, per_page).await?;
+        let total = state.srv().user.count().await?;
 
-    let user_responses = users.into_iter().map(UserResp::from).collect();
+        let user_responses = users.into_iter().map(UserResp::from).collect();
 
-    let response = UserListResp {
-        users: user_responses,
-        total,
-        page,
-        per_page,
-    };
+        let response = UserListResp {
+            users: user_responses,
+            total,
+            page,
+            per_page,
+        };
 
-    Ok(Json(response))
+        Ok(Json(response))
+    }
 }
 
-#[utoipa::path(post, path="/", request_body = CreateUserReq, responses(
-    (status = 200, body = UserResp),
-    (status = 400, body = ErrorResp),
-))]
-pub async fn create(
-    ctx: SessionCtx,
-    State(state): State<AppState>,
-    AppJson(payload): AppJson<CreateUserReq>,
-) -> Result<impl IntoResponse, AppError> {
-    state
-        .srv()
-        .auth
-        .require_permission(ctx.user_id, Perm::UserWrite)
-        .await?;
-
-    let user = state
-        .srv()
-        .user
-        .create(payload.username, payload.password)
-        .await?;
-    let response = UserResp::from(user);
-    Ok(Json(response))
+perm_route! {
+    #[utoipa::path(post, path="/", request_body = CreateUserReq, responses(
+        (status = 200, body = UserResp),
+        (status = 400, body = ErrorResp),
+    ))]
+    pub async fn create(
+        _: RequirePerm<perm::UserWrite>,
+        State(state): State<AppState>,
+        AppJson(payload): AppJson<CreateUserReq>,
+    ) -> Result<impl IntoResponse, AppError> {
+        let user = state
+            .srv()
+            .user
+            .create(payload.username, payload.password)
+            .await?;
+        let response = UserResp::from(user);
+        Ok(Json(response))
+    }
 }
 
-#[utoipa::path(get, path="/{id}", params(
-    ("id" = Pk, Path)
-), responses(
-    (status = 200, body = UserResp),
-    (status = 400, body = ErrorResp),
-))]
-pub async fn get(
-    ctx: SessionCtx,
-    State(state): State<AppState>,
-    AppPath(PkPath { id }): AppPath<PkPath>,
-) -> Result<impl IntoResponse, AppError> {
-    state
-        .srv()
-        .auth
-        .require_permission(ctx.user_id, Perm::UserRead)
-        .await?;
-
-    let user = state.srv().user.get_by_id(id).await?;
-    let response = UserResp::from(user);
-    Ok(Json(response))
+perm_route! {
+    #[utoipa::path(get, path="/{id}", params(
+        ("id" = Pk, Path)
+    ), responses(
+        (status = 200, body = UserResp),
+        (status = 400, body = ErrorResp),
+    ))]
+    pub async fn get(
+        _: RequirePerm<perm::UserRead>,
+        State(state): State<AppState>,
+        AppPath(PkPath { id }): AppPath<PkPath>,
+    ) -> Result<impl IntoResponse, AppError> {
+        let user = state.srv().user.get_by_id(id).await?;
+        let response = UserResp::from(user);
+        Ok(Json(response))
+    }
 }
 
-#[utoipa::path(put, path="/{id}/username", params(
-    ("id" = Pk, Path)
-), request_body = UpdateUsernameReq, responses(
-    (status = 200, body = UserResp),
-    (status = 400, body = ErrorResp),
-))]
-pub async fn update_username(
-    ctx: SessionCtx,
-    State(state): State<AppState>,
-    AppPath(PkPath { id }): AppPath<PkPath>,
-    Json(payload): Json<UpdateUsernameReq>,
-) -> Result<impl IntoResponse, AppError> {
-    state
-        .srv()
-        .auth
-        .require_permission(ctx.user_id, Perm::UserWrite)
-        .await?;
-
-    let user = state
-        .srv()
-        .user
-        .update_username(id, payload.username)
-        .await?;
-    let response = UserResp::from(user);
-    Ok(Json(response))
+perm_route! {
+    #[utoipa::path(put, path="/{id}/username", params(
+        ("id" = Pk, Path)
+    ), request_body = UpdateUsernameReq, responses(
+        (status = 200, body = UserResp),
+        (status = 400, body = ErrorResp),
+    ))]
+    pub async fn update_username(
+        _: RequirePerm<perm::UserWrite>,
+        State(state): State<AppState>,
+        AppPath(PkPath { id }): AppPath<PkPath>,
+        Json(payload): Json<UpdateUsernameReq>,
+    ) -> Result<impl IntoResponse, AppError> {
+        let user = state
+            .srv()
+            .user
+            .update_username(id, payload.username)
+            .await?;
+        let response = UserResp::from(user);
+        Ok(Json(response))
+    }
 }
 
 #[utoipa::path(put, path="/{id}/password", params(
@@ -130,7 +116,7 @@ pub async fn update_username(
     (status = 400, body = ErrorResp),
 ))]
 pub async fn change_password(
-    ctx: SessionCtx,
+    AllowPasswordChange(ctx): AllowPasswordChange<AuthCtx>,
     State(state): State<AppState>,
     AppPath(PkPath { id }): AppPath<PkPath>,
     Json(payload): Json<ChangePasswordReq>,
@@ -145,7 +131,12 @@ pub async fn change_password(
     state
         .srv()
         .user
-        .change_password(id, &payload.old_password, &payload.new_password)
+        .change_password(
+            id,
+            &payload.old_password,
+            &payload.new_password,
+            ctx.session_id.as_deref(),
+        )
         .await?;
     let response = MessageResp {
         message: "Password changed successfully".to_string(),
@@ -153,28 +144,155 @@ pub async fn change_password(
     Ok(Json(response))
 }
 
-#[utoipa::path(delete, path="/{id}", params(
-    ("id" = Pk, Path)
-), responses(
-    (status = 200, body = MessageResp),
-    (status = 400, body = ErrorResp),
-))]
-pub async fn delete(
-    ctx: SessionCtx,
-    State(state): State<AppState>,
-    AppPath(PkPath { id }): AppPath<PkPath>,
-) -> Result<impl IntoResponse, AppError> {
-    state
-        .srv()
-        .auth
-        .require_permission(ctx.user_id, Perm::UserDelete)
-        .await?;
+perm_route! {
+    #[utoipa::path(put, path="/{id}/password/reset", params(
+        ("id" = Pk, Path)
+    ), request_body = ResetPasswordReq, responses(
+        (status = 200, body = MessageResp),
+        (status = 400, body = ErrorResp),
+    ))]
+    pub async fn reset_password(
+        ctx: RequirePerm<perm::UserWrite>,
+        State(state): State<AppState>,
+        AppPath(PkPath { id }): AppPath<PkPath>,
+        AppJson(payload): AppJson<ResetPasswordReq>,
+    ) -> Result<impl IntoResponse, AppError> {
+        // Resetting a password takes the account over; only allow it for users
+        // who hold nothing the caller doesn't.
+        let target_perms = state.srv().role.get_user_permissions(id).await?;
+        state
+            .srv()
+            .auth
+            .require_permissions(ctx.user_id, &target_perms)
+            .await?;
 
-    state.srv().user.delete(id).await?;
-    let response = MessageResp {
-        message: "User deleted successfully".to_string(),
-    };
-    Ok(Json(response))
+        state
+            .srv()
+            .user
+            .reset_password(id, &payload.new_password, payload.force_change)
+            .await?;
+
+        let response = MessageResp {
+            message: "Password reset successfully".to_string(),
+        };
+        Ok(Json(response))
+    }
+}
+
+perm_route! {
+    /// Lift a login lockout or backoff on the user.
+    #[utoipa::path(delete, path="/{id}/lockout", params(
+        ("id" = Pk, Path)
+    ), responses(
+        (status = 200, body = MessageResp),
+        (status = 400, body = ErrorResp),
+    ))]
+    pub async fn unlock(
+        _: RequirePerm<perm::UserWrite>,
+        State(state): State<AppState>,
+        AppPath(PkPath { id }): AppPath<PkPath>,
+    ) -> Result<impl IntoResponse, AppError> {
+        let user = state.srv().user.get_by_id(id).await?;
+        state.srv().throttle.unlock_user(&user.username).await?;
+        let response = MessageResp {
+            message: "User unlocked".to_string(),
+        };
+        Ok(Json(response))
+    }
+}
+
+perm_route! {
+    #[utoipa::path(delete, path="/{id}", params(
+        ("id" = Pk, Path)
+    ), responses(
+        (status = 200, body = MessageResp),
+        (status = 400, body = ErrorResp),
+    ))]
+    pub async fn delete(
+        _: RequirePerm<perm::UserDelete>,
+        State(state): State<AppState>,
+        AppPath(PkPath { id }): AppPath<PkPath>,
+    ) -> Result<impl IntoResponse, AppError> {
+        state.srv().user.delete(id).await?;
+        let response = MessageResp {
+            message: "User deleted successfully".to_string(),
+        };
+        Ok(Json(response))
+    }
+}
+
+perm_route! {
+    #[utoipa::path(get, path="/{id}/roles", params(
+        ("id" = Pk, Path)
+    ), responses(
+        (status = 200, body = Vec<RoleResp>),
+        (status = 400, body = ErrorResp),
+    ))]
+    pub async fn list_roles(
+        _: RequirePerm<perm::RoleRead>,
+        State(state): State<AppState>,
+        AppPath(PkPath { id }): AppPath<PkPath>,
+    ) -> Result<impl IntoResponse, AppError> {
+        state.srv().user.get_by_id(id).await?;
+        let roles = state.srv().role.get_user_roles(id).await?;
+        let response: Vec<RoleResp> = roles.into_iter().map(RoleResp::from).collect();
+        Ok(Json(response))
+    }
+}
+
+perm_route! {
+    #[utoipa::path(post, path="/{id}/roles", params(
+        ("id" = Pk, Path)
+    ), request_body = AssignRoleReq, responses(
+        (status = 200, body = Vec<RoleResp>),
+        (status = 400, body = ErrorResp),
+    ))]
+    pub async fn assign_role(
+        ctx: RequirePerm<perm::RoleWrite>,
+        State(state): State<AppState>,
+        AppPath(PkPath { id }): AppPath<PkPath>,
+        AppJson(payload): AppJson<AssignRoleReq>,
+    ) -> Result<impl IntoResponse, AppError> {
+        state.srv().user.get_by_id(id).await?;
+        let role = state.srv().role.get_by_id(payload.role_id).await?;
+        state
+            .srv()
+            .auth
+            .require_permissions(ctx.user_id, &role.parse_perms())
+            .await?;
+        state.srv().role.assign_to_user(id, payload.role_id).await?;
+
+        let roles = state.srv().role.get_user_roles(id).await?;
+        let response: Vec<RoleResp> = roles.into_iter().map(RoleResp::from).collect();
+        Ok(Json(response))
+    }
+}
+
+perm_route! {
+    #[utoipa::path(delete, path="/{id}/roles/{role_id}", params(
+        ("id" = Pk, Path),
+        ("role_id" = Pk, Path)
+    ), responses(
+        (status = 200, body = MessageResp),
+        (status = 400, body = ErrorResp),
+    ))]
+    pub async fn remove_role(
+        ctx: RequirePerm<perm::RoleWrite>,
+        State(state): State<AppState>,
+        AppPath(UserRolePath { id, role_id }): AppPath<UserRolePath>,
+    ) -> Result<impl IntoResponse, AppError> {
+        let role = state.srv().role.get_by_id(role_id).await?;
+        state
+            .srv()
+            .auth
+            .require_permissions(ctx.user_id, &role.parse_perms())
+            .await?;
+        state.srv().role.remove_from_user(id, role_id).await?;
+        let response = MessageResp {
+            message: "Role removed successfully".to_string(),
+        };
+        Ok(Json(response))
+    }
 }
 
 pub fn router() -> EndpointRouter<AppState> {
@@ -185,6 +303,11 @@ pub fn router() -> EndpointRouter<AppState> {
         .routes(routes![delete])
         .routes(routes![update_username])
         .routes(routes![change_password])
+        .routes(routes![reset_password])
+        .routes(routes![unlock])
+        .routes(routes![list_roles])
+        .routes(routes![assign_role])
+        .routes(routes![remove_role])
         .with_tags(["user"])
         .endpoint("/users")
 }
@@ -0,0 +1,23 @@
+use axum::{Json, extract::State, response::IntoResponse};
+use utoipa_axum::{router::OpenApiRouter, routes};
+
+use crate::{
+    app::AppState,
+    ext::{EndpointRouter, EndpointRouterT, OpenApiRouterExt},
+};
+
+/// Public keys for verifying access tokens; empty while tokens are signed
+/// with the HS256 secret.
+#[utoipa::path(get, path="/jwks.json", responses(
+    (status = 200, description = "JWK set (RFC 7517)", body = Value),
+))]
+pub async fn jwks(State(state): State<AppState>) -> impl IntoResponse {
+    Json(state.srv().token.jwks().clone())
+}
+
+pub fn router() -> EndpointRouter<AppState> {
+    OpenApiRouter::new()
+        .routes(routes![jwks])
+        .with_tags(["well-known"])
+        .endpoint("/.well-known")
+}
@@ -1,19 +1,39 @@
 use axum::{
     extract::{
         State,
-        ws::{Message, WebSocket, WebSocketUpgrade},
+        ws::{CloseFrame, Message, WebSocket, WebSocketUpgrade, close_code},
     },
     response::IntoResponse,
 };
+use tokio_util::sync::CancellationToken;
 
 use crate::app::AppState;
 
-pub async fn ws_handler(State(_state): State<AppState>, ws: WebSocketUpgrade) -> impl IntoResponse {
-    ws.on_upgrade(handle_socket)
+pub async fn ws_handler(State(state): State<AppState>, ws: WebSocketUpgrade) -> impl IntoResponse {
+    let shutdown = state.workers().shutdown_token().clone();
+    ws.on_upgrade(move |socket| handle_socket(socket, shutdown))
 }
 
-async fn handle_socket(mut socket: WebSocket) {
-    while let Some(Ok(msg)) = socket.recv().await {
+/// Echo text messages back until the client leaves or the server shuts
+/// down, in which case the client is sent a close frame.
+async fn handle_socket(mut socket: WebSocket, shutdown: CancellationToken) {
+    loop {
+        let msg = tokio::select! {
+            msg = socket.recv() => msg,
+            () = shutdown.cancelled() => {
+                let frame = CloseFrame {
+                    code: close_code::AWAY,
+                    reason: "Server shutting down".into(),
+                };
+                if let Err(e) = socket.send(Message::Close(Some(frame))).await {
+                    tracing::debug!("Failed to close WebSocket: {}", e);
+                }
+                break;
+            }
+        };
+        let Some(Ok(msg)) = msg else {
+            break;
+        };
         if let Message::Text(text) = msg {
             let reply = format!("echo: {}", text);
             if let Err(e) = socket.send(Message::text(reply)).await {
@@ -1,8 +1,193 @@
-use tower_http::cors::{Any, CorsLayer};
+use std::time::Duration;
 
-pub fn cors() -> CorsLayer {
-    CorsLayer::new()
-        .allow_origin(Any)
-        .allow_methods(Any)
-        .allow_headers(Any)
+use axum::http::{HeaderName, HeaderValue, Method};
+use tower_http::cors::{AllowHeaders, AllowMethods, AllowOrigin, CorsLayer};
+
+use crate::{
+    bail,
+    config::RawAppConfig,
+    error::{ErrorKind, Result},
+};
+
+/// Build the CORS layer from the `cors` config section.
+pub fn cors(cfg: &RawAppConfig) -> Result<CorsLayer> {
+    let cors = &cfg.cors;
+    let credentials = cors.allow_credentials;
+    let origins = cors.origins(cfg.env);
+
+    // Browsers refuse credentialed responses with a `*` origin, and
+    // tower-http rejects the combination outright.
+    let allow_origin = if origins.iter().any(|o| o == "*") {
+        if credentials {
+            bail!(
+                ErrorKind::Config,
+                "cors: origin `*` cannot be combined with allow-credentials"
+            );
+        }
+        AllowOrigin::any()
+    } else {
+        let (patterns, exact): (Vec<_>, Vec<_>) =
+            origins.into_iter().partition(|o| o.contains('*'));
+        let exact = exact
+            .iter()
+            .map(|o| HeaderValue::from_str(o))
+            .collect::<std::result::Result<Vec<_>, _>>()
+            .map_err(|e| ErrorKind::Config.err_msg(e, "cors: invalid origin"))?;
+
+        AllowOrigin::predicate(move |origin, _| {
+            exact.contains(origin)
+                || origin
+                    .to_str()
+                    .is_ok_and(|o| patterns.iter().any(|p| matches_pattern(p, o)))
+        })
+    };
+
+    let allow_methods = if cors.allow_methods.iter().any(|m| m == "*") {
+        wildcard(
+            credentials,
+            AllowMethods::any(),
+            AllowMethods::mirror_request(),
+        )
+    } else {
+        cors.allow_methods
+            .iter()
+            .map(|m| Method::from_bytes(m.to_uppercase().as_bytes()))
+            .collect::<std::result::Result<Vec<_>, _>>()
+            .map_err(|e| ErrorKind::Config.err_msg(e, "cors: invalid method"))?
+            .into()
+    };
+
+    let allow_headers = if cors.allow_headers.iter().any(|h| h == "*") {
+        wildcard(
+            credentials,
+            AllowHeaders::any(),
+            AllowHeaders::mirror_request(),
+        )
+    } else {
+        // Cookie sessions can't make a state-changing request without the
+        // CSRF header, so it is always allowed.
+        let mut headers = cors.allow_headers.clone();
+        let csrf = &cfg.auth.csrf;
+        if csrf.enabled
+            && !headers
+                .iter()
+                .any(|h| h.eq_ignore_ascii_case(&csrf.header_name))
+        {
+            headers.push(csrf.header_name.clone());
+        }
+        header_names(&headers)?.into()
+    };
+
+    Ok(CorsLayer::new()
+        .allow_origin(allow_origin)
+        .allow_methods(allow_methods)
+        .allow_headers(allow_headers)
+        .expose_headers(header_names(&cors.expose_headers)?)
+        .allow_credentials(credentials)
+        .max_age(Duration::from_secs(cors.max_age_seconds)))
+}
+
+/// `*` becomes "anything", or echoes the request when credentials are on.
+fn wildcard<T>(credentials: bool, any: T, mirror: T) -> T {
+    if credentials { mirror } else { any }
+}
+
+fn header_names(names: &[String]) -> Result<Vec<HeaderName>> {
+    names
+        .iter()
+        .map(|h| HeaderName::from_bytes(h.as_bytes()))
+        .collect::<std::result::Result<Vec<_>, _>>()
+        .map_err(|e| ErrorKind::Config.err_msg(e, "cors: invalid header name"))
+}
+
+/// Whether `origin` is listed in `origins`, exactly or by pattern.
+///
+/// A lone `*` entry is not honoured here: it only ever permits
+/// non-credentialed requests.
+pub fn origin_allowed(origins: &[String], origin: &str) -> bool {
+    origins
+        .iter()
+        .filter(|o| *o != "*")
+        .any(|o| o == origin || (o.contains('*') && matches_pattern(o, origin)))
+}
+
+/// Match an origin against a pattern where `*` stands for any run of
+/// characters other than `/`.
+fn matches_pattern(pattern: &str, origin: &str) -> bool {
+    let mut parts = pattern.split('*');
+    let first = parts.next().unwrap_or_default();
+    let Some(mut rest) = origin.strip_prefix(first) else {
+        return false;
+    };
+
+    let mut parts = parts.peekable();
+    while let Some(part) = parts.next() {
+        if parts.peek().is_none() {
+            // Last literal must anchor at the end.
+            return rest
+                .strip_suffix(part)
+                .is_some_and(|wild| !wild.contains('/'));
+        }
+        match rest.find(part) {
+            Some(i) if !rest[..i].contains('/') => rest = &rest[i + part.len()..],
+            _ => return false,
+        }
+    }
+
+    rest.is_empty()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_matches_pattern() {
+        assert!(matches_pattern(
+            "http://localhost:*",
+            "http://localhost:5173"
+        ));
+        assert!(matches_pattern(
+            "https://*.example.com",
+            "https://app.example.com"
+        ));
+        assert!(matches_pattern(
+            "https://*.example.com",
+            "https://a.b.example.com"
+        ));
+        assert!(!matches_pattern(
+            "https://*.example.com",
+            "https://example.com"
+        ));
+        assert!(!matches_pattern(
+            "https://*.example.com",
+            "https://evil.com"
+        ));
+        assert!(!matches_pattern(
+            "https://*.example.com",
+            "http://app.example.com"
+        ));
+        assert!(!matches_pattern(
+            "https://*.example.com",
+            "https://x/.example.com"
+        ));
+        assert!(!matches_pattern(
+            "http://localhost:*",
+            "http://localhost.evil.com"
+        ));
+        assert!(matches_pattern(
+            "https://example.com",
+            "https://example.com"
+        ));
+    }
+
+    #[test]
+    fn test_wildcard_origin_with_credentials_rejected() {
+        let mut cfg = RawAppConfig::default();
+        cfg.cors.allow_origins = Some(vec!["*".to_string()]);
+        assert!(cors(&cfg).is_err());
+
+        cfg.cors.allow_credentials = false;
+        assert!(cors(&cfg).is_ok());
+    }
 }
@@ -0,0 +1,193 @@
+use axum::{
+    extract::{Request, State},
+    http::{HeaderMap, header, request::Parts},
+    middleware::Next,
+    response::Response,
+};
+use axum_extra::extract::cookie::{Cookie, CookieJar};
+use hmac::{Hmac, Mac};
+use jiff::Timestamp;
+use sha2::Sha256;
+
+use super::cors::origin_allowed;
+use crate::{
+    app::{
+        AppState,
+        extractor::{bearer_token, build_session_cookie, session_cookie},
+    },
+    bail,
+    config::CsrfMode,
+    error::{ErrorKind, Result},
+    util::token,
+};
+
+/// Reject cross-site, state-changing requests.
+///
+/// Safe methods and Bearer-authenticated requests pass through; the auth
+/// extractors never fall back to the session cookie when a Bearer token is
+/// present (see [`SessionCtx`](crate::app::extractor::SessionCtx)). Everything
+/// else must come from an allowed `Origin`, and requests carrying a session
+/// cookie must also echo the CSRF token in the configured header.
+pub async fn csrf_protect(
+    State(state): State<AppState>,
+    req: Request,
+    next: Next,
+) -> Result<Response> {
+    let cfg = &state.cfg().auth.csrf;
+    if !cfg.enabled || req.method().is_safe() {
+        return Ok(next.run(req).await);
+    }
+
+    let (parts, body) = req.into_parts();
+    if bearer_token(&parts).is_none() {
+        if cfg.check_origin {
+            check_origin(&state, &parts.headers)?;
+        }
+        if let Some(session_id) = session_cookie(&parts, &state) {
+            check_token(&state, &parts, &session_id).await?;
+        }
+    }
+
+    Ok(next.run(Request::from_parts(parts, body)).await)
+}
+
+/// The token a client must send for `session_id`, plus the cookie to set in
+/// double-submit mode, lasting until the session's `expires_at`.
+pub async fn issue_token(
+    state: &AppState,
+    session_id: &str,
+    expires_at: Timestamp,
+) -> Result<(String, Option<Cookie<'static>>)> {
+    let cfg = &state.cfg().auth.csrf;
+    match cfg.mode {
+        CsrfMode::Synchronizer => {
+            let token = state
+                .srv()
+                .session
+                .csrf_token(session_id)
+                .await?
+                .ok_or(ErrorKind::Unauthorized)?;
+            Ok((token, None))
+        }
+        CsrfMode::DoubleSubmit => {
+            let nonce = token::random_hex(16);
+            let token = format!("{nonce}.{}", sign(&cfg.secret, session_id, &nonce));
+
+            // Same lifetime and scope as the session cookie, but readable by
+            // scripts so they can copy it into the header.
+            let mut cookie = build_session_cookie(&state.cfg().auth.session, &token, expires_at);
+            cookie.set_name(cfg.cookie_name.clone());
+            cookie.set_http_only(false);
+            Ok((token, Some(cookie)))
+        }
+    }
+}
+
+async fn check_token(state: &AppState, parts: &Parts, session_id: &str) -> Result<()> {
+    let cfg = &state.cfg().auth.csrf;
+    let Some(sent) = parts
+        .headers
+        .get(&cfg.header_name)
+        .and_then(|v| v.to_str().ok())
+    else {
+        bail!(ErrorKind::CsrfFailed, "Missing CSRF token");
+    };
+
+    let valid = match cfg.mode {
+        CsrfMode::Synchronizer => match state.srv().session.find(session_id).await? {
+            Some(session) => {
+                !session.csrf_token.is_empty()
+                    && token::constant_time_eq(sent.as_bytes(), session.csrf_token.as_bytes())
+            }
+            // Unknown session: let the auth extractor reject it.
+            None => return Ok(()),
+        },
+        CsrfMode::DoubleSubmit => {
+            let jar = CookieJar::from_headers(&parts.headers);
+            let cookie = jar.get(&cfg.cookie_name).map(|c| c.value()).unwrap_or("");
+            token::constant_time_eq(sent.as_bytes(), cookie.as_bytes())
+                && verify(&cfg.secret, session_id, sent)
+        }
+    };
+
+    if !valid {
+        bail!(ErrorKind::CsrfFailed, "Invalid CSRF token");
+    }
+    Ok(())
+}
+
+/// Accept requests without `Origin`/`Referer` (non-browser clients), from
+/// this host, or from an allowed CORS origin.
+fn check_origin(state: &AppState, headers: &HeaderMap) -> Result<()> {
+    let origin = match headers.get(header::ORIGIN) {
+        Some(v) => v.to_str().unwrap_or_default().to_owned(),
+        None => match headers.get(header::REFERER).and_then(|v| v.to_str().ok()) {
+            Some(referer) => referer_origin(referer).unwrap_or_default(),
+            None => return Ok(()),
+        },
+    };
+
+    let host = headers.get(header::HOST).and_then(|v| v.to_str().ok());
+    let same_origin = origin
+        .split_once("://")
+        .is_some_and(|(_, authority)| Some(authority) == host);
+    let cfg = state.cfg();
+    if same_origin || origin_allowed(&cfg.cors.origins(cfg.env), &origin) {
+        return Ok(());
+    }
+
+    bail!(ErrorKind::CsrfFailed, "Cross-origin request rejected")
+}
+
+/// `https://host:port/path?q` -> `https://host:port`
+fn referer_origin(referer: &str) -> Option<String> {
+    let (scheme, rest) = referer.split_once("://")?;
+    let authority = rest.split(['/', '?', '#']).next()?;
+    Some(format!("{scheme}://{authority}"))
+}
+
+fn sign(secret: &str, session_id: &str, nonce: &str) -> String {
+    hex::encode(mac(secret, session_id, nonce).finalize().into_bytes())
+}
+
+fn verify(secret: &str, session_id: &str, token: &str) -> bool {
+    let Some((nonce, sig)) = token.split_once('.') else {
+        return false;
+    };
+    let Ok(sig) = hex::decode(sig) else {
+        return false;
+    };
+    mac(secret, session_id, nonce).verify_slice(&sig).is_ok()
+}
+
+fn mac(secret: &str, session_id: &str, nonce: &str) -> Hmac<Sha256> {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts any key length");
+    mac.update(session_id.as_bytes());
+    mac.update(b".");
+    mac.update(nonce.as_bytes());
+    mac
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_double_submit_signature() {
+        let token = format!("abc.{}", sign("secret", "session-1", "abc"));
+        assert!(verify("secret", "session-1", &token));
+        assert!(!verify("secret", "session-2", &token));
+        assert!(!verify("other", "session-1", &token));
+        assert!(!verify("secret", "session-1", "abc.00"));
+    }
+
+    #[test]
+    fn test_referer_origin() {
+        assert_eq!(
+            referer_origin("https://app.example.com:8443/a/b?c#d").as_deref(),
+            Some("https://app.example.com:8443")
+        );
+        assert_eq!(referer_origin("garbage"), None);
+    }
+}
@@ -1 +1,3 @@
 pub mod cors;
+pub mod csrf;
+pub mod session;
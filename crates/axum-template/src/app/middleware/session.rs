@@ -0,0 +1,62 @@
+use std::sync::{Arc, Mutex};
+
+use axum::{
+    extract::{Request, State},
+    http::{HeaderValue, header},
+    middleware::Next,
+    response::Response,
+};
+use jiff::Timestamp;
+
+use crate::app::{AppState, extractor::build_session_cookie};
+
+/// Request extension through which [`SessionCtx`](crate::app::extractor::SessionCtx)
+/// reports that it extended the session.
+#[derive(Debug, Clone, Default)]
+pub struct SessionRefresh(Arc<Mutex<Option<(String, Timestamp)>>>);
+
+impl SessionRefresh {
+    pub fn set(&self, session_id: &str, expires_at: Timestamp) {
+        *self.0.lock().unwrap_or_else(|e| e.into_inner()) =
+            Some((session_id.to_owned(), expires_at));
+    }
+
+    fn take(&self) -> Option<(String, Timestamp)> {
+        self.0.lock().unwrap_or_else(|e| e.into_inner()).take()
+    }
+}
+
+/// Re-issue the session cookie when the session was extended, so its
+/// `Max-Age` keeps tracking the server-side expiry.
+///
+/// Responses that already set the cookie themselves (login, logout) are left
+/// alone.
+pub async fn refresh_session_cookie(
+    State(state): State<AppState>,
+    mut req: Request,
+    next: Next,
+) -> Response {
+    let refresh = SessionRefresh::default();
+    req.extensions_mut().insert(refresh.clone());
+
+    let mut response = next.run(req).await;
+
+    let Some((session_id, expires_at)) = refresh.take() else {
+        return response;
+    };
+    let cfg = &state.cfg().auth.session;
+    let prefix = format!("{}=", cfg.cookie_name);
+    let already_set = response
+        .headers()
+        .get_all(header::SET_COOKIE)
+        .iter()
+        .any(|v| v.as_bytes().starts_with(prefix.as_bytes()));
+
+    if !already_set {
+        let cookie = build_session_cookie(cfg, &session_id, expires_at).to_string();
+        if let Ok(value) = HeaderValue::from_str(&cookie) {
+            response.headers_mut().append(header::SET_COOKIE, value);
+        }
+    }
+    response
+}
@@ -14,8 +14,16 @@ use utoipa_axum::router::OpenApiRouter;
 use utoipa_scalar::{Scalar, Servable};
 use utoipa_swagger_ui::SwaggerUi;
 
-use super::{handler, middleware::cors};
-use crate::{app::AppState, config::RawAppConfig, error::Result, ext::EndpointRouterT};
+use super::{
+    handler,
+    middleware::{cors, csrf, session},
+};
+use crate::{
+    app::AppState,
+    config::RawAppConfig,
+    error::Result,
+    ext::{EndpointRouterT, document_csrf_header},
+};
 
 #[derive(RustEmbed)]
 #[folder = "static/"]
@@ -80,15 +88,41 @@ async fn index_handler() -> impl IntoResponse {
     }
 }
 
-/// Create the application router with all routes and middleware
-pub async fn create_router(state: AppState) -> Result<Router> {
+/// Create the documented `/api` router
+pub fn api_router() -> OpenApiRouter<AppState> {
     let router = OpenApiRouter::new()
         .merge(handler::chore::router())
         .mount(handler::auth::router())
+        .mount(handler::mfa::router())
         .mount(handler::user::router())
+        .mount(handler::role::router())
         .mount(handler::jwt_demo::router());
 
-    let (router, api) = OpenApiRouter::new().nest("/api", router).split_for_parts();
+    OpenApiRouter::new().nest("/api", router)
+}
+
+/// Create the `/health` probe router.
+///
+/// Kept apart from [`api_router`] so probes bypass the trace and CORS layers.
+pub fn health_router() -> OpenApiRouter<AppState> {
+    OpenApiRouter::new().mount(handler::health::router())
+}
+
+/// Create the `/.well-known` router for documents fetched by other services.
+pub fn well_known_router() -> OpenApiRouter<AppState> {
+    OpenApiRouter::new().mount(handler::well_known::router())
+}
+
+/// Create the application router with all routes and middleware
+pub async fn create_router(state: AppState) -> Result<Router> {
+    let (router, mut api) = api_router().merge(well_known_router()).split_for_parts();
+    let (health, health_api) = health_router().split_for_parts();
+    api.merge(health_api);
+
+    let csrf = &state.cfg().auth.csrf;
+    if csrf.enabled {
+        document_csrf_header(&mut api, &csrf.header_name);
+    }
 
     Ok(router
         .merge(Scalar::with_url("/api-docs/scalar", api.clone()))
@@ -96,8 +130,17 @@ pub async fn create_router(state: AppState) -> Result<Router> {
         .layer(
             ServiceBuilder::new()
                 .layer(TraceLayer::new_for_http())
-                .layer(cors::cors()),
+                .layer(cors::cors(state.cfg())?)
+                .layer(axum::middleware::from_fn_with_state(
+                    state.clone(),
+                    session::refresh_session_cookie,
+                ))
+                .layer(axum::middleware::from_fn_with_state(
+                    state.clone(),
+                    csrf::csrf_protect,
+                )),
         )
+        .merge(health)
         .route("/", get(index_handler))
         .route("/{*path}", get(static_handler))
         .with_state(state))
@@ -1,34 +1,128 @@
+use std::{future::IntoFuture, net::SocketAddr, time::Duration};
+
+use tokio_util::sync::CancellationToken;
+
 use crate::{
     app::{
         router::{create_listener, create_router},
         state::AppState,
     },
     config::AppConfig,
+    domain::Services,
     error::Result,
     infra::logging::init_tracing,
 };
 
+/// How often expired sessions, tokens and the like are deleted.
+const CLEANUP_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
 pub async fn serve() -> Result<()> {
     let _ = dotenvy::dotenv();
     let cfg = AppConfig::load()?;
 
-    init_tracing(&cfg.log);
+    // Flushes the file log when `serve` returns.
+    let _log_guard = init_tracing(&cfg.log);
 
     let listener = create_listener(&cfg).await?;
+    let drain_timeout = Duration::from_secs(cfg.server.shutdown_timeout_seconds);
 
     let app_state = AppState::new(cfg).await?;
+    let workers = app_state.workers().clone();
+    let shutdown = workers.shutdown_token().clone();
+    let services = app_state.srv().clone();
+    workers.spawn("cleanup", |shutdown| cleanup(services, shutdown));
 
     let router = create_router(app_state).await?;
     let addr = listener.local_addr()?;
 
     tracing::info!("App listening on {}", addr);
 
-    tokio::spawn(async move {
-        if let Err(err) = axum::serve(listener, router).await {
+    tokio::spawn({
+        let shutdown = shutdown.clone();
+        async move {
+            shutdown_signal().await;
+            tracing::info!("Shutdown signal received, draining connections and workers");
+            shutdown.cancel();
+        }
+    });
+    let server = axum::serve(
+        listener,
+        router.into_make_service_with_connect_info::<SocketAddr>(),
+    )
+    .with_graceful_shutdown(shutdown.clone().cancelled_owned())
+    .into_future();
+
+    let drain = async {
+        if let Err(err) = server.await {
             tracing::error!("Server error: {}", err);
         }
-    })
-    .await?;
+        // Also stops the workers if the server failed on its own.
+        shutdown.cancel();
+        workers.join().await;
+    };
+    tokio::select! {
+        _ = drain => {}
+        _ = drain_deadline(&shutdown, drain_timeout) => {
+            tracing::warn!(
+                "Connections or workers still running after {}s, shutting down anyway",
+                drain_timeout.as_secs()
+            );
+        }
+    }
 
+    tracing::info!("Server stopped");
     Ok(())
 }
+
+/// Periodically delete rows that can no longer be used, until `shutdown`.
+/// A failed pass is logged and retried at the next tick rather than
+/// stopping the worker.
+async fn cleanup(services: Services, shutdown: CancellationToken) -> Result<()> {
+    let mut interval = tokio::time::interval(CLEANUP_INTERVAL);
+    loop {
+        tokio::select! {
+            _ = interval.tick() => {}
+            _ = shutdown.cancelled() => return Ok(()),
+        }
+        if let Err(err) = services.purge_expired().await {
+            tracing::warn!("Cleanup failed: {}", err);
+        }
+    }
+}
+
+/// Resolves `timeout` after shutdown starts; never resolves otherwise.
+async fn drain_deadline(shutdown: &CancellationToken, timeout: Duration) {
+    shutdown.cancelled().await;
+    tokio::time::sleep(timeout).await;
+}
+
+/// Wait for Ctrl+C, or SIGTERM on Unix.
+async fn shutdown_signal() {
+    let ctrl_c = async {
+        if let Err(err) = tokio::signal::ctrl_c().await {
+            tracing::error!("Failed to listen for Ctrl+C: {}", err);
+            std::future::pending::<()>().await;
+        }
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+            Ok(mut signal) => {
+                signal.recv().await;
+            }
+            Err(err) => {
+                tracing::error!("Failed to listen for SIGTERM: {}", err);
+                std::future::pending::<()>().await;
+            }
+        }
+    };
+
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => {}
+        _ = terminate => {}
+    }
+}
@@ -1,9 +1,26 @@
+use std::{
+    sync::{Arc, atomic::AtomicBool},
+    time::Duration,
+};
+
+use jiff::SignedDuration;
 use toasty::Db;
 
 use crate::{
-    config::AppConfig,
-    domain::{Services, db::init_db},
-    error::Result,
+    bail,
+    config::{
+        AppConfig, AppEnv, CookieSameSite, CsrfConfig, CsrfMode, DEFAULT_SECRET, JwtConfig,
+        PasswordConfig, SessionConfig, ThrottleLimits,
+    },
+    domain::{
+        AttemptLimits, ClaimsHook, MfaPolicy, Services, SessionPolicy, ThrottlePolicy, TokenPolicy,
+        TokenService,
+        db::init_db,
+        jwt_keys::{JwtKey, JwtKeys},
+    },
+    error::{ErrorKind, Result, ResultExt},
+    infra::worker::Workers,
+    util::password,
 };
 
 #[derive(Debug, Clone)]
@@ -11,21 +28,68 @@ pub struct AppState {
     pub config: AppConfig,
     pub db: Db,
     pub services: Services,
+    pub workers: Workers,
+    /// Set by the readiness probe once no migrations are pending, after
+    /// which it stops asking.
+    pub migrations_applied: Arc<AtomicBool>,
 }
 
 impl AppState {
     pub async fn new(config: AppConfig) -> Result<Self> {
+        Self::with_claims_hook(config, None).await
+    }
+
+    /// Like [`new`](Self::new), adding `claims_hook`'s claims to every
+    /// access token.
+    pub async fn with_claims_hook(
+        config: AppConfig,
+        claims_hook: Option<Arc<dyn ClaimsHook>>,
+    ) -> Result<Self> {
+        check_csrf_secret(&config.auth.csrf, config.env)?;
+        check_session_cookie(&config.auth.session)?;
         let db = init_db(&config.database.url).await?;
+        let session = &config.auth.session;
+        let session_policy = SessionPolicy {
+            idle_timeout: SignedDuration::from_mins(session.idle_timeout_minutes as i64),
+            absolute_timeout: SignedDuration::from_hours(absolute_timeout_hours(session) as i64),
+            touch_interval: SignedDuration::from_secs(session.touch_interval_seconds as i64),
+        };
+        let jwt = &config.auth.jwt;
+        let token_policy = TokenPolicy {
+            access_ttl: SignedDuration::from_secs(jwt.expires_in_seconds as i64),
+            refresh_ttl: SignedDuration::from_secs(jwt.refresh_expires_in_seconds as i64),
+            issuer: jwt.issuer.clone(),
+            audience: jwt.audience.clone(),
+            leeway: SignedDuration::from_secs(jwt.leeway_seconds as i64),
+            version_cache_ttl: SignedDuration::from_secs(jwt.version_cache_seconds as i64),
+        };
+        let mfa = &config.auth.mfa;
+        let mfa_policy = MfaPolicy {
+            issuer: mfa.issuer.clone(),
+            challenge_ttl: SignedDuration::from_secs(mfa.challenge_ttl_seconds as i64),
+            max_attempts: mfa.max_attempts,
+            recovery_codes: mfa.recovery_codes,
+        };
+        let throttle = &config.auth.throttle;
+        let throttle_policy = ThrottlePolicy {
+            user: attempt_limits(&throttle.user),
+            ip: attempt_limits(&throttle.ip),
+            reset_after: SignedDuration::from_secs(throttle.reset_after_seconds as i64),
+        };
         let services = Services::new(
             db.clone(),
-            &config.auth.jwt.secret,
-            config.auth.jwt.expires_in_seconds,
-            config.auth.session.ttl_hours,
+            TokenService::new(db.clone(), load_jwt_keys(jwt)?, token_policy, claims_hook),
+            session_policy,
+            mfa_policy,
+            throttle_policy,
+            load_password_hasher(&config.password)?,
         );
         Ok(Self {
             config,
             db,
             services,
+            workers: Workers::default(),
+            migrations_applied: Default::default(),
         })
     }
 }
@@ -42,4 +106,118 @@ impl AppState {
     pub fn srv(&self) -> &Services {
         &self.services
     }
+
+    pub fn workers(&self) -> &Workers {
+        &self.workers
+    }
+}
+
+fn attempt_limits(cfg: &ThrottleLimits) -> AttemptLimits {
+    AttemptLimits {
+        free_attempts: cfg.free_attempts,
+        base_delay: SignedDuration::from_secs(cfg.base_delay_seconds as i64),
+        max_delay: SignedDuration::from_secs(cfg.max_delay_seconds as i64),
+        lockout_threshold: cfg.lockout_threshold,
+        lockout: SignedDuration::from_secs(cfg.lockout_seconds as i64),
+    }
+}
+
+/// Browsers drop `SameSite=None` cookies that are not also `Secure`, which
+/// would silently break every cookie login.
+fn check_session_cookie(cfg: &SessionConfig) -> Result<()> {
+    if cfg.cookie_same_site == CookieSameSite::None && !cfg.cookie_secure {
+        bail!(
+            ErrorKind::Config,
+            "auth.session.cookie-same-site = \"none\" requires cookie-secure = true"
+        );
+    }
+    Ok(())
+}
+
+/// `absolute-timeout-hours`, unless the config still uses its old name
+/// `ttl-hours`.
+fn absolute_timeout_hours(cfg: &SessionConfig) -> u64 {
+    match cfg.ttl_hours {
+        Some(hours) => {
+            tracing::warn!(
+                "auth.session.ttl-hours is deprecated; rename it to absolute-timeout-hours"
+            );
+            hours
+        }
+        None => cfg.absolute_timeout_hours,
+    }
+}
+
+/// Refuse to run in production with the placeholder secret, with which
+/// anyone could sign double-submit CSRF tokens.
+fn check_csrf_secret(cfg: &CsrfConfig, env: AppEnv) -> Result<()> {
+    if env == AppEnv::Production
+        && cfg.enabled
+        && cfg.mode == CsrfMode::DoubleSubmit
+        && cfg.secret == DEFAULT_SECRET
+    {
+        bail!(
+            ErrorKind::Config,
+            "auth.csrf.secret must be changed from its default in production"
+        );
+    }
+    Ok(())
+}
+
+/// Read the configured key files, falling back to the HS256 secret.
+fn load_jwt_keys(cfg: &JwtConfig) -> Result<JwtKeys> {
+    if cfg.keys.is_empty() {
+        return Ok(JwtKeys::hmac(&cfg.secret));
+    }
+    let keys = cfg
+        .keys
+        .iter()
+        .map(|key| {
+            let pem = std::fs::read(&key.private_key_file).err_kind_msg(
+                ErrorKind::Config,
+                format!("Cannot read JWT key {}", key.private_key_file.display()),
+            )?;
+            JwtKey::from_pem(key.kid.clone(), key.algorithm.into(), &pem)
+        })
+        .collect::<Result<Vec<_>>>()?;
+    JwtKeys::new(keys, cfg.signing_kid.as_deref())
+}
+
+/// Build the password hasher, reading the pepper file if one is configured.
+fn load_password_hasher(cfg: &PasswordConfig) -> Result<password::Hasher> {
+    let pepper = cfg
+        .pepper_file
+        .as_ref()
+        .map(|path| {
+            let pepper = std::fs::read(path).err_kind_msg(
+                ErrorKind::Config,
+                format!("Cannot read password pepper {}", path.display()),
+            )?;
+            let pepper = pepper.trim_ascii_end().to_vec();
+            if pepper.is_empty() {
+                bail!(
+                    ErrorKind::Config,
+                    "Password pepper {} is empty",
+                    path.display()
+                );
+            }
+            Ok(pepper)
+        })
+        .transpose()?;
+    let hasher = password::Hasher::new(
+        cfg.algorithm.into(),
+        cfg.m_cost,
+        cfg.t_cost,
+        cfg.p_cost,
+        pepper,
+    )?;
+    let max_concurrent = match cfg.max_concurrent {
+        Some(0) => bail!(
+            ErrorKind::Config,
+            "password.max-concurrent must be positive"
+        ),
+        Some(max) => max,
+        None => std::thread::available_parallelism().map_or(1, |n| n.get()),
+    };
+    Ok(hasher.with_concurrency(max_concurrent, Duration::from_millis(cfg.queue_timeout_ms)))
 }
@@ -1,22 +1,18 @@
 use anyhow::Result;
-use axum_template::{config::AppConfig, domain::model};
+use axum_template::{config::AppConfig, domain::db};
 
 #[tokio::main]
 async fn main() -> Result<()> {
     let _ = dotenvy::dotenv();
     let app_config = AppConfig::load()?;
 
-    let cli_config = toasty_cli::Config::load()?;
-    let db = toasty::Db::builder()
-        .models(toasty::models!(
-            model::User,
-            model::Role,
-            model::UserRole,
-            model::RefreshToken,
-            model::Session
-        ))
-        .connect(&app_config.database.url)
-        .await?;
+    let mut cli_config = toasty_cli::Config::load()?;
+    // SQL differs per backend, so SQLite keeps its own migration history
+    if db::is_sqlite(&app_config.database.url) {
+        cli_config.migration.path = cli_config.migration.path.join("sqlite");
+    }
+
+    let db = db::init_db(&app_config.database.url).await?;
 
     let cli = toasty_cli::ToastyCli::with_config(db, cli_config);
     cli.parse_and_run().await?;
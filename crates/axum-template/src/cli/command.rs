@@ -1,4 +1,8 @@
-use clap::{Parser, Subcommand};
+use std::path::PathBuf;
+
+use clap::{Parser, Subcommand, ValueEnum};
+
+use crate::config::JwtAlgorithm;
 
 #[derive(Parser)]
 #[command(name = env!("CARGO_CRATE_NAME"))]
@@ -32,6 +36,50 @@ pub enum Commands {
 
     /// List all available permissions
     Perms,
+
+    /// Lift a login lockout or backoff
+    Unlock {
+        /// Username to unlock
+        #[arg(short, long, required_unless_present = "ip")]
+        username: Option<String>,
+
+        /// Client IP address to unlock
+        #[arg(long, conflicts_with = "username")]
+        ip: Option<String>,
+    },
+
+    /// Generate a JWT signing key and print how to stage it
+    JwtKeygen {
+        /// Key algorithm (RS256 keys must be generated with openssl)
+        #[arg(short, long, value_enum, default_value = "EdDSA")]
+        algorithm: KeygenAlgorithm,
+
+        /// Key id; defaults to today's date with a random suffix
+        #[arg(short, long)]
+        kid: Option<String>,
+
+        /// Directory to write `<kid>.pem` into
+        #[arg(short, long, default_value = "keys")]
+        out: PathBuf,
+    },
+}
+
+/// The [`JwtAlgorithm`]s `jwt-keygen` can generate keys for.
+#[derive(Clone, Copy, ValueEnum)]
+pub enum KeygenAlgorithm {
+    #[value(name = "ES256")]
+    Es256,
+    #[value(name = "EdDSA")]
+    EdDsa,
+}
+
+impl From<KeygenAlgorithm> for JwtAlgorithm {
+    fn from(algorithm: KeygenAlgorithm) -> Self {
+        match algorithm {
+            KeygenAlgorithm::Es256 => Self::Es256,
+            KeygenAlgorithm::EdDsa => Self::EdDsa,
+        }
+    }
 }
 
 #[derive(Subcommand)]
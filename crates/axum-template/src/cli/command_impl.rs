@@ -1,11 +1,15 @@
+use std::{fs, io::Write, path::Path};
+
+use super::command::KeygenAlgorithm;
 use crate::{
     bail,
-    config::RawAppConfig,
+    config::{JwtAlgorithm, RawAppConfig},
     domain::{
-        Services,
+        Services, jwt_keys,
         model::{DefaultRole, Perm},
     },
-    error::{ErrorKind, Result},
+    error::{ErrorKind, Result, ResultExt},
+    util::token,
 };
 
 pub async fn init_rbac(services: &Services) -> Result<()> {
@@ -67,6 +71,31 @@ pub async fn create_superuser(
     Ok(())
 }
 
+pub async fn unlock(
+    services: &Services,
+    username: Option<String>,
+    ip: Option<String>,
+) -> Result<()> {
+    let (target, cleared) = match (username, ip) {
+        (Some(username), _) => {
+            let cleared = services.throttle.unlock_user(&username).await?;
+            (username, cleared)
+        }
+        (None, Some(ip)) => {
+            let cleared = services.throttle.unlock_ip(&ip).await?;
+            (ip, cleared)
+        }
+        (None, None) => bail!(ErrorKind::InvalidParameter, "Give a username or an IP"),
+    };
+
+    if cleared {
+        println!("Unlocked {}", target);
+    } else {
+        println!("{} was not throttled", target);
+    }
+    Ok(())
+}
+
 pub async fn list_roles(services: &Services) -> Result<()> {
     let roles = services.role.list_all().await?;
 
@@ -129,3 +158,40 @@ pub fn print_config(config: &RawAppConfig) -> Result<()> {
     println!("{}", json);
     Ok(())
 }
+
+pub fn jwt_keygen(algorithm: KeygenAlgorithm, kid: Option<String>, out: &Path) -> Result<()> {
+    let algorithm = JwtAlgorithm::from(algorithm);
+    let kid = kid.unwrap_or_else(|| {
+        let date = jiff::Zoned::now().strftime("%Y%m%d");
+        format!("{date}-{}", token::random_hex(2))
+    });
+    let pem = jwt_keys::generate_pem(algorithm.into())?;
+
+    fs::create_dir_all(out)?;
+    let path = out.join(format!("{kid}.pem"));
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    options
+        .open(&path)
+        .err_kind_msg(
+            ErrorKind::AlreadyExists,
+            format!("Cannot create {}", path.display()),
+        )?
+        .write_all(pem.as_bytes())?;
+
+    let algorithm = serde_json::to_string(&algorithm)?;
+    println!("Wrote {}\n", path.display());
+    println!("1. Add the key and deploy. It is published at /.well-known/jwks.json");
+    println!("   but does not sign yet (unless it is the only key):\n");
+    println!("   [[auth.jwt.keys]]");
+    println!("   kid = \"{kid}\"");
+    println!("   algorithm = {algorithm}");
+    println!("   private-key-file = \"{}\"\n", path.display());
+    println!("2. Once verifiers have refreshed their key sets, sign with it:\n");
+    println!("   [auth.jwt]");
+    println!("   signing-kid = \"{kid}\"\n");
+    println!("3. After auth.jwt.expires-in-seconds, remove the previous key.");
+    Ok(())
+}
@@ -13,6 +13,12 @@ pub async fn run() -> Result<()> {
 
     match cli.command {
         None => crate::app::serve().await,
+        // Needs no database.
+        Some(Commands::JwtKeygen {
+            algorithm,
+            kid,
+            out,
+        }) => command_impl::jwt_keygen(algorithm, kid, &out),
         Some(cmd) => {
             let services = get_services().await?;
 
@@ -37,6 +43,10 @@ pub async fn run() -> Result<()> {
                     }
                 },
                 Commands::Perms => command_impl::list_permissions().await,
+                Commands::Unlock { username, ip } => {
+                    command_impl::unlock(&services, username, ip).await
+                }
+                Commands::JwtKeygen { .. } => unreachable!("handled above"),
             }
         }
     }
@@ -1,12 +1,39 @@
+use std::{net::IpAddr, path::PathBuf};
+
 use serde::{Deserialize, Serialize};
 
+/// Placeholder for `auth.jwt.secret` and `auth.csrf.secret`.
+pub const DEFAULT_SECRET: &str = "change-me-in-production";
+
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Default)]
 #[serde(default, rename_all = "kebab-case")]
 pub struct RawAppConfig {
+    pub env: AppEnv,
     pub server: ServerConfig,
     pub log: LogConfig,
     pub database: DatabaseConfig,
     pub auth: AuthConfig,
+    pub password: PasswordConfig,
+    pub cors: CorsConfig,
+}
+
+/// Deployment environment, used to pick defaults that are safe for it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum AppEnv {
+    Development,
+    Production,
+}
+
+impl Default for AppEnv {
+    /// Debug builds default to development, release builds to production.
+    fn default() -> Self {
+        if cfg!(debug_assertions) {
+            Self::Development
+        } else {
+            Self::Production
+        }
+    }
 }
 
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
@@ -14,6 +41,11 @@ pub struct RawAppConfig {
 pub struct ServerConfig {
     pub host: String,
     pub port: u16,
+    /// How long in-flight requests may take to finish after a shutdown signal.
+    pub shutdown_timeout_seconds: u64,
+    /// Reverse proxies whose `X-Forwarded-For` is believed. Requests from
+    /// any other peer are attributed to the peer itself.
+    pub trusted_proxies: Vec<IpAddr>,
 }
 
 impl Default for ServerConfig {
@@ -21,54 +53,357 @@ impl Default for ServerConfig {
         Self {
             host: "0.0.0.0".to_string(),
             port: 8000,
+            shutdown_timeout_seconds: 30,
+            trusted_proxies: Vec::new(),
         }
     }
 }
 
-#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Default)]
 #[serde(default, rename_all = "kebab-case")]
 pub struct AuthConfig {
     pub session: SessionConfig,
     pub jwt: JwtConfig,
+    pub csrf: CsrfConfig,
+    pub mfa: MfaConfig,
+    pub throttle: ThrottleConfig,
 }
 
-impl Default for AuthConfig {
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, rename_all = "kebab-case")]
+pub struct SessionConfig {
+    pub cookie_name: String,
+    /// A session expires this long after its last request.
+    pub idle_timeout_minutes: u64,
+    /// A session expires this long after login, however active it is.
+    pub absolute_timeout_hours: u64,
+    /// Deprecated name for `absolute-timeout-hours`, which it overrides.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ttl_hours: Option<u64>,
+    /// Minimum gap between activity writes for a session, so busy clients
+    /// don't cost a DB write per request.
+    pub touch_interval_seconds: u64,
+    /// Hide the cookie from JavaScript.
+    pub cookie_http_only: bool,
+    /// Only send the cookie over HTTPS. Browsers still accept it on
+    /// `http://localhost`.
+    pub cookie_secure: bool,
+    /// `none` requires `cookie-secure`.
+    pub cookie_same_site: CookieSameSite,
+    /// Unset scopes the cookie to the exact host that set it.
+    pub cookie_domain: Option<String>,
+    pub cookie_path: String,
+}
+
+impl Default for SessionConfig {
     fn default() -> Self {
         Self {
-            session: SessionConfig::default(),
-            jwt: JwtConfig::default(),
+            cookie_name: "session_id".to_string(),
+            idle_timeout_minutes: 24 * 60,
+            absolute_timeout_hours: 7 * 24,
+            ttl_hours: None,
+            touch_interval_seconds: 60,
+            cookie_http_only: true,
+            cookie_secure: true,
+            cookie_same_site: CookieSameSite::Lax,
+            cookie_domain: None,
+            cookie_path: "/".to_string(),
         }
     }
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum CookieSameSite {
+    Strict,
+    Lax,
+    None,
+}
+
+/// CSRF protection for cookie-authenticated, state-changing requests.
+/// Bearer-token requests are never checked.
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 #[serde(default, rename_all = "kebab-case")]
-pub struct SessionConfig {
+pub struct CsrfConfig {
+    pub enabled: bool,
+    pub mode: CsrfMode,
+    /// Request header carrying the token.
+    pub header_name: String,
+    /// Cookie carrying the token in `double-submit` mode.
     pub cookie_name: String,
-    pub ttl_hours: u64,
+    /// Key signing `double-submit` tokens to the session.
+    pub secret: String,
+    /// Reject requests whose `Origin` (or `Referer`) is neither this host nor
+    /// an allowed CORS origin.
+    pub check_origin: bool,
 }
 
-impl Default for SessionConfig {
+impl Default for CsrfConfig {
     fn default() -> Self {
         Self {
-            cookie_name: "session_id".to_string(),
-            ttl_hours: 24,
+            enabled: true,
+            mode: CsrfMode::Synchronizer,
+            header_name: "x-csrf-token".to_string(),
+            cookie_name: "csrf_token".to_string(),
+            secret: DEFAULT_SECRET.to_string(),
+            check_origin: true,
         }
     }
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum CsrfMode {
+    /// Token stored on the session row.
+    Synchronizer,
+    /// Token in a script-readable cookie, signed to the session and echoed
+    /// back in the header.
+    DoubleSubmit,
+}
+
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 #[serde(default, rename_all = "kebab-case")]
 pub struct JwtConfig {
+    /// HS256 secret, used only while `keys` is empty.
     pub secret: String,
     pub expires_in_seconds: u64,
+    /// Lifetime of each refresh token; rotation issues a fresh one.
+    pub refresh_expires_in_seconds: u64,
+    /// `iss` of issued tokens; tokens from any other issuer are rejected.
+    pub issuer: String,
+    /// `aud` of issued tokens; incoming tokens must name one of these.
+    /// Empty disables the check.
+    pub audience: Vec<String>,
+    /// Clock skew tolerated when checking `exp` and `nbf`.
+    pub leeway_seconds: u64,
+    /// How long each user's token version is cached when verifying access
+    /// tokens; revocations on other instances apply within this time.
+    pub version_cache_seconds: u64,
+    /// Asymmetric keys. All of them verify tokens and are published at
+    /// `/.well-known/jwks.json`.
+    pub keys: Vec<JwtKeyConfig>,
+    /// `kid` of the key new tokens are signed with; defaults to the first
+    /// of `keys`.
+    pub signing_kid: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct JwtKeyConfig {
+    pub kid: String,
+    pub algorithm: JwtAlgorithm,
+    /// PKCS#8 PEM private key (PKCS#1 is also accepted for RS256).
+    pub private_key_file: PathBuf,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum JwtAlgorithm {
+    #[serde(rename = "RS256")]
+    Rs256,
+    #[serde(rename = "ES256")]
+    Es256,
+    #[serde(rename = "EdDSA")]
+    EdDsa,
+}
+
+impl From<JwtAlgorithm> for jsonwebtoken::Algorithm {
+    fn from(algorithm: JwtAlgorithm) -> Self {
+        match algorithm {
+            JwtAlgorithm::Rs256 => Self::RS256,
+            JwtAlgorithm::Es256 => Self::ES256,
+            JwtAlgorithm::EdDsa => Self::EdDSA,
+        }
+    }
 }
 
 impl Default for JwtConfig {
     fn default() -> Self {
         Self {
-            secret: "change-me-in-production".to_string(),
+            secret: DEFAULT_SECRET.to_string(),
             expires_in_seconds: 900,
+            refresh_expires_in_seconds: 30 * 24 * 3600,
+            issuer: "axum-template".to_string(),
+            audience: vec!["axum-template".to_string()],
+            leeway_seconds: 30,
+            version_cache_seconds: 5,
+            keys: Vec::new(),
+            signing_kid: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, rename_all = "kebab-case")]
+pub struct MfaConfig {
+    /// Issuer shown next to the account in authenticator apps.
+    pub issuer: String,
+    /// How long a login may wait for its second factor.
+    pub challenge_ttl_seconds: u64,
+    /// Wrong codes allowed per challenge before the login must restart.
+    pub max_attempts: u32,
+    /// Recovery codes issued at enrollment.
+    pub recovery_codes: usize,
+}
+
+impl Default for MfaConfig {
+    fn default() -> Self {
+        Self {
+            issuer: "axum-template".to_string(),
+            challenge_ttl_seconds: 300,
+            max_attempts: 5,
+            recovery_codes: 10,
+        }
+    }
+}
+
+/// Failed-login limits, tracked separately per username and per client IP.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, rename_all = "kebab-case")]
+pub struct ThrottleConfig {
+    pub user: ThrottleLimits,
+    pub ip: ThrottleLimits,
+    /// Failures older than this are forgotten.
+    pub reset_after_seconds: u64,
+}
+
+impl Default for ThrottleConfig {
+    fn default() -> Self {
+        Self {
+            user: ThrottleLimits::default(),
+            ip: ThrottleLimits {
+                free_attempts: 20,
+                lockout_threshold: 100,
+                ..ThrottleLimits::default()
+            },
+            reset_after_seconds: 3600,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, rename_all = "kebab-case")]
+pub struct ThrottleLimits {
+    /// Failures allowed before any delay.
+    pub free_attempts: u32,
+    /// Delay after the first failure beyond `free-attempts`, doubling with
+    /// each further one.
+    pub base_delay_seconds: u64,
+    pub max_delay_seconds: u64,
+    /// Failures that lock the username or IP out entirely.
+    pub lockout_threshold: u32,
+    pub lockout_seconds: u64,
+}
+
+impl Default for ThrottleLimits {
+    fn default() -> Self {
+        Self {
+            free_attempts: 3,
+            base_delay_seconds: 1,
+            max_delay_seconds: 300,
+            lockout_threshold: 10,
+            lockout_seconds: 900,
+        }
+    }
+}
+
+/// Argon2 parameters for new password hashes. Hashes made with other
+/// parameters are upgraded at the user's next login.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, rename_all = "kebab-case")]
+pub struct PasswordConfig {
+    pub algorithm: PasswordAlgorithm,
+    /// Memory cost in KiB.
+    pub m_cost: u32,
+    /// Number of passes.
+    pub t_cost: u32,
+    /// Degree of parallelism.
+    pub p_cost: u32,
+    /// File holding a secret mixed into every hash and kept out of the
+    /// database. Losing it invalidates every password hashed with it.
+    pub pepper_file: Option<PathBuf>,
+    /// Hashes computed at once; defaults to the number of CPUs.
+    pub max_concurrent: Option<usize>,
+    /// How long a login waits for a free hashing slot before it is refused
+    /// with 503.
+    pub queue_timeout_ms: u64,
+}
+
+impl Default for PasswordConfig {
+    fn default() -> Self {
+        Self {
+            algorithm: PasswordAlgorithm::Argon2id,
+            m_cost: argon2::Params::DEFAULT_M_COST,
+            t_cost: argon2::Params::DEFAULT_T_COST,
+            p_cost: argon2::Params::DEFAULT_P_COST,
+            pepper_file: None,
+            max_concurrent: None,
+            queue_timeout_ms: 1000,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum PasswordAlgorithm {
+    Argon2d,
+    Argon2i,
+    Argon2id,
+}
+
+impl From<PasswordAlgorithm> for argon2::Algorithm {
+    fn from(algorithm: PasswordAlgorithm) -> Self {
+        match algorithm {
+            PasswordAlgorithm::Argon2d => Self::Argon2d,
+            PasswordAlgorithm::Argon2i => Self::Argon2i,
+            PasswordAlgorithm::Argon2id => Self::Argon2id,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, rename_all = "kebab-case")]
+pub struct CorsConfig {
+    /// Allowed origins. Entries containing `*` are patterns, e.g.
+    /// `https://*.example.com` or `http://localhost:*`; a lone `*` allows any
+    /// origin and cannot be combined with `allow-credentials`.
+    ///
+    /// Unset means the default for [`AppEnv`]: local dev servers in
+    /// development, same-origin only in production.
+    pub allow_origins: Option<Vec<String>>,
+    pub allow_methods: Vec<String>,
+    pub allow_headers: Vec<String>,
+    pub expose_headers: Vec<String>,
+    /// Let browsers send cookies cross-origin, needed for session auth.
+    pub allow_credentials: bool,
+    pub max_age_seconds: u64,
+}
+
+impl CorsConfig {
+    pub fn origins(&self, env: AppEnv) -> Vec<String> {
+        if let Some(origins) = &self.allow_origins {
+            return origins.clone();
+        }
+        match env {
+            AppEnv::Development => vec![
+                "http://localhost:*".to_string(),
+                "http://127.0.0.1:*".to_string(),
+            ],
+            AppEnv::Production => Vec::new(),
+        }
+    }
+}
+
+impl Default for CorsConfig {
+    fn default() -> Self {
+        Self {
+            allow_origins: None,
+            allow_methods: ["GET", "POST", "PUT", "PATCH", "DELETE"]
+                .map(String::from)
+                .to_vec(),
+            allow_headers: ["authorization", "content-type"].map(String::from).to_vec(),
+            expose_headers: Vec::new(),
+            allow_credentials: true,
+            max_age_seconds: 3600,
         }
     }
 }
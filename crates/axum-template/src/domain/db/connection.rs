@@ -1,17 +1,84 @@
+use std::borrow::Cow;
+
 use toasty::Db;
 
 use crate::{domain::model, error::Result};
 
 pub async fn init_db(url: &str) -> Result<Db> {
-    let db = Db::builder()
-        .models(toasty::models!(
-            model::User,
-            model::Role,
-            model::UserRole,
-            model::RefreshToken,
-            model::Session,
-        ))
-        .connect(url)
-        .await?;
+    let url = normalize_url(url);
+    let mut builder = Db::builder();
+    builder.models(toasty::models!(
+        model::User,
+        model::Role,
+        model::UserRole,
+        model::RefreshToken,
+        model::Session,
+        model::RecoveryCode,
+        model::MfaChallenge,
+        model::LoginThrottle,
+    ));
+
+    #[cfg(feature = "sqlite")]
+    let db = if is_sqlite(&url) {
+        builder
+            .build(super::sqlite::SqliteDriver::new(&url)?)
+            .await?
+    } else {
+        builder.connect(&url).await?
+    };
+    #[cfg(not(feature = "sqlite"))]
+    let db = builder.connect(&url).await?;
+
+    // An in-memory database lives only as long as this process, so there is
+    // nothing for migrations to run against; create the schema directly.
+    if is_sqlite_memory(&url) {
+        db.push_schema().await?;
+    }
+
     Ok(db)
 }
+
+/// Round-trip a trivial query to check the database is reachable.
+pub async fn ping(db: &Db) -> Result<()> {
+    let mut db = db.clone();
+    model::User::filter_by_id(0).first().exec(&mut db).await?;
+    Ok(())
+}
+
+pub fn is_sqlite(url: &str) -> bool {
+    url.starts_with("sqlite:")
+}
+
+pub fn is_sqlite_memory(url: &str) -> bool {
+    url == "sqlite::memory:"
+}
+
+/// Accept the common `sqlite://file.db` spelling for a relative path, which
+/// would otherwise parse `file.db` as the URL host.
+pub fn normalize_url(url: &str) -> Cow<'_, str> {
+    match url.strip_prefix("sqlite://") {
+        Some(":memory:") => Cow::Borrowed("sqlite::memory:"),
+        Some(path) if !path.starts_with('/') => Cow::Owned(format!("sqlite:{path}")),
+        _ => Cow::Borrowed(url),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_normalize_url() {
+        assert_eq!(normalize_url("sqlite::memory:"), "sqlite::memory:");
+        assert_eq!(normalize_url("sqlite://:memory:"), "sqlite::memory:");
+        assert_eq!(normalize_url("sqlite://file.db"), "sqlite:file.db");
+        assert_eq!(
+            normalize_url("sqlite:///tmp/file.db"),
+            "sqlite:///tmp/file.db"
+        );
+        assert_eq!(
+            normalize_url("postgres://postgres:@localhost:5432/db"),
+            "postgres://postgres:@localhost:5432/db"
+        );
+    }
+}
@@ -0,0 +1,21 @@
+use toasty::{Db, Executor, stmt::IntoStatement};
+use toasty_core::{driver::Rows, stmt::Value};
+
+use crate::error::Result;
+
+/// Execute a query-based update and return how many rows it changed.
+///
+/// With the expected current value in the filter this is an atomic
+/// compare-and-set: of two concurrent callers only one sees `1`.
+pub async fn exec_count(db: &mut Db, update: impl IntoStatement<Returning = ()>) -> Result<usize> {
+    let response = db
+        .exec_untyped(update.into_statement().into_untyped())
+        .await?;
+    Ok(match response.values {
+        Rows::Count(count) => count as usize,
+        rows => match rows.collect_as_value().await? {
+            Value::List(changed) => changed.len(),
+            _ => 0,
+        },
+    })
+}
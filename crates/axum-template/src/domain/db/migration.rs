@@ -0,0 +1,51 @@
+use std::collections::HashSet;
+
+use toasty::Db;
+use toasty_cli::HistoryFile;
+
+use super::{is_sqlite, is_sqlite_memory};
+use crate::error::{ErrorKind, Result};
+
+/// Migration history compiled into this build, one per backend.
+const POSTGRES_HISTORY: &str =
+    include_str!(concat!(env!("CARGO_MANIFEST_DIR"), "/toasty/history.toml"));
+const SQLITE_HISTORY: &str = include_str!(concat!(
+    env!("CARGO_MANIFEST_DIR"),
+    "/toasty/sqlite/history.toml"
+));
+
+/// Names of the migrations shipped with this build that the database has
+/// not applied yet.
+///
+/// In-memory SQLite databases get their schema pushed on connect, so they
+/// never have pending migrations.
+pub async fn pending_migrations(db: &Db) -> Result<Vec<String>> {
+    let url = db.driver().url();
+    if is_sqlite_memory(&url) {
+        return Ok(Vec::new());
+    }
+
+    let history = if is_sqlite(&url) {
+        SQLITE_HISTORY
+    } else {
+        POSTGRES_HISTORY
+    };
+    let history: HistoryFile = history
+        .parse()
+        .map_err(|e: anyhow::Error| ErrorKind::Internal.dyn_err(e.into()))?;
+
+    let mut conn = db.driver().connect().await?;
+    let applied: HashSet<u64> = conn
+        .applied_migrations()
+        .await?
+        .iter()
+        .map(|m| m.id())
+        .collect();
+
+    Ok(history
+        .migrations()
+        .iter()
+        .filter(|m| !applied.contains(&m.id))
+        .map(|m| m.name.clone())
+        .collect())
+}
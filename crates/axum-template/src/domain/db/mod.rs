@@ -1,5 +1,11 @@
 pub mod connection;
+pub mod exec;
+pub mod migration;
+#[cfg(feature = "sqlite")]
+pub mod sqlite;
 pub mod types;
 
-pub use connection::init_db;
+pub use connection::{init_db, is_sqlite, is_sqlite_memory, ping};
+pub use exec::exec_count;
+pub use migration::pending_migrations;
 pub use types::*;
@@ -0,0 +1,109 @@
+//! SQLite driver wrapper.
+//!
+//! toasty serializes every 64-bit integer as `BIGINT`, but SQLite only
+//! accepts `AUTOINCREMENT` on an `INTEGER PRIMARY KEY` column. The wrapper
+//! rewrites that column definition in generated DDL and otherwise defers to
+//! [`toasty_driver_sqlite::Sqlite`].
+
+use std::{borrow::Cow, sync::Arc};
+
+use async_trait::async_trait;
+use toasty_core::{
+    driver::{Capability, Connection, Driver, ExecResponse, operation::Operation},
+    schema::{
+        Schema,
+        db::{self, AppliedMigration, Migration, RenameHints, SchemaDiff},
+    },
+};
+use toasty_driver_sqlite::Sqlite;
+
+const BIGINT_AUTOINCREMENT: &str = "BIGINT NOT NULL PRIMARY KEY AUTOINCREMENT";
+const INTEGER_AUTOINCREMENT: &str = "INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT";
+
+#[derive(Debug)]
+pub struct SqliteDriver {
+    inner: Sqlite,
+}
+
+impl SqliteDriver {
+    pub fn new(url: &str) -> toasty::Result<Self> {
+        Ok(Self {
+            inner: Sqlite::new(url)?,
+        })
+    }
+}
+
+#[async_trait]
+impl Driver for SqliteDriver {
+    fn url(&self) -> Cow<'_, str> {
+        self.inner.url()
+    }
+
+    fn capability(&self) -> &'static Capability {
+        self.inner.capability()
+    }
+
+    async fn connect(&self) -> toasty::Result<Box<dyn Connection>> {
+        Ok(Box::new(SqliteConnection {
+            inner: self.inner.connect().await?,
+        }))
+    }
+
+    fn max_connections(&self) -> Option<usize> {
+        self.inner.max_connections()
+    }
+
+    fn generate_migration(&self, schema_diff: &SchemaDiff<'_>) -> Migration {
+        fix_migration(self.inner.generate_migration(schema_diff))
+    }
+
+    async fn reset_db(&self) -> toasty::Result<()> {
+        self.inner.reset_db().await
+    }
+}
+
+#[derive(Debug)]
+struct SqliteConnection {
+    inner: Box<dyn Connection>,
+}
+
+#[async_trait]
+impl Connection for SqliteConnection {
+    async fn exec(
+        &mut self,
+        schema: &Arc<Schema>,
+        plan: Operation,
+    ) -> toasty::Result<ExecResponse> {
+        self.inner.exec(schema, plan).await
+    }
+
+    /// Create the schema through the same (fixed) DDL a migration would use.
+    async fn push_schema(&mut self, schema: &Schema) -> toasty::Result<()> {
+        let empty = db::Schema::default();
+        let hints = RenameHints::default();
+        let diff = SchemaDiff::from(&empty, &schema.db, &hints);
+        let migration = Sqlite::in_memory().generate_migration(&diff);
+
+        self.inner
+            .apply_migration(0, "push_schema", &fix_migration(migration))
+            .await
+    }
+
+    async fn applied_migrations(&mut self) -> toasty::Result<Vec<AppliedMigration>> {
+        self.inner.applied_migrations().await
+    }
+
+    async fn apply_migration(
+        &mut self,
+        id: u64,
+        name: &str,
+        migration: &Migration,
+    ) -> toasty::Result<()> {
+        self.inner.apply_migration(id, name, migration).await
+    }
+}
+
+fn fix_migration(migration: Migration) -> Migration {
+    let Migration::Sql(sql) = migration;
+    Migration::new_sql(sql.replace(BIGINT_AUTOINCREMENT, INTEGER_AUTOINCREMENT))
+}
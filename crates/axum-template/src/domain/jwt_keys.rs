@@ -0,0 +1,290 @@
+//! JWT signing and verification keys.
+//!
+//! Without configured keys, tokens are HS256-signed with the shared secret.
+//! With asymmetric keys, one key signs and every key verifies, selected by
+//! the token's `kid`; their public halves are published as a JWK set so other
+//! services can verify tokens without holding any secret.
+
+use std::{collections::HashMap, fmt, sync::Arc};
+
+use base64::{Engine, engine::general_purpose::URL_SAFE_NO_PAD};
+use jsonwebtoken::{
+    Algorithm, DecodingKey, EncodingKey, Header,
+    jwk::{
+        AlgorithmParameters, CommonParameters, EllipticCurve, EllipticCurveKeyParameters,
+        EllipticCurveKeyType, Jwk, JwkSet, KeyAlgorithm, OctetKeyPairParameters, OctetKeyPairType,
+        PublicKeyUse, RSAKeyParameters, RSAKeyType,
+    },
+};
+use ring::{
+    rand::SystemRandom,
+    rsa::PublicKeyComponents,
+    signature::{self, KeyPair},
+};
+
+use crate::{
+    bail,
+    error::{ErrorKind, OptionAppExt, Result, ResultExt},
+};
+
+/// One key: the signing half and the matching verifier.
+#[derive(Clone)]
+pub struct JwtKey {
+    kid: Option<String>,
+    algorithm: Algorithm,
+    encoding: EncodingKey,
+    decoding: DecodingKey,
+    jwk: Option<Jwk>,
+}
+
+impl fmt::Debug for JwtKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("JwtKey")
+            .field("kid", &self.kid)
+            .field("algorithm", &self.algorithm)
+            .finish()
+    }
+}
+
+impl JwtKey {
+    /// Load a PKCS#8 private key (or a PKCS#1 RSA key) for `algorithm`.
+    pub fn from_pem(kid: String, algorithm: Algorithm, pem: &[u8]) -> Result<Self> {
+        let invalid = || format!("Invalid {algorithm:?} private key for kid {kid:?}");
+        let der = pem::parse(pem).err_kind_msg(ErrorKind::Config, invalid())?;
+        let der = der.contents();
+
+        let (encoding, params) = match algorithm {
+            Algorithm::RS256 => {
+                let pair = signature::RsaKeyPair::from_pkcs8(der)
+                    .or_else(|_| signature::RsaKeyPair::from_der(der))
+                    .err_kind_msg(ErrorKind::Config, invalid())?;
+                let public = PublicKeyComponents::<Vec<u8>>::from(pair.public());
+                (
+                    EncodingKey::from_rsa_pem(pem).err_kind_msg(ErrorKind::Config, invalid())?,
+                    AlgorithmParameters::RSA(RSAKeyParameters {
+                        key_type: RSAKeyType::RSA,
+                        n: URL_SAFE_NO_PAD.encode(&public.n),
+                        e: URL_SAFE_NO_PAD.encode(&public.e),
+                    }),
+                )
+            }
+            Algorithm::ES256 => {
+                let pair = signature::EcdsaKeyPair::from_pkcs8(
+                    &signature::ECDSA_P256_SHA256_FIXED_SIGNING,
+                    der,
+                    &SystemRandom::new(),
+                )
+                .err_kind_msg(ErrorKind::Config, invalid())?;
+                // Uncompressed point: 0x04 || x || y.
+                let point = pair.public_key().as_ref();
+                (
+                    EncodingKey::from_ec_der(der),
+                    AlgorithmParameters::EllipticCurve(EllipticCurveKeyParameters {
+                        key_type: EllipticCurveKeyType::EC,
+                        curve: EllipticCurve::P256,
+                        x: URL_SAFE_NO_PAD.encode(&point[1..33]),
+                        y: URL_SAFE_NO_PAD.encode(&point[33..]),
+                    }),
+                )
+            }
+            Algorithm::EdDSA => {
+                let pair = signature::Ed25519KeyPair::from_pkcs8_maybe_unchecked(der)
+                    .err_kind_msg(ErrorKind::Config, invalid())?;
+                (
+                    EncodingKey::from_ed_der(der),
+                    AlgorithmParameters::OctetKeyPair(OctetKeyPairParameters {
+                        key_type: OctetKeyPairType::OctetKeyPair,
+                        curve: EllipticCurve::Ed25519,
+                        x: URL_SAFE_NO_PAD.encode(pair.public_key().as_ref()),
+                    }),
+                )
+            }
+            _ => bail!(
+                ErrorKind::Config,
+                "Unsupported JWT key algorithm {algorithm:?}"
+            ),
+        };
+
+        let jwk = Jwk {
+            common: CommonParameters {
+                public_key_use: Some(PublicKeyUse::Signature),
+                key_algorithm: Some(key_algorithm(algorithm)),
+                key_id: Some(kid.clone()),
+                ..Default::default()
+            },
+            algorithm: params,
+        };
+        let decoding = DecodingKey::from_jwk(&jwk).err_kind_msg(ErrorKind::Config, invalid())?;
+
+        Ok(Self {
+            kid: Some(kid),
+            algorithm,
+            encoding,
+            decoding,
+            jwk: Some(jwk),
+        })
+    }
+
+    pub fn algorithm(&self) -> Algorithm {
+        self.algorithm
+    }
+
+    pub fn decoding(&self) -> &DecodingKey {
+        &self.decoding
+    }
+}
+
+/// The signing key plus every key accepted for verification.
+#[derive(Debug, Clone)]
+pub struct JwtKeys {
+    signing: Arc<JwtKey>,
+    verifying: Arc<HashMap<String, JwtKey>>,
+    jwks: Arc<JwkSet>,
+}
+
+impl JwtKeys {
+    /// HS256 with a shared secret; tokens carry no `kid` and nothing is
+    /// published.
+    pub fn hmac(secret: &str) -> Self {
+        let key = JwtKey {
+            kid: None,
+            algorithm: Algorithm::HS256,
+            encoding: EncodingKey::from_secret(secret.as_bytes()),
+            decoding: DecodingKey::from_secret(secret.as_bytes()),
+            jwk: None,
+        };
+        Self {
+            signing: Arc::new(key),
+            verifying: Default::default(),
+            jwks: Arc::new(JwkSet { keys: Vec::new() }),
+        }
+    }
+
+    /// Asymmetric keys, signing with `signing_kid` or else the first key.
+    pub fn new(keys: Vec<JwtKey>, signing_kid: Option<&str>) -> Result<Self> {
+        let signing = match signing_kid {
+            Some(kid) => keys
+                .iter()
+                .find(|k| k.kid.as_deref() == Some(kid))
+                .ok_or_err_msg(ErrorKind::Config, format!("No JWT key with kid {kid:?}"))?,
+            None => keys
+                .first()
+                .ok_or_err_msg(ErrorKind::Config, "No JWT keys configured")?,
+        }
+        .clone();
+
+        let jwks = JwkSet {
+            keys: keys.iter().filter_map(|k| k.jwk.clone()).collect(),
+        };
+        let mut verifying = HashMap::new();
+        for key in keys {
+            let kid = key.kid.clone().unwrap_or_default();
+            if verifying.insert(kid.clone(), key).is_some() {
+                bail!(ErrorKind::Config, "Duplicate JWT kid {kid:?}");
+            }
+        }
+
+        Ok(Self {
+            signing: Arc::new(signing),
+            verifying: Arc::new(verifying),
+            jwks: Arc::new(jwks),
+        })
+    }
+
+    /// Header and key for signing a new token.
+    pub fn signing(&self) -> (Header, &EncodingKey) {
+        let header = Header {
+            kid: self.signing.kid.clone(),
+            ..Header::new(self.signing.algorithm)
+        };
+        (header, &self.signing.encoding)
+    }
+
+    /// The key a token with header `kid` must verify against. The algorithm
+    /// comes from the key, never from the token.
+    pub fn verifying(&self, kid: Option<&str>) -> Option<&JwtKey> {
+        match kid {
+            Some(kid) => self.verifying.get(kid),
+            None if self.signing.kid.is_none() => Some(&self.signing),
+            None => None,
+        }
+    }
+
+    /// Public keys, for `/.well-known/jwks.json`.
+    pub fn jwks(&self) -> &JwkSet {
+        &self.jwks
+    }
+}
+
+/// Generate a PKCS#8 PEM private key for `algorithm`.
+///
+/// Only ES256 and EdDSA keys can be generated here; create RS256 keys with
+/// e.g. `openssl genpkey -algorithm RSA -pkeyopt rsa_keygen_bits:3072`.
+pub fn generate_pem(algorithm: Algorithm) -> Result<String> {
+    let rng = SystemRandom::new();
+    let der = match algorithm {
+        Algorithm::ES256 => signature::EcdsaKeyPair::generate_pkcs8(
+            &signature::ECDSA_P256_SHA256_FIXED_SIGNING,
+            &rng,
+        ),
+        Algorithm::EdDSA => signature::Ed25519KeyPair::generate_pkcs8(&rng),
+        _ => bail!(
+            ErrorKind::InvalidParameter,
+            "Cannot generate {algorithm:?} keys; use ES256 or EdDSA, or create one with openssl"
+        ),
+    }
+    .err_kind_msg(ErrorKind::Internal, "Key generation failed")?;
+    Ok(pem::encode(&pem::Pem::new("PRIVATE KEY", der.as_ref())))
+}
+
+fn key_algorithm(algorithm: Algorithm) -> KeyAlgorithm {
+    match algorithm {
+        Algorithm::RS256 => KeyAlgorithm::RS256,
+        Algorithm::ES256 => KeyAlgorithm::ES256,
+        Algorithm::EdDSA => KeyAlgorithm::EdDSA,
+        _ => unreachable!("only asymmetric algorithms are loaded"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use jsonwebtoken::{Validation, decode, encode};
+    use serde_json::{Value, json};
+
+    use super::*;
+
+    #[test]
+    fn test_generated_keys_sign_and_verify() {
+        for algorithm in [Algorithm::ES256, Algorithm::EdDSA] {
+            let pem = generate_pem(algorithm).unwrap();
+            let key = JwtKey::from_pem("k1".into(), algorithm, pem.as_bytes()).unwrap();
+            let keys = JwtKeys::new(vec![key], None).unwrap();
+
+            let (header, encoding) = keys.signing();
+            assert_eq!(header.kid.as_deref(), Some("k1"));
+            let claims = json!({ "sub": 1, "exp": u32::MAX });
+            let token = encode(&header, &claims, encoding).unwrap();
+
+            let key = keys.verifying(Some("k1")).unwrap();
+            let decoded = decode::<Value>(&token, key.decoding(), &Validation::new(algorithm));
+            assert!(decoded.is_ok(), "{algorithm:?}: {decoded:?}");
+            assert!(keys.verifying(None).is_none());
+            assert_eq!(keys.jwks().keys.len(), 1);
+        }
+    }
+
+    #[test]
+    fn test_signing_kid_selects_key() {
+        let keys = ["old", "new"]
+            .into_iter()
+            .map(|kid| {
+                let pem = generate_pem(Algorithm::EdDSA).unwrap();
+                JwtKey::from_pem(kid.into(), Algorithm::EdDSA, pem.as_bytes()).unwrap()
+            })
+            .collect();
+        let keys = JwtKeys::new(keys, Some("new")).unwrap();
+        assert_eq!(keys.signing().0.kid.as_deref(), Some("new"));
+        assert!(keys.verifying(Some("old")).is_some());
+        assert_eq!(keys.jwks().keys.len(), 2);
+    }
+}
@@ -1,7 +1,11 @@
 use toasty::Db;
 
+use crate::{error::Result, util::password};
+
 pub mod db;
+pub mod jwt_keys;
 pub mod model;
+pub mod opaque;
 pub mod service;
 
 pub use service::*;
@@ -13,21 +17,43 @@ pub struct Services {
     pub auth: service::AuthService,
     pub session: service::SessionService,
     pub token: service::TokenService,
+    pub mfa: service::MfaService,
+    pub throttle: service::ThrottleService,
 }
 
 impl Services {
     pub fn new(
         db: Db,
-        jwt_secret: &str,
-        jwt_expires_in_seconds: u64,
-        session_ttl_hours: u64,
+        token: service::TokenService,
+        session_policy: service::SessionPolicy,
+        mfa_policy: service::MfaPolicy,
+        throttle_policy: service::ThrottlePolicy,
+        hasher: password::Hasher,
     ) -> Self {
+        let throttle = service::ThrottleService::new(db.clone(), throttle_policy);
+        let session = service::SessionService::new(db.clone(), session_policy, token.clone());
         Self {
-            user: service::UserService::new(db.clone()),
-            role: service::RoleService::new(db.clone()),
-            auth: service::AuthService::new(db.clone()),
-            session: service::SessionService::new(db.clone(), session_ttl_hours),
-            token: service::TokenService::new(db, jwt_secret.to_owned(), jwt_expires_in_seconds),
+            user: service::UserService::new(
+                db.clone(),
+                hasher.clone(),
+                token.clone(),
+                session.clone(),
+            ),
+            role: service::RoleService::new(db.clone(), token.clone()),
+            auth: service::AuthService::new(db.clone(), throttle.clone(), hasher),
+            session,
+            token,
+            mfa: service::MfaService::new(db, mfa_policy, throttle.clone()),
+            throttle,
         }
     }
+
+    /// Delete expired sessions, refresh tokens and MFA challenges, and
+    /// forgotten login failure counts.
+    pub async fn purge_expired(&self) -> Result<()> {
+        self.session.purge_expired().await?;
+        self.token.purge_expired_refresh_tokens().await?;
+        self.mfa.purge_expired().await?;
+        self.throttle.purge_stale().await
+    }
 }
@@ -0,0 +1,22 @@
+use toasty::Model;
+
+/// Failed login attempts for one username or client IP.
+#[derive(Debug, Clone, Model)]
+pub struct LoginThrottle {
+    #[key]
+    #[auto]
+    pub id: i64,
+
+    /// `user:<username>` or `ip:<address>`.
+    #[unique]
+    pub key: String,
+
+    /// Consecutive failures; reset by a successful login or after a quiet
+    /// period.
+    pub failures: i64,
+
+    /// Logins are refused until then.
+    pub blocked_until: Option<jiff::Timestamp>,
+
+    pub last_failure_at: jiff::Timestamp,
+}
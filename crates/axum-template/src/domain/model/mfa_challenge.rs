@@ -0,0 +1,25 @@
+use toasty::Model;
+
+/// A login that passed the password check and awaits its second factor.
+#[derive(Debug, Clone, Model)]
+pub struct MfaChallenge {
+    #[key]
+    #[auto]
+    pub id: i64,
+
+    #[index]
+    pub user_id: i64,
+
+    /// SHA-256 of the challenge token handed to the client.
+    #[unique]
+    pub token_hash: String,
+
+    /// Wrong codes entered so far.
+    #[default(0)]
+    pub attempts: i64,
+
+    pub expires_at: jiff::Timestamp,
+
+    #[auto]
+    pub created_at: jiff::Timestamp,
+}
@@ -1,11 +1,17 @@
+pub mod login_throttle;
+pub mod mfa_challenge;
 pub mod permission;
+pub mod recovery_code;
 pub mod refresh_token;
 pub mod role;
 pub mod session;
 pub mod user;
 pub mod user_role;
 
+pub use login_throttle::LoginThrottle;
+pub use mfa_challenge::MfaChallenge;
 pub use permission::Perm;
+pub use recovery_code::RecoveryCode;
 pub use refresh_token::RefreshToken;
 pub use role::{DefaultRole, Role};
 pub use session::Session;
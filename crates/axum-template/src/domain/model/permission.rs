@@ -2,53 +2,74 @@ use serde::{Deserialize, Serialize};
 use strum::{Display, EnumIter, EnumString, IntoEnumIterator, IntoStaticStr};
 use utoipa::ToSchema;
 
-#[derive(
-    Debug,
-    Clone,
-    Copy,
-    PartialEq,
-    Eq,
-    PartialOrd,
-    Ord,
-    Hash,
-    EnumString,
-    EnumIter,
-    IntoStaticStr,
-    Serialize,
-    Deserialize,
-    Display,
-    ToSchema,
-)]
-pub enum Perm {
-    #[serde(rename = "*")]
-    #[strum(serialize = "*")]
-    All,
-
-    #[serde(rename = "user:read")]
-    #[strum(serialize = "user:read")]
-    UserRead,
-    #[serde(rename = "user:write")]
-    #[strum(serialize = "user:write")]
-    UserWrite,
-    #[serde(rename = "user:delete")]
-    #[strum(serialize = "user:delete")]
-    UserDelete,
-    #[serde(rename = "user:*")]
-    #[strum(serialize = "user:*")]
-    UserAll,
-
-    #[serde(rename = "role:read")]
-    #[strum(serialize = "role:read")]
-    RoleRead,
-    #[serde(rename = "role:write")]
-    #[strum(serialize = "role:write")]
-    RoleWrite,
-    #[serde(rename = "role:delete")]
-    #[strum(serialize = "role:delete")]
-    RoleDelete,
-    #[serde(rename = "role:*")]
-    #[strum(serialize = "role:*")]
-    RoleAll,
+/// Type-level marker for a [`Perm`], used to parameterise `RequirePerm`.
+pub trait PermMarker: Send + Sync + 'static {
+    const PERM: Perm;
+}
+
+/// Defines [`Perm`] and, for each variant, a [`PermMarker`] of the same name
+/// in [`perm`], so the two cannot drift apart.
+macro_rules! perms {
+    ( $( $name:ident = $code:tt, $description:tt; )* ) => {
+        #[derive(
+            Debug,
+            Clone,
+            Copy,
+            PartialEq,
+            Eq,
+            PartialOrd,
+            Ord,
+            Hash,
+            EnumString,
+            EnumIter,
+            IntoStaticStr,
+            Serialize,
+            Deserialize,
+            Display,
+            ToSchema,
+        )]
+        pub enum Perm {
+            $(
+                #[serde(rename = $code)]
+                #[strum(serialize = $code)]
+                $name,
+            )*
+        }
+
+        impl Perm {
+            pub const fn description(&self) -> &'static str {
+                match self {
+                    $( Self::$name => $description, )*
+                }
+            }
+        }
+
+        /// Markers for every [`Perm`] variant, e.g. `RequirePerm<perm::UserRead>`.
+        pub mod perm {
+            $(
+                #[derive(Debug)]
+                pub struct $name;
+
+                impl super::PermMarker for $name {
+                    const PERM: super::Perm = super::Perm::$name;
+                }
+            )*
+        }
+    };
+}
+
+perms! {
+    All = "*", "超级用户";
+
+    UserRead = "user:read", "查看用户信息";
+    UserWrite = "user:write", "创建/修改用户";
+    UserDelete = "user:delete", "删除用户";
+    UserAll = "user:*", "用户管理所有权限";
+
+    RoleRead = "role:read", "查看角色信息";
+    RoleWrite = "role:write", "创建/修改角色";
+    RoleDelete = "role:delete", "删除角色";
+    RoleAll = "role:*", "角色管理所有权限";
 }
 
 pub fn perms_match(self_code: &str, target_code: &str) -> bool {
@@ -75,20 +96,6 @@ impl Perm {
         self.into()
     }
 
-    pub const fn description(&self) -> &'static str {
-        match self {
-            Self::All => "超级用户",
-            Self::UserRead => "查看用户信息",
-            Self::UserWrite => "创建/修改用户",
-            Self::UserDelete => "删除用户",
-            Self::UserAll => "用户管理所有权限",
-            Self::RoleRead => "查看角色信息",
-            Self::RoleWrite => "创建/修改角色",
-            Self::RoleDelete => "删除角色",
-            Self::RoleAll => "角色管理所有权限",
-        }
-    }
-
     pub fn matches(&self, target_code: &str) -> bool {
         perms_match(self.code(), target_code)
     }
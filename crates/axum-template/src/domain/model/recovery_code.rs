@@ -0,0 +1,22 @@
+use toasty::Model;
+
+/// A one-time code that stands in for a TOTP code when the authenticator is
+/// lost.
+#[derive(Debug, Clone, Model)]
+pub struct RecoveryCode {
+    #[key]
+    #[auto]
+    pub id: i64,
+
+    #[index]
+    pub user_id: i64,
+
+    /// SHA-256 of the code shown to the user at enrollment.
+    #[unique]
+    pub code_hash: String,
+
+    pub used_at: Option<jiff::Timestamp>,
+
+    #[auto]
+    pub created_at: jiff::Timestamp,
+}
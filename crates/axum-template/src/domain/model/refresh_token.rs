@@ -8,11 +8,23 @@ pub struct RefreshToken {
 
     pub user_id: i64,
 
+    /// SHA-256 of the token handed to the client.
     #[unique]
-    pub token: String,
+    pub token_hash: String,
 
     pub expires_at: jiff::Timestamp,
 
+    /// Shared by every token descended from the same login.
+    #[index]
+    pub family_id: String,
+
+    /// The token this one was rotated from; `None` for the first of a family.
+    pub parent_id: Option<i64>,
+
+    /// Set once the token has been rotated, or when its family is revoked.
+    #[default(false)]
+    pub revoked: bool,
+
     #[auto]
     pub created_at: jiff::Timestamp,
 }
@@ -56,6 +56,10 @@ pub struct Role {
 
     pub permissions: String,
 
+    /// Members must use a second factor to log in.
+    #[default(false)]
+    pub require_mfa: bool,
+
     #[auto]
     pub created_at: jiff::Timestamp,
 
@@ -1,5 +1,7 @@
 use toasty::Model;
 
+use crate::util::token;
+
 #[derive(Debug, Clone, Model)]
 pub struct Session {
     #[key]
@@ -8,11 +10,33 @@ pub struct Session {
 
     pub user_id: i64,
 
+    /// SHA-256 of the session id; the raw id only ever lives in the cookie.
     #[unique]
-    pub session_id: String,
+    pub session_id_hash: String,
 
     pub expires_at: jiff::Timestamp,
 
+    /// Synchronizer CSRF token; empty until first issued.
+    #[default(String::new())]
+    pub csrf_token: String,
+
     #[auto]
     pub created_at: jiff::Timestamp,
+
+    /// Last request seen on this session, updated at most once per touch
+    /// interval.
+    pub last_seen_at: jiff::Timestamp,
+
+    /// `User-Agent` of the client that logged in.
+    pub user_agent: Option<String>,
+
+    /// Client IP at login.
+    pub ip: Option<String>,
+}
+
+impl Session {
+    /// Whether this is the session for the raw `session_id`.
+    pub fn matches(&self, session_id: &str) -> bool {
+        self.session_id_hash == token::digest(session_id)
+    }
 }
@@ -13,6 +13,24 @@ pub struct User {
 
     pub password: String,
 
+    #[default(false)]
+    pub must_change_password: bool,
+
+    /// Embedded in access tokens as `ver`; bumping it revokes every
+    /// outstanding token.
+    #[default(0)]
+    pub token_version: i64,
+
+    /// Base32 TOTP secret; set at enrollment, before it is confirmed.
+    pub totp_secret: Option<String>,
+
+    /// Whether the TOTP secret has been confirmed and is required at login.
+    #[default(false)]
+    pub totp_enabled: bool,
+
+    /// Last TOTP time step accepted, so a code can't be replayed.
+    pub totp_last_step: Option<i64>,
+
     #[auto]
     pub created_at: jiff::Timestamp,
 
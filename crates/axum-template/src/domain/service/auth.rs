@@ -1,12 +1,13 @@
 use toasty::Db;
 
+use super::ThrottleService;
 use crate::{
     bail,
     domain::{
         db::Pk,
         model::{Perm, User, UserRole},
     },
-    error::{ErrorKind, Result},
+    error::{ErrorKind, OptionalExt, Result},
     util::password,
 };
 
@@ -24,11 +25,17 @@ impl AuthUser {
 #[derive(Debug, Clone)]
 pub struct AuthService {
     db: Db,
+    throttle: ThrottleService,
+    hasher: password::Hasher,
 }
 
 impl AuthService {
-    pub fn new(db: Db) -> Self {
-        Self { db }
+    pub fn new(db: Db, throttle: ThrottleService, hasher: password::Hasher) -> Self {
+        Self {
+            db,
+            throttle,
+            hasher,
+        }
     }
 
     fn db(&self) -> Db {
@@ -52,18 +59,51 @@ impl AuthService {
         Ok(perms)
     }
 
+    /// Check a password login from `ip`.
+    ///
+    /// Refused with `TooManyAttempts` or `AccountLocked` while the username
+    /// or IP is throttled, before the password is hashed; failures count
+    /// towards the throttle.
+    ///
+    /// An unknown username is checked against a dummy hash and fails exactly
+    /// like a wrong password, so neither timing nor the result reveals which
+    /// usernames exist. A password hashed with outdated parameters is
+    /// rehashed with the current ones.
     pub async fn authenticate(
         &self,
         username: &str,
         password_str: &str,
+        ip: Option<&str>,
     ) -> Result<Option<AuthUser>> {
+        self.throttle.check(username, ip).await?;
+
         let mut db = self.db();
-        let Some(user) = User::filter_by_username(username).get(&mut db).await.ok() else {
-            return Ok(None);
+        let user = User::filter_by_username(username)
+            .get(&mut db)
+            .await
+            .optional()?;
+        let verified = match &user {
+            Some(user) => self.hasher.verify(password_str, &user.password).await?,
+            None => self.hasher.verify_dummy(password_str).await?,
         };
+        let mut user = match user {
+            Some(user) if verified => user,
+            _ => {
+                self.throttle.record_failure(username, ip).await?;
+                return Ok(None);
+            }
+        };
+        // With a second factor the failures are only cleared once that is
+        // passed too, or logging in again would reset wrong codes.
+        if !user.totp_enabled {
+            self.throttle.record_success(username).await?;
+        }
 
-        if !password::verify(password_str, &user.password)? {
-            return Ok(None);
+        // Upgrade hashes made with older parameters while we have the
+        // password in hand.
+        if self.hasher.needs_rehash(&user.password) {
+            let hashed = self.hasher.hash(password_str).await?;
+            user.update().password(hashed).exec(&mut db).await?;
         }
 
         let permissions = self.get_user_permissions(user.id).await?;
@@ -88,4 +128,19 @@ impl AuthService {
         }
         Ok(())
     }
+
+    /// Reject unless the user holds every one of `perms`, so that nobody can
+    /// grant, or act on holders of, more than they have themselves.
+    pub async fn require_permissions(&self, user_id: Pk, perms: &[Perm]) -> Result<()> {
+        for &perm in perms {
+            if !self.check_permission(user_id, perm).await? {
+                bail!(
+                    ErrorKind::PermissionDenied,
+                    "Permission {} exceeds your own",
+                    perm.code()
+                );
+            }
+        }
+        Ok(())
+    }
 }
@@ -0,0 +1,442 @@
+use jiff::{SignedDuration, Timestamp};
+use toasty::Db;
+
+use crate::{
+    bail,
+    domain::{
+        ThrottleService,
+        db::{Pk, exec_count},
+        model::{MfaChallenge, RecoveryCode, User, UserRole},
+        opaque::TokenKind,
+    },
+    error::{ErrorKind, OptionalExt, Result},
+    util::{token, totp},
+};
+
+/// Second-factor rules.
+#[derive(Debug, Clone)]
+pub struct MfaPolicy {
+    /// Issuer shown in authenticator apps.
+    pub issuer: String,
+    pub challenge_ttl: SignedDuration,
+    /// Wrong codes allowed per challenge.
+    pub max_attempts: u32,
+    /// Recovery codes issued per enrollment.
+    pub recovery_codes: usize,
+}
+
+/// What the user needs to set up an authenticator, shown once.
+#[derive(Debug, Clone)]
+pub struct TotpEnrollment {
+    pub secret: String,
+    pub otpauth_uri: String,
+    pub recovery_codes: Vec<String>,
+}
+
+/// Issued instead of a session or tokens when a login needs a second factor.
+#[derive(Debug, Clone)]
+pub struct PendingMfa {
+    pub challenge_token: String,
+    /// The user has no confirmed authenticator yet but one of their roles
+    /// requires it; they must enroll before completing the login.
+    pub enrollment_required: bool,
+    pub expires_at: Timestamp,
+}
+
+#[derive(Debug, Clone)]
+pub struct MfaStatus {
+    pub enabled: bool,
+    pub required: bool,
+    pub recovery_codes_remaining: usize,
+}
+
+#[derive(Debug, Clone)]
+pub struct MfaService {
+    db: Db,
+    policy: MfaPolicy,
+    throttle: ThrottleService,
+}
+
+impl MfaService {
+    pub fn new(db: Db, policy: MfaPolicy, throttle: ThrottleService) -> Self {
+        Self {
+            db,
+            policy,
+            throttle,
+        }
+    }
+
+    fn db(&self) -> Db {
+        self.db.clone()
+    }
+
+    /// Whether any of the user's roles makes MFA mandatory.
+    pub async fn is_required(&self, user_id: Pk) -> Result<bool> {
+        let mut db = self.db();
+        let urs = UserRole::all()
+            .filter(UserRole::fields().user_id().eq(user_id))
+            .include(UserRole::fields().role())
+            .exec(&mut db)
+            .await?;
+        Ok(urs.iter().any(|ur| ur.role.get().require_mfa))
+    }
+
+    pub async fn status(&self, user_id: Pk) -> Result<MfaStatus> {
+        let mut db = self.db();
+        let user = User::get_by_id(&mut db, &user_id).await?;
+        let recovery_codes_remaining = if user.totp_enabled {
+            Self::unused_recovery_codes(&mut db, user_id).await?.len()
+        } else {
+            0
+        };
+        Ok(MfaStatus {
+            enabled: user.totp_enabled,
+            required: self.is_required(user_id).await?,
+            recovery_codes_remaining,
+        })
+    }
+
+    /// Start a second-factor challenge if `user` needs one to log in.
+    pub async fn challenge(&self, user: &User) -> Result<Option<PendingMfa>> {
+        let enrollment_required = !user.totp_enabled && self.is_required(user.id).await?;
+        if !user.totp_enabled && !enrollment_required {
+            return Ok(None);
+        }
+
+        let mut db = self.db();
+        let now = Timestamp::now();
+        let stale = MfaChallenge::all()
+            .filter(MfaChallenge::fields().user_id().eq(user.id))
+            .exec(&mut db)
+            .await?;
+        for c in stale.iter().filter(|c| c.expires_at <= now) {
+            MfaChallenge::filter_by_id(c.id)
+                .delete()
+                .exec(&mut db)
+                .await?;
+        }
+
+        let challenge_token = TokenKind::MfaChallenge.generate();
+        let expires_at = now + self.policy.challenge_ttl;
+        toasty::create!(MfaChallenge {
+            user_id: user.id,
+            token_hash: token::digest(&challenge_token),
+            expires_at,
+        })
+        .exec(&mut db)
+        .await?;
+        Ok(Some(PendingMfa {
+            challenge_token,
+            enrollment_required,
+            expires_at,
+        }))
+    }
+
+    /// Finish a challenged login with a TOTP or recovery code, returning the
+    /// user it was for.
+    ///
+    /// A user enrolling during the login confirms their authenticator here.
+    /// Too many wrong codes void the challenge, and the login starts over.
+    pub async fn complete_challenge(&self, challenge_token: &str, code: &str) -> Result<Pk> {
+        let mut db = self.db();
+        let challenge = self.find_challenge(&mut db, challenge_token).await?;
+        let mut user = User::get_by_id(&mut db, &challenge.user_id).await?;
+
+        if !self.verify_code(&mut db, &mut user, code).await? {
+            self.count_failed_attempt(&mut db, challenge).await?;
+            bail!(ErrorKind::InvalidCredentials, "Invalid code");
+        }
+
+        // Expire the challenge before it is used up, so that of two
+        // concurrent completions only one logs in.
+        let claimed = exec_count(
+            &mut db,
+            MfaChallenge::filter_by_id(challenge.id)
+                .filter(MfaChallenge::fields().expires_at().eq(challenge.expires_at))
+                .update()
+                .expires_at(Timestamp::now()),
+        )
+        .await?;
+        if claimed == 0 {
+            bail!(ErrorKind::Unauthorized, "Invalid or expired MFA challenge");
+        }
+        MfaChallenge::filter_by_id(challenge.id)
+            .delete()
+            .exec(&mut db)
+            .await?;
+        if !user.totp_enabled {
+            user.update().totp_enabled(true).exec(&mut db).await?;
+        }
+        self.throttle.record_success(&user.username).await?;
+        Ok(challenge.user_id)
+    }
+
+    /// Count a wrong code against the challenge, voiding it once
+    /// `max_attempts` is reached.
+    async fn count_failed_attempt(&self, db: &mut Db, mut challenge: MfaChallenge) -> Result<()> {
+        loop {
+            let attempts = challenge.attempts + 1;
+            if attempts >= self.policy.max_attempts as i64 {
+                MfaChallenge::filter_by_id(challenge.id)
+                    .delete()
+                    .exec(db)
+                    .await?;
+                bail!(
+                    ErrorKind::Unauthorized,
+                    "Too many invalid codes; log in again"
+                );
+            }
+            let counted = exec_count(
+                db,
+                MfaChallenge::filter_by_id(challenge.id)
+                    .filter(MfaChallenge::fields().attempts().eq(challenge.attempts))
+                    .update()
+                    .attempts(attempts),
+            )
+            .await?;
+            if counted == 1 {
+                return Ok(());
+            }
+            // A concurrent attempt was counted first; count on top of it.
+            match MfaChallenge::get_by_id(&mut *db, &challenge.id)
+                .await
+                .optional()?
+            {
+                Some(current) => challenge = current,
+                None => bail!(
+                    ErrorKind::Unauthorized,
+                    "Too many invalid codes; log in again"
+                ),
+            }
+        }
+    }
+
+    /// Delete challenges that can no longer be completed.
+    pub async fn purge_expired(&self) -> Result<()> {
+        let mut db = self.db();
+        MfaChallenge::all()
+            .filter(MfaChallenge::fields().expires_at().le(Timestamp::now()))
+            .delete()
+            .exec(&mut db)
+            .await?;
+        Ok(())
+    }
+
+    /// Generate a TOTP secret and recovery codes for the user. Nothing is
+    /// enforced until a code from the authenticator is confirmed.
+    pub async fn enroll(&self, user_id: Pk) -> Result<TotpEnrollment> {
+        let mut db = self.db();
+        let mut user = User::get_by_id(&mut db, &user_id).await?;
+        if user.totp_enabled {
+            bail!(
+                ErrorKind::AlreadyExists,
+                "Two-factor authentication is already enabled"
+            );
+        }
+
+        let secret = totp::generate_secret();
+        user.update()
+            .totp_secret(Some(secret.clone()))
+            .totp_last_step(None)
+            .exec(&mut db)
+            .await?;
+        let recovery_codes = self.replace_recovery_codes(&mut db, user_id).await?;
+
+        Ok(TotpEnrollment {
+            otpauth_uri: totp::otpauth_uri(&self.policy.issuer, &user.username, &secret),
+            secret,
+            recovery_codes,
+        })
+    }
+
+    /// [`enroll`](Self::enroll) on behalf of a challenged login whose user
+    /// must enroll before they can log in.
+    pub async fn enroll_for_challenge(&self, challenge_token: &str) -> Result<TotpEnrollment> {
+        let mut db = self.db();
+        let challenge = self.find_challenge(&mut db, challenge_token).await?;
+        self.enroll(challenge.user_id).await
+    }
+
+    /// Enable TOTP once the user proves their authenticator works.
+    pub async fn confirm(&self, user_id: Pk, code: &str) -> Result<()> {
+        let mut db = self.db();
+        let mut user = User::get_by_id(&mut db, &user_id).await?;
+        if user.totp_enabled {
+            bail!(
+                ErrorKind::AlreadyExists,
+                "Two-factor authentication is already enabled"
+            );
+        }
+        if user.totp_secret.is_none() {
+            bail!(ErrorKind::BadRequest, "Start enrollment first");
+        }
+        if !self.verify_code(&mut db, &mut user, code).await? {
+            bail!(ErrorKind::InvalidCredentials, "Invalid code");
+        }
+        user.update().totp_enabled(true).exec(&mut db).await?;
+        Ok(())
+    }
+
+    /// Turn TOTP off. Not allowed while a role requires it.
+    pub async fn disable(&self, user_id: Pk, code: &str) -> Result<()> {
+        let mut db = self.db();
+        let mut user = self.verified_user(&mut db, user_id, code).await?;
+        if self.is_required(user_id).await? {
+            bail!(
+                ErrorKind::PermissionDenied,
+                "Two-factor authentication is required for your role"
+            );
+        }
+
+        user.update()
+            .totp_enabled(false)
+            .totp_secret(None)
+            .totp_last_step(None)
+            .exec(&mut db)
+            .await?;
+        RecoveryCode::all()
+            .filter(RecoveryCode::fields().user_id().eq(user_id))
+            .delete()
+            .exec(&mut db)
+            .await?;
+        Ok(())
+    }
+
+    /// Replace the user's recovery codes, invalidating the old ones.
+    pub async fn regenerate_recovery_codes(&self, user_id: Pk, code: &str) -> Result<Vec<String>> {
+        let mut db = self.db();
+        self.verified_user(&mut db, user_id, code).await?;
+        self.replace_recovery_codes(&mut db, user_id).await
+    }
+
+    /// The user, after checking `code` against their enabled second factor.
+    async fn verified_user(&self, db: &mut Db, user_id: Pk, code: &str) -> Result<User> {
+        let mut user = User::get_by_id(db, &user_id).await?;
+        if !user.totp_enabled {
+            bail!(
+                ErrorKind::BadRequest,
+                "Two-factor authentication is not enabled"
+            );
+        }
+        if !self.verify_code(db, &mut user, code).await? {
+            bail!(ErrorKind::InvalidCredentials, "Invalid code");
+        }
+        Ok(user)
+    }
+
+    async fn find_challenge(&self, db: &mut Db, challenge_token: &str) -> Result<MfaChallenge> {
+        if !TokenKind::MfaChallenge.is_valid(challenge_token) {
+            bail!(ErrorKind::Unauthorized, "Invalid or expired MFA challenge");
+        }
+        let challenge = MfaChallenge::filter_by_token_hash(token::digest(challenge_token))
+            .get(db)
+            .await
+            .optional()?
+            .filter(|c| c.expires_at > Timestamp::now());
+        match challenge {
+            Some(challenge) => Ok(challenge),
+            None => bail!(ErrorKind::Unauthorized, "Invalid or expired MFA challenge"),
+        }
+    }
+
+    /// Check a TOTP code, or a recovery code once TOTP is enabled, consuming
+    /// it either way.
+    ///
+    /// Wrong codes count as failed logins for the user's name in the
+    /// [`ThrottleService`], which refuses further codes while it backs off,
+    /// so codes cannot be guessed across fresh challenges or through the
+    /// enrollment endpoints.
+    async fn verify_code(&self, db: &mut Db, user: &mut User, code: &str) -> Result<bool> {
+        self.throttle.check(&user.username, None).await?;
+        let valid = self.check_code(db, user, code).await?;
+        if !valid {
+            self.throttle.record_failure(&user.username, None).await?;
+        }
+        Ok(valid)
+    }
+
+    async fn check_code(&self, db: &mut Db, user: &mut User, code: &str) -> Result<bool> {
+        let code = code.trim();
+        if let Some(secret) = &user.totp_secret
+            && let Some(step) = totp::verify(secret, code, Timestamp::now().as_second())
+        {
+            // A code is good for its whole time step; refuse it a second
+            // time, including to a concurrent request.
+            let last_step = User::fields().totp_last_step();
+            let recorded = exec_count(
+                db,
+                User::filter_by_id(user.id)
+                    .filter(last_step.clone().is_none().or(last_step.lt(Some(step))))
+                    .update()
+                    .totp_last_step(Some(step)),
+            )
+            .await?;
+            if recorded == 0 {
+                return Ok(false);
+            }
+            user.totp_last_step = Some(step);
+            return Ok(true);
+        }
+
+        if !user.totp_enabled {
+            return Ok(false);
+        }
+        let code_hash = token::digest(&normalize_recovery_code(code));
+        let recovery = Self::unused_recovery_codes(db, user.id)
+            .await?
+            .into_iter()
+            .find(|c| token::constant_time_eq(c.code_hash.as_bytes(), code_hash.as_bytes()));
+        let Some(recovery) = recovery else {
+            return Ok(false);
+        };
+        let used = exec_count(
+            db,
+            RecoveryCode::filter_by_id(recovery.id)
+                .filter(RecoveryCode::fields().used_at().is_none())
+                .update()
+                .used_at(Some(Timestamp::now())),
+        )
+        .await?;
+        Ok(used == 1)
+    }
+
+    async fn unused_recovery_codes(db: &mut Db, user_id: Pk) -> Result<Vec<RecoveryCode>> {
+        Ok(RecoveryCode::all()
+            .filter(RecoveryCode::fields().user_id().eq(user_id))
+            .exec(db)
+            .await?
+            .into_iter()
+            .filter(|c| c.used_at.is_none())
+            .collect())
+    }
+
+    async fn replace_recovery_codes(&self, db: &mut Db, user_id: Pk) -> Result<Vec<String>> {
+        RecoveryCode::all()
+            .filter(RecoveryCode::fields().user_id().eq(user_id))
+            .delete()
+            .exec(db)
+            .await?;
+
+        let mut codes = Vec::with_capacity(self.policy.recovery_codes);
+        for _ in 0..self.policy.recovery_codes {
+            let raw = token::random_hex(5);
+            toasty::create!(RecoveryCode {
+                user_id,
+                code_hash: token::digest(&raw),
+            })
+            .exec(db)
+            .await?;
+            codes.push(format!("{}-{}", &raw[..5], &raw[5..]));
+        }
+        Ok(codes)
+    }
+}
+
+/// Recovery codes are shown as `xxxxx-xxxxx` but accepted without the dash
+/// and in either case.
+fn normalize_recovery_code(code: &str) -> String {
+    code.chars()
+        .filter(|c| c.is_ascii_alphanumeric())
+        .map(|c| c.to_ascii_lowercase())
+        .collect()
+}
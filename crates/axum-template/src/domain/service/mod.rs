@@ -1,11 +1,15 @@
 pub mod auth;
+pub mod mfa;
 pub mod role;
 pub mod session;
+pub mod throttle;
 pub mod token;
 pub mod user;
 
 pub use auth::AuthService;
+pub use mfa::{MfaPolicy, MfaService, MfaStatus, PendingMfa, TotpEnrollment};
 pub use role::RoleService;
-pub use session::SessionService;
-pub use token::TokenService;
+pub use session::{SessionPolicy, SessionService};
+pub use throttle::{AttemptLimits, ThrottlePolicy, ThrottleService};
+pub use token::{ClaimsHook, TokenPolicy, TokenService};
 pub use user::UserService;
@@ -3,20 +3,22 @@ use toasty::Db;
 use crate::{
     bail,
     domain::{
+        TokenService,
         db::Pk,
         model::{Perm, Role, UserRole},
     },
-    error::{ErrorKind, Result},
+    error::{ErrorKind, OptionalExt, Result},
 };
 
 #[derive(Debug, Clone)]
 pub struct RoleService {
     db: Db,
+    token: TokenService,
 }
 
 impl RoleService {
-    pub fn new(db: Db) -> Self {
-        Self { db }
+    pub fn new(db: Db, token: TokenService) -> Self {
+        Self { db, token }
     }
 
     fn db(&self) -> Db {
@@ -46,7 +48,7 @@ impl RoleService {
 
     pub async fn find_by_id(&self, id: Pk) -> Result<Option<Role>> {
         let mut db = self.db();
-        Ok(Role::get_by_id(&mut db, &id).await.ok())
+        Role::get_by_id(&mut db, &id).await.optional()
     }
 
     pub async fn get_by_id(&self, id: Pk) -> Result<Role> {
@@ -56,11 +58,15 @@ impl RoleService {
 
     pub async fn find_by_name(&self, name: &str) -> Result<Option<Role>> {
         let mut db = self.db();
-        Ok(Role::filter_by_name(name).get(&mut db).await.ok())
+        Role::filter_by_name(name).get(&mut db).await.optional()
     }
 
     async fn exists_by_name_inner(db: &mut Db, name: &str) -> Result<bool> {
-        Ok(Role::filter_by_name(name).get(db).await.is_ok())
+        Ok(Role::filter_by_name(name)
+            .get(db)
+            .await
+            .optional()?
+            .is_some())
     }
 
     pub async fn list(&self, page: u64, per_page: u64) -> Result<Vec<Role>> {
@@ -84,31 +90,95 @@ impl RoleService {
         Ok(Role::all().count().exec(&mut db).await?)
     }
 
-    pub async fn update_name(&self, id: Pk, new_name: String) -> Result<Role> {
+    /// Rename the role and/or replace its description, leaving whichever
+    /// is `None` unchanged.
+    pub async fn update(
+        &self,
+        id: Pk,
+        new_name: Option<String>,
+        description: Option<Option<String>>,
+    ) -> Result<Role> {
         let mut db = self.db();
         let mut role = Role::get_by_id(&mut db, &id).await?;
 
-        if new_name != role.name && Self::exists_by_name_inner(&mut db, &new_name).await? {
+        if let Some(new_name) = &new_name
+            && *new_name != role.name
+            && Self::exists_by_name_inner(&mut db, new_name).await?
+        {
             bail!(ErrorKind::AlreadyExists, "Role name already exists");
         }
+        if new_name.is_none() && description.is_none() {
+            return Ok(role);
+        }
+
+        let mut update = role.update();
+        if let Some(new_name) = new_name {
+            update.set_name(new_name);
+        }
+        if let Some(description) = description {
+            update.set_description(description);
+        }
+        update.exec(&mut db).await?;
+        Ok(Role::get_by_id(&mut db, &id).await?)
+    }
 
-        role.update().name(new_name).exec(&mut db).await?;
+    /// Replace the role's permissions and revoke its members' access tokens.
+    pub async fn update_permissions(&self, id: Pk, perms: &[Perm]) -> Result<Role> {
+        let mut db = self.db();
+        let mut role = Role::get_by_id(&mut db, &id).await?;
+        let permissions = serde_json::to_string(perms).unwrap_or_default();
+        role.update().permissions(permissions).exec(&mut db).await?;
+        self.revoke_members_tokens(&mut db, id).await?;
         Ok(Role::get_by_id(&mut db, &id).await?)
     }
 
-    pub async fn update_description(&self, id: Pk, description: Option<String>) -> Result<Role> {
+    /// Make a second factor mandatory, or optional, for the role's members.
+    pub async fn update_require_mfa(&self, id: Pk, require_mfa: bool) -> Result<Role> {
         let mut db = self.db();
         let mut role = Role::get_by_id(&mut db, &id).await?;
-        role.update().description(description).exec(&mut db).await?;
+        role.update().require_mfa(require_mfa).exec(&mut db).await?;
         Ok(Role::get_by_id(&mut db, &id).await?)
     }
 
+    /// Delete the role, unassigning it and revoking its former members'
+    /// access tokens.
     pub async fn delete(&self, id: Pk) -> Result<()> {
         let mut db = self.db();
+        let members = Self::member_ids(&mut db, id).await?;
+        UserRole::all()
+            .filter(UserRole::fields().role_id().eq(id))
+            .delete()
+            .exec(&mut db)
+            .await?;
         Role::filter_by_id(id).delete().exec(&mut db).await?;
+        for user_id in members {
+            self.token.revoke_access_tokens(user_id).await?;
+        }
         Ok(())
     }
 
+    async fn member_ids(db: &mut Db, role_id: Pk) -> Result<Vec<Pk>> {
+        let mut ids: Vec<Pk> = UserRole::all()
+            .filter(UserRole::fields().role_id().eq(role_id))
+            .exec(db)
+            .await?
+            .iter()
+            .map(|ur| ur.user_id)
+            .collect();
+        ids.sort_unstable();
+        ids.dedup();
+        Ok(ids)
+    }
+
+    async fn revoke_members_tokens(&self, db: &mut Db, role_id: Pk) -> Result<()> {
+        for user_id in Self::member_ids(db, role_id).await? {
+            self.token.revoke_access_tokens(user_id).await?;
+        }
+        Ok(())
+    }
+
+    /// Assign the role and revoke the user's access tokens, so the change
+    /// takes effect at once.
     pub async fn assign_to_user(&self, user_id: Pk, role_id: Pk) -> Result<()> {
         let mut db = self.db();
         let existing = UserRole::all()
@@ -124,9 +194,10 @@ impl RoleService {
         toasty::create!(UserRole { user_id, role_id })
             .exec(&mut db)
             .await?;
-        Ok(())
+        self.token.revoke_access_tokens(user_id).await
     }
 
+    /// Unassign the role and revoke the user's access tokens.
     pub async fn remove_from_user(&self, user_id: Pk, role_id: Pk) -> Result<()> {
         let mut db = self.db();
         UserRole::all()
@@ -135,7 +206,7 @@ impl RoleService {
             .delete()
             .exec(&mut db)
             .await?;
-        Ok(())
+        self.token.revoke_access_tokens(user_id).await
     }
 
     pub async fn get_user_roles(&self, user_id: Pk) -> Result<Vec<Role>> {
@@ -1,88 +1,201 @@
-use uuid::Uuid;
+use jiff::{SignedDuration, Timestamp};
 
 use crate::{
-    domain::{db::Pk, model::Session},
-    error::Result,
+    domain::{TokenService, db::Pk, model::Session, opaque::TokenKind},
+    error::{ErrorKind, OptionalExt, Result},
+    util::token,
 };
 
+/// Session lifetime rules.
+#[derive(Debug, Clone, Copy)]
+pub struct SessionPolicy {
+    /// Expire after this long without a request.
+    pub idle_timeout: SignedDuration,
+    /// Expire this long after creation regardless of activity.
+    pub absolute_timeout: SignedDuration,
+    /// Record activity at most this often.
+    pub touch_interval: SignedDuration,
+}
+
 #[derive(Debug, Clone)]
 pub struct SessionService {
     db: toasty::Db,
-    ttl_hours: u64,
+    policy: SessionPolicy,
+    token: TokenService,
 }
 
 impl SessionService {
-    pub fn new(db: toasty::Db, ttl_hours: u64) -> Self {
-        Self { db, ttl_hours }
+    pub fn new(db: toasty::Db, policy: SessionPolicy, token: TokenService) -> Self {
+        Self { db, policy, token }
     }
 
     fn db(&self) -> toasty::Db {
         self.db.clone()
     }
 
-    fn expires_at(&self) -> jiff::Timestamp {
-        jiff::Timestamp::now() + jiff::Span::new().hours(self.ttl_hours as i64)
+    /// Idle deadline from `now`, capped by the absolute deadline.
+    fn expires_at(&self, created_at: Timestamp, now: Timestamp) -> Timestamp {
+        (now + self.policy.idle_timeout).min(created_at + self.policy.absolute_timeout)
     }
 
+    pub fn is_expired(&self, session: &Session) -> bool {
+        let now = Timestamp::now();
+        session.expires_at <= now || session.created_at + self.policy.absolute_timeout <= now
+    }
+
+    /// Whether enough time has passed since the last recorded activity to
+    /// be worth a write.
     pub fn should_extend(&self, session: &Session) -> bool {
-        let now = jiff::Timestamp::now();
-        let elapsed_secs = now.as_second() as i64 - session.created_at.as_second() as i64;
-        elapsed_secs >= (self.ttl_hours * 3600 / 2) as i64
+        Timestamp::now().duration_since(session.last_seen_at) >= self.policy.touch_interval
     }
 
-    pub async fn create(&self, user_id: Pk) -> Result<String> {
+    /// Start a session, returning its id, to be sent to the client once, and
+    /// when it expires unless extended.
+    pub async fn create(
+        &self,
+        user_id: Pk,
+        user_agent: Option<String>,
+        ip: Option<String>,
+    ) -> Result<(String, Timestamp)> {
         let mut db = self.db();
-        let session_id = Uuid::new_v4().to_string();
-        let expires_at = self.expires_at();
+        let session_id = TokenKind::Session.generate();
+        let now = Timestamp::now();
+        let expires_at = self.expires_at(now, now);
         toasty::create!(Session {
             user_id,
-            session_id: session_id.clone(),
+            session_id_hash: token::digest(&session_id),
             expires_at,
+            last_seen_at: now,
+            user_agent,
+            ip,
+            csrf_token: token::random_hex(32),
         })
         .exec(&mut db)
         .await?;
-        Ok(session_id)
+        Ok((session_id, expires_at))
     }
 
     pub async fn find(&self, session_id: &str) -> Result<Option<Session>> {
+        if !TokenKind::Session.is_valid(session_id) {
+            return Ok(None);
+        }
         let mut db = self.db();
-        Ok(Session::filter_by_session_id(session_id)
+        Session::filter_by_session_id_hash(token::digest(session_id))
             .get(&mut db)
             .await
-            .ok())
+            .optional()
     }
 
-    pub async fn extend(&self, session_id: &str) -> Result<()> {
+    /// The session's synchronizer CSRF token, issuing one if it has none.
+    pub async fn csrf_token(&self, session_id: &str) -> Result<Option<String>> {
+        let Some(mut session) = self.find(session_id).await? else {
+            return Ok(None);
+        };
+        if session.csrf_token.is_empty() {
+            let mut db = self.db();
+            session
+                .update()
+                .csrf_token(token::random_hex(32))
+                .exec(&mut db)
+                .await?;
+        }
+        Ok(Some(session.csrf_token))
+    }
+
+    /// Record activity and slide the idle deadline forward, up to the
+    /// absolute one. Returns the new expiry.
+    pub async fn extend(&self, mut session: Session) -> Result<Timestamp> {
         let mut db = self.db();
-        let mut session = Session::filter_by_session_id(session_id)
-            .get(&mut db)
-            .await?;
+        let now = Timestamp::now();
+        let expires_at = self.expires_at(session.created_at, now);
         session
             .update()
-            .expires_at(self.expires_at())
+            .expires_at(expires_at)
+            .last_seen_at(now)
             .exec(&mut db)
             .await?;
-        Ok(())
+        Ok(expires_at)
+    }
+
+    /// The user's live sessions, most recently active first.
+    pub async fn list_by_user_id(&self, user_id: Pk) -> Result<Vec<Session>> {
+        let mut db = self.db();
+        let mut sessions: Vec<Session> = Session::all()
+            .filter(Session::fields().user_id().eq(user_id))
+            .exec(&mut db)
+            .await?
+            .into_iter()
+            .filter(|s| !self.is_expired(s))
+            .collect();
+        sessions.sort_by_key(|s| std::cmp::Reverse(s.last_seen_at));
+        Ok(sessions)
+    }
+
+    /// Delete the session with primary key `id` if it belongs to `user_id`,
+    /// returning it.
+    pub async fn delete_for_user(&self, user_id: Pk, id: Pk) -> Result<Session> {
+        let mut db = self.db();
+        let session = Session::filter_by_id(id)
+            .get(&mut db)
+            .await
+            .optional()?
+            .filter(|s| s.user_id == user_id)
+            .ok_or(ErrorKind::NotFound)?;
+        Session::filter_by_id(id).delete().exec(&mut db).await?;
+        Ok(session)
+    }
+
+    /// Sign the user out everywhere but `keep_session_id`: delete their other
+    /// sessions and refresh tokens and revoke their access tokens. A JWT
+    /// client cannot be told apart from the user's other devices, so it is
+    /// signed out too.
+    pub async fn delete_others(&self, user_id: Pk, keep_session_id: Option<&str>) -> Result<()> {
+        let mut db = self.db();
+        let sessions = Session::all()
+            .filter(Session::fields().user_id().eq(user_id))
+            .exec(&mut db)
+            .await?;
+        for s in sessions
+            .iter()
+            .filter(|s| !keep_session_id.is_some_and(|id| s.matches(id)))
+        {
+            Session::filter_by_id(s.id).delete().exec(&mut db).await?;
+        }
+        self.token.revoke_all(user_id).await
     }
 
     pub async fn delete(&self, session_id: &str) -> Result<()> {
+        if !TokenKind::Session.is_valid(session_id) {
+            return Ok(());
+        }
         let mut db = self.db();
-        Session::filter_by_session_id(session_id)
+        Session::filter_by_session_id_hash(token::digest(session_id))
             .delete()
             .exec(&mut db)
             .await?;
         Ok(())
     }
 
+    /// Sign the user out everywhere.
     pub async fn delete_by_user_id(&self, user_id: Pk) -> Result<()> {
+        self.delete_others(user_id, None).await
+    }
+
+    /// Delete sessions past their idle or absolute deadline.
+    pub async fn purge_expired(&self) -> Result<()> {
         let mut db = self.db();
-        let sessions = Session::all()
-            .filter(Session::fields().user_id().eq(user_id))
+        let now = Timestamp::now();
+        let fields = Session::fields();
+        Session::all()
+            .filter(
+                fields
+                    .expires_at()
+                    .le(now)
+                    .or(fields.created_at().le(now - self.policy.absolute_timeout)),
+            )
+            .delete()
             .exec(&mut db)
             .await?;
-        for s in &sessions {
-            Session::filter_by_id(s.id).delete().exec(&mut db).await?;
-        }
         Ok(())
     }
 }
@@ -0,0 +1,283 @@
+use jiff::{SignedDuration, Timestamp};
+use toasty::Db;
+
+use crate::{
+    domain::{db::exec_count, model::LoginThrottle},
+    error::{AppError, ErrorKind, OptionalExt, Result},
+};
+
+/// Backoff and lockout thresholds for one kind of key.
+#[derive(Debug, Clone, Copy)]
+pub struct AttemptLimits {
+    /// Failures allowed before any delay.
+    pub free_attempts: u32,
+    /// First delay; doubles with each further failure up to `max_delay`.
+    pub base_delay: SignedDuration,
+    pub max_delay: SignedDuration,
+    /// Failures that trigger a lockout of `lockout`.
+    pub lockout_threshold: u32,
+    pub lockout: SignedDuration,
+}
+
+impl AttemptLimits {
+    /// How long to refuse logins after the `failures`th failure.
+    fn block_for(&self, failures: u32) -> Option<SignedDuration> {
+        if failures >= self.lockout_threshold {
+            return Some(self.lockout);
+        }
+        let excess = failures.checked_sub(self.free_attempts + 1)?;
+        let delay = self.base_delay * 2i32.saturating_pow(excess.min(30));
+        Some(delay.min(self.max_delay))
+    }
+}
+
+/// Failed-login limits.
+#[derive(Debug, Clone, Copy)]
+pub struct ThrottlePolicy {
+    pub user: AttemptLimits,
+    pub ip: AttemptLimits,
+    /// Failures older than this are forgotten.
+    pub reset_after: SignedDuration,
+}
+
+/// Tracks failed logins per username and per client IP, so password
+/// guessing slows down exponentially and is refused outright before any
+/// password hash is computed.
+#[derive(Debug, Clone)]
+pub struct ThrottleService {
+    db: Db,
+    policy: ThrottlePolicy,
+}
+
+impl ThrottleService {
+    pub fn new(db: Db, policy: ThrottlePolicy) -> Self {
+        Self { db, policy }
+    }
+
+    fn db(&self) -> Db {
+        self.db.clone()
+    }
+
+    /// Refuse the attempt if the username or IP is backing off or locked
+    /// out. A locked username is `AccountLocked`; everything else is
+    /// `TooManyAttempts`.
+    pub async fn check(&self, username: &str, ip: Option<&str>) -> Result<()> {
+        let now = Timestamp::now();
+        if let Some((until, failures)) = self.blocked_until(&user_key(username), now).await? {
+            let (kind, msg) = if failures >= self.policy.user.lockout_threshold as i64 {
+                (
+                    ErrorKind::AccountLocked,
+                    "Account temporarily locked after too many failed logins",
+                )
+            } else {
+                (
+                    ErrorKind::TooManyAttempts,
+                    "Too many failed logins; try again later",
+                )
+            };
+            return Err(blocked(kind, msg, until, now));
+        }
+        if let Some(ip) = ip
+            && let Some((until, _)) = self.blocked_until(&ip_key(ip), now).await?
+        {
+            return Err(blocked(
+                ErrorKind::TooManyAttempts,
+                "Too many failed logins from this address; try again later",
+                until,
+                now,
+            ));
+        }
+        Ok(())
+    }
+
+    pub async fn record_failure(&self, username: &str, ip: Option<&str>) -> Result<()> {
+        self.record(&user_key(username), &self.policy.user).await?;
+        if let Some(ip) = ip {
+            self.record(&ip_key(ip), &self.policy.ip).await?;
+        }
+        Ok(())
+    }
+
+    /// Clear the username's failures. The IP's are kept, so an attacker
+    /// can't reset them by logging in to an account of their own.
+    pub async fn record_success(&self, username: &str) -> Result<()> {
+        self.clear(&user_key(username)).await.map(|_| ())
+    }
+
+    /// Lift any backoff or lockout on `username`. Returns whether there was
+    /// anything to clear.
+    pub async fn unlock_user(&self, username: &str) -> Result<bool> {
+        self.clear(&user_key(username)).await
+    }
+
+    /// Lift any backoff or lockout on `ip`.
+    pub async fn unlock_ip(&self, ip: &str) -> Result<bool> {
+        self.clear(&ip_key(ip)).await
+    }
+
+    /// The block in force for `key` and the failures behind it.
+    async fn blocked_until(&self, key: &str, now: Timestamp) -> Result<Option<(Timestamp, i64)>> {
+        let mut db = self.db();
+        let throttle = LoginThrottle::filter_by_key(key)
+            .get(&mut db)
+            .await
+            .optional()?;
+        Ok(throttle.and_then(|t| {
+            t.blocked_until
+                .filter(|&until| until > now)
+                .map(|until| (until, t.failures))
+        }))
+    }
+
+    /// Count a failure for `key`. Concurrent failures are each counted: the
+    /// row is updated only if no other failure was recorded since it was
+    /// read, and otherwise re-read and counted again.
+    async fn record(&self, key: &str, limits: &AttemptLimits) -> Result<()> {
+        let mut db = self.db();
+        let failures = loop {
+            let now = Timestamp::now();
+            let existing = LoginThrottle::filter_by_key(key)
+                .get(&mut db)
+                .await
+                .optional()?;
+
+            let Some(throttle) = existing else {
+                self.purge_stale().await?;
+                let created = toasty::create!(LoginThrottle {
+                    key: key.to_owned(),
+                    failures: 1,
+                    blocked_until: limits.block_for(1).map(|d| now + d),
+                    last_failure_at: now,
+                })
+                .exec(&mut db)
+                .await;
+                match created {
+                    Ok(_) => break 1,
+                    // Another first failure created the row; count on top.
+                    Err(_)
+                        if LoginThrottle::filter_by_key(key)
+                            .get(&mut db)
+                            .await
+                            .optional()?
+                            .is_some() =>
+                    {
+                        continue;
+                    }
+                    Err(e) => return Err(e.into()),
+                }
+            };
+
+            // Start over once failures have gone quiet or a lockout has been
+            // served.
+            let stale = throttle.last_failure_at + self.policy.reset_after <= now;
+            let served = throttle.failures >= limits.lockout_threshold as i64
+                && throttle.blocked_until.is_none_or(|until| until <= now);
+            let failures = if stale || served {
+                1
+            } else {
+                throttle.failures + 1
+            };
+
+            let fields = LoginThrottle::fields();
+            let counted = exec_count(
+                &mut db,
+                LoginThrottle::filter_by_id(throttle.id)
+                    .filter(fields.failures().eq(throttle.failures))
+                    .filter(fields.last_failure_at().eq(throttle.last_failure_at))
+                    .update()
+                    .failures(failures)
+                    .blocked_until(limits.block_for(failures as u32).map(|d| now + d))
+                    .last_failure_at(now),
+            )
+            .await?;
+            if counted == 1 {
+                break failures;
+            }
+        };
+
+        if failures == limits.lockout_threshold as i64 {
+            tracing::warn!(
+                target: "security",
+                event = "login_lockout",
+                key,
+                failures,
+                "Login locked out after repeated failures"
+            );
+        }
+        Ok(())
+    }
+
+    /// Delete the counts of keys that are not blocked and whose failures are
+    /// old enough to be forgotten anyway. Runs whenever a new key is first
+    /// counted, so the table stays about as large as the set of keys failing
+    /// within `reset_after`.
+    pub async fn purge_stale(&self) -> Result<()> {
+        let mut db = self.db();
+        let now = Timestamp::now();
+        let fields = LoginThrottle::fields();
+        LoginThrottle::all()
+            .filter(fields.last_failure_at().le(now - self.policy.reset_after))
+            .filter(
+                fields
+                    .blocked_until()
+                    .is_none()
+                    .or(fields.blocked_until().le(Some(now))),
+            )
+            .delete()
+            .exec(&mut db)
+            .await?;
+        Ok(())
+    }
+
+    async fn clear(&self, key: &str) -> Result<bool> {
+        let mut db = self.db();
+        let existing = LoginThrottle::filter_by_key(key)
+            .get(&mut db)
+            .await
+            .optional()?;
+        if existing.is_none() {
+            return Ok(false);
+        }
+        LoginThrottle::filter_by_key(key)
+            .delete()
+            .exec(&mut db)
+            .await?;
+        Ok(true)
+    }
+}
+
+fn user_key(username: &str) -> String {
+    format!("user:{username}")
+}
+
+fn ip_key(ip: &str) -> String {
+    format!("ip:{ip}")
+}
+
+fn blocked(kind: ErrorKind, msg: &str, until: Timestamp, now: Timestamp) -> AppError {
+    let seconds = until.duration_since(now).as_secs_f64().ceil() as u64;
+    kind.msg(msg).with_retry_after(seconds.max(1))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_backoff_doubles_then_locks_out() {
+        let limits = AttemptLimits {
+            free_attempts: 2,
+            base_delay: SignedDuration::from_secs(1),
+            max_delay: SignedDuration::from_secs(5),
+            lockout_threshold: 7,
+            lockout: SignedDuration::from_mins(15),
+        };
+        let delays: Vec<_> = (1..=7)
+            .map(|n| limits.block_for(n).map(|d| d.as_secs()))
+            .collect();
+        assert_eq!(
+            delays,
+            [None, None, Some(1), Some(2), Some(4), Some(5), Some(900)]
+        );
+    }
+}
@@ -1,25 +1,79 @@
-use std::fmt;
+use std::{
+    collections::HashMap,
+    fmt,
+    sync::{Arc, Mutex},
+};
 
-use jsonwebtoken::{DecodingKey, EncodingKey, Validation, decode, encode};
+use async_trait::async_trait;
+use jiff::{SignedDuration, Timestamp};
+use jsonwebtoken::{Validation, decode, decode_header, encode, jwk::JwkSet};
 use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
 use toasty::Db;
 use uuid::Uuid;
 
 use crate::{
     bail,
     domain::{
-        db::Pk,
+        db::{Pk, exec_count},
+        jwt_keys::JwtKeys,
         model::{RefreshToken, User},
+        opaque::TokenKind,
     },
-    error::{ErrorKind, Result, ResultExt},
+    error::{AppError, ErrorKind, OptionAppExt, OptionalExt, Result, ResultExt},
+    util::token,
 };
 
+/// Registered claims; [`ClaimsHook`] output may not reuse these names.
+const REGISTERED_CLAIMS: [&str; 9] = [
+    "sub", "username", "ver", "iss", "aud", "exp", "nbf", "iat", "jti",
+];
+
+/// Cached versions beyond this many are pruned of stale entries.
+const VERSION_CACHE_CAPACITY: usize = 10_000;
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Claims {
     pub sub: Pk,
     pub username: String,
+    /// The user's `token_version` at issue time.
+    pub ver: i64,
+    pub iss: String,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub aud: Vec<String>,
     pub exp: usize,
+    pub nbf: usize,
     pub iat: usize,
+    pub jti: String,
+    /// Claims added by the [`ClaimsHook`].
+    #[serde(flatten)]
+    pub custom: Map<String, Value>,
+}
+
+/// Adds application claims, such as roles or a tenant, to access tokens.
+///
+/// Install one with `AppState::with_claims_hook`; handlers read the
+/// claims back through `JwtCtx::claim`.
+#[async_trait]
+pub trait ClaimsHook: Send + Sync {
+    async fn claims(&self, user: &User) -> Result<Map<String, Value>>;
+}
+
+/// Access and refresh token rules.
+#[derive(Debug, Clone)]
+pub struct TokenPolicy {
+    pub access_ttl: SignedDuration,
+    pub refresh_ttl: SignedDuration,
+    /// Emitted as `iss` and required on incoming tokens.
+    pub issuer: String,
+    /// Emitted as `aud`; incoming tokens must name one of these. Not checked
+    /// when empty.
+    pub audience: Vec<String>,
+    /// Clock skew tolerated on `exp` and `nbf`.
+    pub leeway: SignedDuration,
+    /// How long a user's `token_version` is cached. Bumps made by another
+    /// instance take up to this long to be seen here.
+    pub version_cache_ttl: SignedDuration,
 }
 
 #[derive(Debug, Clone)]
@@ -32,16 +86,28 @@ pub struct RotatedTokens {
 #[derive(Clone)]
 pub struct TokenService {
     db: Db,
-    encoding: EncodingKey,
-    decoding: DecodingKey,
-    expires_in_seconds: u64,
+    keys: JwtKeys,
+    policy: TokenPolicy,
+    claims_hook: Option<Arc<dyn ClaimsHook>>,
+    versions: Arc<Mutex<HashMap<Pk, CachedUser>>>,
+}
+
+/// What access checks need to know about a user, cached for
+/// `version_cache_ttl`.
+#[derive(Debug, Clone, Copy)]
+struct CachedUser {
+    token_version: i64,
+    must_change_password: bool,
+    fetched: Timestamp,
 }
 
 impl fmt::Debug for TokenService {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         f.debug_struct("TokenService")
             .field("db", &self.db)
-            .field("expires_in_seconds", &self.expires_in_seconds)
+            .field("keys", &self.keys)
+            .field("policy", &self.policy)
+            .field("claims_hook", &self.claims_hook.is_some())
             .finish()
     }
 }
@@ -51,45 +117,249 @@ impl TokenService {
         self.db.clone()
     }
 
-    pub fn new(db: Db, jwt_secret: String, expires_in_seconds: u64) -> Self {
+    pub fn new(
+        db: Db,
+        keys: JwtKeys,
+        policy: TokenPolicy,
+        claims_hook: Option<Arc<dyn ClaimsHook>>,
+    ) -> Self {
         Self {
             db,
-            encoding: EncodingKey::from_secret(jwt_secret.as_ref()),
-            decoding: DecodingKey::from_secret(jwt_secret.as_ref()),
-            expires_in_seconds,
+            keys,
+            policy,
+            claims_hook,
+            versions: Default::default(),
         }
     }
 
-    pub fn encode_access_token(&self, user: &User) -> Result<String> {
+    pub async fn encode_access_token(&self, user: &User) -> Result<String> {
+        let custom = match &self.claims_hook {
+            Some(hook) => hook.claims(user).await?,
+            None => Map::new(),
+        };
+        if let Some(name) = custom
+            .keys()
+            .find(|k| REGISTERED_CLAIMS.contains(&k.as_str()))
+        {
+            bail!(
+                ErrorKind::Internal,
+                "Custom claim {name:?} collides with a registered claim"
+            );
+        }
+
         let now = jiff::Timestamp::now().as_second() as usize;
         let claims = Claims {
             sub: user.id,
             username: user.username.clone(),
-            exp: now + self.expires_in_seconds as usize,
+            ver: user.token_version,
+            iss: self.policy.issuer.clone(),
+            aud: self.policy.audience.clone(),
+            exp: now + self.policy.access_ttl.as_secs() as usize,
+            nbf: now,
             iat: now,
+            jti: Uuid::new_v4().to_string(),
+            custom,
         };
-        encode(&Default::default(), &claims, &self.encoding)
-            .err_kind_msg(ErrorKind::Internal, "Token generation failed")
+        let (header, key) = self.keys.signing();
+        encode(&header, &claims, key).err_kind_msg(ErrorKind::Internal, "Token generation failed")
     }
 
     pub fn decode_access_token(&self, token: &str) -> Result<Claims> {
-        decode::<Claims>(token, &self.decoding, &Validation::default())
+        let header = decode_header(token).err_kind_msg(ErrorKind::Unauthorized, "Invalid token")?;
+        let key = self
+            .keys
+            .verifying(header.kid.as_deref())
+            .ok_or_err_msg(ErrorKind::Unauthorized, "Invalid token")?;
+
+        let mut validation = Validation::new(key.algorithm());
+        validation.leeway = self.policy.leeway.as_secs() as u64;
+        validation.validate_nbf = true;
+        validation.set_issuer(&[&self.policy.issuer]);
+        // `sub` is numeric, which jsonwebtoken's spec check does not
+        // recognise; deserializing `Claims` requires it anyway.
+        validation.set_required_spec_claims(&["exp", "nbf", "iss"]);
+        if self.policy.audience.is_empty() {
+            validation.validate_aud = false;
+        } else {
+            validation.set_audience(&self.policy.audience);
+        }
+
+        decode::<Claims>(token, key.decoding(), &validation)
             .map(|d| d.claims)
             .err_kind_msg(ErrorKind::Unauthorized, "Invalid token")
     }
 
+    /// Decode `token` and check it has not been revoked since it was issued.
+    pub async fn verify_access_token(&self, token: &str) -> Result<Claims> {
+        let claims = self.decode_access_token(token)?;
+        if claims.ver != self.cached_user(claims.sub).await?.token_version {
+            bail!(ErrorKind::Unauthorized, "Token revoked");
+        }
+        Ok(claims)
+    }
+
+    /// Whether the user was told to change their password, as of at most
+    /// `version_cache_ttl` ago.
+    pub async fn must_change_password(&self, user_id: Pk) -> Result<bool> {
+        Ok(self.cached_user(user_id).await?.must_change_password)
+    }
+
+    async fn cached_user(&self, user_id: Pk) -> Result<CachedUser> {
+        let now = Timestamp::now();
+        if let Some(&cached) = self.lock_versions().get(&user_id)
+            && now < cached.fetched + self.policy.version_cache_ttl
+        {
+            return Ok(cached);
+        }
+
+        let mut db = self.db();
+        let user = User::get_by_id(&mut db, &user_id)
+            .await
+            .optional()?
+            .ok_or_err_msg(ErrorKind::Unauthorized, "Token revoked")?;
+        Ok(self.cache_user(&user, now))
+    }
+
+    fn cache_user(&self, user: &User, now: Timestamp) -> CachedUser {
+        let cached = CachedUser {
+            token_version: user.token_version,
+            must_change_password: user.must_change_password,
+            fetched: now,
+        };
+        let mut versions = self.lock_versions();
+        if versions.len() >= VERSION_CACHE_CAPACITY {
+            let ttl = self.policy.version_cache_ttl;
+            versions.retain(|_, c| now < c.fetched + ttl);
+        }
+        versions.insert(user.id, cached);
+        cached
+    }
+
+    fn lock_versions(&self) -> std::sync::MutexGuard<'_, HashMap<Pk, CachedUser>> {
+        self.versions.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Revoke every access token issued to the user so far by bumping their
+    /// `token_version`.
+    pub async fn revoke_access_tokens(&self, user_id: Pk) -> Result<()> {
+        let mut db = self.db();
+        let mut user = User::get_by_id(&mut db, &user_id).await?;
+        loop {
+            let version = user.token_version + 1;
+            let bumped = exec_count(
+                &mut db,
+                User::filter_by_id(user_id)
+                    .filter(User::fields().token_version().eq(user.token_version))
+                    .update()
+                    .token_version(version),
+            )
+            .await?;
+            if bumped == 1 {
+                user.token_version = version;
+                break;
+            }
+            // A concurrent revocation bumped it first; bump on top of it, so
+            // tokens issued in between are revoked too.
+            user = User::get_by_id(&mut db, &user_id).await?;
+        }
+        self.cache_user(&user, Timestamp::now());
+        Ok(())
+    }
+
+    /// Sign the user out of every JWT client: delete their refresh tokens
+    /// and revoke their access tokens.
+    pub async fn revoke_all(&self, user_id: Pk) -> Result<()> {
+        self.delete_all_refresh_tokens(user_id).await?;
+        self.revoke_access_tokens(user_id).await
+    }
+
+    /// Drop the cached version of a deleted user, so this instance rejects
+    /// their tokens immediately. Only the local cache is affected: other
+    /// instances keep accepting them for up to `version_cache_ttl`.
+    pub fn forget_token_version(&self, user_id: Pk) {
+        self.lock_versions().remove(&user_id);
+    }
+
+    /// Public verification keys, published as the JWK set.
+    pub fn jwks(&self) -> &JwkSet {
+        self.keys.jwks()
+    }
+
+    /// Issue the first refresh token of a new family, purging the user's
+    /// expired ones.
     pub async fn generate_refresh_token(&self, user_id: Pk) -> Result<String> {
         let mut db = self.db();
-        let token = Uuid::new_v4().to_string();
-        let expires_at = jiff::Timestamp::now() + jiff::Span::new().hours(30 * 24);
+        RefreshToken::all()
+            .filter(RefreshToken::fields().user_id().eq(user_id))
+            .filter(RefreshToken::fields().expires_at().lt(Timestamp::now()))
+            .delete()
+            .exec(&mut db)
+            .await?;
+        self.issue_refresh_token(user_id, Uuid::new_v4().to_string(), None)
+            .await
+    }
+
+    /// Delete every expired refresh token. Revoked ones are kept until then,
+    /// so their reuse is still recognised.
+    pub async fn purge_expired_refresh_tokens(&self) -> Result<()> {
+        let mut db = self.db();
+        RefreshToken::all()
+            .filter(RefreshToken::fields().expires_at().lt(Timestamp::now()))
+            .delete()
+            .exec(&mut db)
+            .await?;
+        Ok(())
+    }
+
+    async fn issue_refresh_token(
+        &self,
+        user_id: Pk,
+        family_id: String,
+        parent_id: Option<Pk>,
+    ) -> Result<String> {
+        let mut db = self.db();
+        let refresh_token = TokenKind::Refresh.generate();
+        let expires_at = jiff::Timestamp::now() + self.policy.refresh_ttl;
         toasty::create!(RefreshToken {
             user_id,
-            token: token.clone(),
+            token_hash: token::digest(&refresh_token),
             expires_at,
+            family_id,
+            parent_id,
         })
         .exec(&mut db)
         .await?;
-        Ok(token)
+        Ok(refresh_token)
+    }
+
+    /// Revoke the family of a rotated token presented again, returning the
+    /// error to answer with.
+    async fn reuse_detected(&self, stored: &RefreshToken) -> AppError {
+        if let Err(e) = self.revoke_family(&stored.family_id).await {
+            return e;
+        }
+        tracing::warn!(
+            target: "security",
+            event = "refresh_token_reuse",
+            user_id = stored.user_id,
+            family_id = %stored.family_id,
+            token_id = stored.id,
+            "Revoked refresh token reused; revoked its family"
+        );
+        ErrorKind::Unauthorized.msg("Invalid refresh token")
+    }
+
+    /// Revoke every token in `family_id`.
+    async fn revoke_family(&self, family_id: &str) -> Result<()> {
+        let mut db = self.db();
+        RefreshToken::all()
+            .filter(RefreshToken::fields().family_id().eq(family_id))
+            .filter(RefreshToken::fields().revoked().eq(false))
+            .update()
+            .revoked(true)
+            .exec(&mut db)
+            .await?;
+        Ok(())
     }
 
     pub async fn delete_all_refresh_tokens(&self, user_id: Pk) -> Result<()> {
@@ -107,27 +377,57 @@ impl TokenService {
         Ok(())
     }
 
+    /// Exchange a refresh token for a new access/refresh pair in the same
+    /// family.
+    ///
+    /// Rotated tokens are kept, revoked, so that presenting one again is
+    /// recognised as reuse: the token was copied, and whichever party holds
+    /// the live end of the chain cannot be trusted, so the whole family is
+    /// revoked.
     pub async fn rotate_refresh_token(&self, refresh_token_str: &str) -> Result<RotatedTokens> {
+        if !TokenKind::Refresh.is_valid(refresh_token_str) {
+            bail!(ErrorKind::Unauthorized, "Invalid refresh token");
+        }
+
         let mut db = self.db();
 
-        let stored = RefreshToken::filter_by_token(refresh_token_str)
+        let stored = RefreshToken::filter_by_token_hash(token::digest(refresh_token_str))
             .get(&mut db)
             .await
             .err_kind_msg(ErrorKind::Unauthorized, "Invalid refresh token")?;
 
-        RefreshToken::filter_by_id(stored.id)
-            .delete()
-            .exec(&mut db)
-            .await?;
+        if stored.revoked {
+            return Err(self.reuse_detected(&stored).await);
+        }
 
         let now = jiff::Timestamp::now();
         if stored.expires_at < now {
             bail!(ErrorKind::Unauthorized, "Refresh token expired");
         }
 
+        // Build the access token before touching the chain, so a failing
+        // claims hook leaves the presented token usable.
         let user = User::get_by_id(&mut db, &stored.user_id).await?;
-        let access_token = self.encode_access_token(&user)?;
-        let refresh_token = self.generate_refresh_token(user.id).await?;
+        let access_token = self.encode_access_token(&user).await?;
+
+        // The successor is issued first, then the token is revoked only if
+        // still live. Of two concurrent rotations, the one that finds it
+        // already revoked is a reuse, and by then both successors exist to
+        // be revoked with the family.
+        let refresh_token = self
+            .issue_refresh_token(stored.user_id, stored.family_id.clone(), Some(stored.id))
+            .await?;
+        let revoked = exec_count(
+            &mut db,
+            RefreshToken::filter_by_id(stored.id)
+                .filter(RefreshToken::fields().revoked().eq(false))
+                .update()
+                .revoked(true),
+        )
+        .await?;
+        if revoked == 0 {
+            return Err(self.reuse_detected(&stored).await);
+        }
 
         Ok(RotatedTokens {
             access_token,
@@ -136,3 +436,77 @@ impl TokenService {
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::domain::db::init_db;
+
+    async fn service() -> TokenService {
+        let policy = TokenPolicy {
+            access_ttl: SignedDuration::from_mins(15),
+            refresh_ttl: SignedDuration::from_hours(24),
+            issuer: "test".to_owned(),
+            audience: vec!["api".to_owned()],
+            leeway: SignedDuration::from_secs(30),
+            version_cache_ttl: SignedDuration::from_secs(5),
+        };
+        let db = init_db("sqlite::memory:").await.unwrap();
+        TokenService::new(db, JwtKeys::hmac("secret"), policy, None)
+    }
+
+    /// A valid token for `service`, after `edit` has changed its claims.
+    fn token_with(service: &TokenService, edit: impl FnOnce(&mut Claims)) -> String {
+        let now = jiff::Timestamp::now().as_second();
+        let mut claims = Claims {
+            sub: 1,
+            username: "alice".to_owned(),
+            ver: 0,
+            iss: "test".to_owned(),
+            aud: vec!["api".to_owned()],
+            exp: (now + 900) as usize,
+            nbf: now as usize,
+            iat: now as usize,
+            jti: Uuid::new_v4().to_string(),
+            custom: Map::new(),
+        };
+        edit(&mut claims);
+        let (header, key) = service.keys.signing();
+        encode(&header, &claims, key).unwrap()
+    }
+
+    fn nbf_in(secs: i64) -> impl FnOnce(&mut Claims) {
+        move |claims| claims.nbf = (jiff::Timestamp::now().as_second() + secs) as usize
+    }
+
+    #[tokio::test]
+    async fn test_nbf_within_leeway() {
+        let service = service().await;
+        let r = service.decode_access_token(&token_with(&service, nbf_in(10)));
+        assert!(r.is_ok(), "{r:?}");
+        assert!(
+            service
+                .decode_access_token(&token_with(&service, nbf_in(120)))
+                .is_err()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_wrong_issuer_rejected() {
+        let service = service().await;
+        let token = token_with(&service, |claims| claims.iss = "staging".to_owned());
+        assert!(service.decode_access_token(&token).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_wrong_audience_rejected() {
+        let service = service().await;
+        let token = token_with(&service, |claims| claims.aud = vec!["other".to_owned()]);
+        assert!(service.decode_access_token(&token).is_err());
+        let token = token_with(&service, |claims| {
+            claims.aud = vec!["other".to_owned(), "api".to_owned()]
+        });
+        let r = service.decode_access_token(&token);
+        assert!(r.is_ok(), "{r:?}");
+    }
+}
@@ -2,19 +2,32 @@ use toasty::Db;
 
 use crate::{
     bail,
-    domain::{db::Pk, model::User},
-    error::{ErrorKind, Result},
+    domain::{SessionService, TokenService, db::Pk, model::User},
+    error::{ErrorKind, OptionalExt, Result},
     util::password,
 };
 
 #[derive(Debug, Clone)]
 pub struct UserService {
     db: Db,
+    hasher: password::Hasher,
+    token: TokenService,
+    session: SessionService,
 }
 
 impl UserService {
-    pub fn new(db: Db) -> Self {
-        Self { db }
+    pub fn new(
+        db: Db,
+        hasher: password::Hasher,
+        token: TokenService,
+        session: SessionService,
+    ) -> Self {
+        Self {
+            db,
+            hasher,
+            token,
+            session,
+        }
     }
 
     fn db(&self) -> Db {
@@ -27,7 +40,7 @@ impl UserService {
             bail!(ErrorKind::AlreadyExists, "Username already exists");
         }
 
-        let hashed = password::hash(&password)?;
+        let hashed = self.hasher.hash(&password).await?;
         Ok(toasty::create!(User {
             username,
             password: hashed
@@ -38,7 +51,7 @@ impl UserService {
 
     pub async fn find_by_id(&self, id: Pk) -> Result<Option<User>> {
         let mut db = self.db();
-        Ok(User::get_by_id(&mut db, &id).await.ok())
+        User::get_by_id(&mut db, &id).await.optional()
     }
 
     pub async fn get_by_id(&self, id: Pk) -> Result<User> {
@@ -52,7 +65,11 @@ impl UserService {
     }
 
     async fn exists_by_username_inner(db: &mut Db, username: &str) -> Result<bool> {
-        Ok(User::filter_by_username(username).get(db).await.is_ok())
+        Ok(User::filter_by_username(username)
+            .get(db)
+            .await
+            .optional()?
+            .is_some())
     }
 
     pub async fn list(&self, page: u64, per_page: u64) -> Result<Vec<User>> {
@@ -85,35 +102,56 @@ impl UserService {
         Ok(User::get_by_id(&mut db, &id).await?)
     }
 
+    /// Replace the password after checking the old one, and sign the user
+    /// out everywhere but `keep_session_id`, so whoever knew the old
+    /// password loses their sessions and tokens.
     pub async fn change_password(
         &self,
         id: Pk,
         old_password: &str,
         new_password: &str,
+        keep_session_id: Option<&str>,
     ) -> Result<()> {
         let mut db = self.db();
         let mut user = User::get_by_id(&mut db, &id).await?;
 
-        if !password::verify(old_password, &user.password)? {
+        if !self.hasher.verify(old_password, &user.password).await? {
             bail!(ErrorKind::InvalidCredentials, "Invalid old password");
         }
 
-        let hashed = password::hash(new_password)?;
-        user.update().password(hashed).exec(&mut db).await?;
-        Ok(())
+        let hashed = self.hasher.hash(new_password).await?;
+        user.update()
+            .password(hashed)
+            .must_change_password(false)
+            .exec(&mut db)
+            .await?;
+        self.session.delete_others(id, keep_session_id).await
     }
 
-    pub async fn reset_password(&self, id: Pk, new_password: &str) -> Result<()> {
+    /// Set a new password without the old one and sign the user out
+    /// everywhere. With `force_change` the user is flagged to change it again
+    /// after their next login.
+    pub async fn reset_password(
+        &self,
+        id: Pk,
+        new_password: &str,
+        force_change: bool,
+    ) -> Result<()> {
         let mut db = self.db();
         let mut user = User::get_by_id(&mut db, &id).await?;
-        let hashed = password::hash(new_password)?;
-        user.update().password(hashed).exec(&mut db).await?;
-        Ok(())
+        let hashed = self.hasher.hash(new_password).await?;
+        user.update()
+            .password(hashed)
+            .must_change_password(force_change)
+            .exec(&mut db)
+            .await?;
+        self.session.delete_by_user_id(id).await
     }
 
     pub async fn delete(&self, id: Pk) -> Result<()> {
         let mut db = self.db();
         User::filter_by_id(id).delete().exec(&mut db).await?;
+        self.token.forget_token_version(id);
         Ok(())
     }
 }
@@ -32,6 +32,14 @@ pub enum ErrorKind {
     PermissionDenied,
     #[strum(serialize = "auth.invalid_credentials")]
     InvalidCredentials,
+    #[strum(serialize = "auth.csrf_failed")]
+    CsrfFailed,
+    #[strum(serialize = "auth.too_many_attempts")]
+    TooManyAttempts,
+    #[strum(serialize = "auth.account_locked")]
+    AccountLocked,
+    #[strum(serialize = "auth.password_change_required")]
+    PasswordChangeRequired,
 
     // ========================================================
     // Resource
@@ -46,6 +54,8 @@ pub enum ErrorKind {
     // ========================================================
     #[strum(serialize = "sys.config")]
     Config,
+    #[strum(serialize = "sys.unavailable")]
+    Unavailable,
     #[strum(serialize = "err.external")]
     External,
     #[strum(serialize = "err.internal")]
@@ -65,6 +75,10 @@ impl ErrorKind {
             Self::Forbidden => "Forbidden",
             Self::PermissionDenied => "Permission denied",
             Self::InvalidCredentials => "Invalid credentials",
+            Self::CsrfFailed => "CSRF validation failed",
+            Self::TooManyAttempts => "Too many attempts; try again later",
+            Self::AccountLocked => "Account temporarily locked",
+            Self::PasswordChangeRequired => "Password must be changed first",
             Self::ValidationFailed => "Validation failed",
             Self::BadRequest => "Bad request",
 
@@ -72,13 +86,14 @@ impl ErrorKind {
             Self::AlreadyExists => "Resource already exists",
 
             Self::Config => "Configuration error",
+            Self::Unavailable => "Service temporarily unavailable",
             Self::External => "External service error",
             Self::Internal => "Internal server error",
         }
     }
 
     pub fn is_internal_error(&self) -> bool {
-        matches!(self, Self::Config | Self::Internal)
+        matches!(self, Self::Config | Self::Unavailable | Self::Internal)
     }
 
     pub fn to_error(self) -> AppError {
@@ -120,6 +135,7 @@ pub struct AppError {
     kind: ErrorKind,
     message: Option<String>,
     errors: Option<Value>,
+    retry_after: Option<u64>,
     #[source]
     source: Option<BoxedDynError>,
 }
@@ -130,6 +146,7 @@ impl AppError {
             kind,
             message: None,
             errors: None,
+            retry_after: None,
             source: None,
         }
     }
@@ -160,6 +177,17 @@ impl AppError {
         self
     }
 
+    /// Tell the client how many seconds to wait before retrying, sent as
+    /// `Retry-After`.
+    pub fn with_retry_after(mut self, seconds: u64) -> Self {
+        self.retry_after = Some(seconds);
+        self
+    }
+
+    pub fn retry_after(&self) -> Option<u64> {
+        self.retry_after
+    }
+
     pub fn kind(&self) -> &ErrorKind {
         &self.kind
     }
@@ -249,6 +277,21 @@ where
     }
 }
 
+pub trait OptionalExt<T> {
+    /// Treat "record not found" as `None` while still propagating other errors.
+    fn optional(self) -> Result<Option<T>>;
+}
+
+impl<T> OptionalExt<T> for std::result::Result<T, toasty::Error> {
+    fn optional(self) -> Result<Option<T>> {
+        match self {
+            Ok(v) => Ok(Some(v)),
+            Err(e) if e.is_record_not_found() => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+}
+
 impl From<ErrorKind> for AppError {
     fn from(kind: ErrorKind) -> Self {
         kind.to_error()
@@ -276,7 +319,6 @@ register_errors! {
     std::io::Error                           => ErrorKind::Internal;
     serde_json::Error                        => ErrorKind::DataParse;
     config::ConfigError                      => ErrorKind::Config;
-    toasty::Error                            => ErrorKind::Internal;
     inquire::error::InquireError             => ErrorKind::Internal;
     axum::extract::rejection::PathRejection  => ErrorKind::InvalidParameter;
     axum::extract::rejection::QueryRejection => ErrorKind::InvalidParameter;
@@ -285,6 +327,48 @@ register_errors! {
     tokio::task::JoinError                   => ErrorKind::Internal;
 }
 
+/// Classify an ORM error so lookups and constraint violations surface as
+/// client errors instead of a generic 500.
+pub fn toasty_error_kind(err: &toasty::Error) -> ErrorKind {
+    if err.is_record_not_found() {
+        return ErrorKind::NotFound;
+    }
+    if err.is_connection_pool() || err.is_transaction_timeout() {
+        return ErrorKind::Unavailable;
+    }
+
+    let mut source: Option<&(dyn std::error::Error + 'static)> = Some(err);
+    while let Some(e) = source {
+        if e.is::<std::io::Error>() {
+            return ErrorKind::Unavailable;
+        }
+        source = e.source();
+    }
+
+    // Drivers don't expose a typed constraint error, so match on the
+    // messages postgres, sqlite and mysql use for unique violations.
+    let msg = err.to_string();
+    if msg.contains("duplicate key value violates unique constraint")
+        || msg.contains("UNIQUE constraint failed")
+        || msg.contains("Duplicate entry")
+    {
+        return ErrorKind::AlreadyExists;
+    }
+    if msg.contains("connection closed") {
+        return ErrorKind::Unavailable;
+    }
+
+    ErrorKind::Internal
+}
+
+impl From<toasty::Error> for AppError {
+    fn from(e: toasty::Error) -> Self {
+        let kind = toasty_error_kind(&e);
+        // Keep table and key details out of client-facing messages
+        kind.err_msg(e, kind.default_message())
+    }
+}
+
 #[macro_export]
 macro_rules! bail {
     ($msg:literal $(,)?) => {
@@ -300,3 +384,36 @@ macro_rules! bail {
         return ::core::result::Result::Err($crate::ErrorKind::msg($kind, format!($($arg)+)))
     };
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_toasty_error_kind() {
+        let not_found = toasty::Error::record_not_found("table=users key=1");
+        assert_eq!(toasty_error_kind(&not_found), ErrorKind::NotFound);
+
+        let refused = toasty::Error::driver_operation_failed(std::io::Error::new(
+            std::io::ErrorKind::ConnectionRefused,
+            "refused",
+        ));
+        assert_eq!(toasty_error_kind(&refused), ErrorKind::Unavailable);
+
+        let duplicate = toasty::Error::driver_operation_failed(std::fmt::Error).context(
+            toasty::Error::from_args(format_args!(
+                "duplicate key value violates unique constraint \"index_users_by_username\""
+            )),
+        );
+        assert_eq!(toasty_error_kind(&duplicate), ErrorKind::AlreadyExists);
+
+        let other = toasty::Error::from_args(format_args!("something went wrong"));
+        assert_eq!(toasty_error_kind(&other), ErrorKind::Internal);
+    }
+
+    #[test]
+    fn test_toasty_not_found_hides_details() {
+        let err = AppError::from(toasty::Error::record_not_found("table=users key=1"));
+        assert_eq!(err.message(), "Resource not found");
+    }
+}
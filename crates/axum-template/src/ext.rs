@@ -1,7 +1,14 @@
-use utoipa::openapi::OpenApi;
+use utoipa::openapi::{
+    OpenApi, Required,
+    path::{ParameterBuilder, ParameterIn},
+    schema::{Object, Type},
+};
 use utoipa_axum::router::OpenApiRouter;
 
-use crate::app::AppState;
+use crate::{
+    app::{AppState, extractor::PERM_EXTENSION},
+    domain::model::Perm,
+};
 
 pub trait OpenApiRouterExt {
     fn with_tags<T: Into<String>>(self, tags: impl IntoIterator<Item = T>) -> Self;
@@ -18,7 +25,7 @@ impl OpenApiRouterExt for OpenApiRouter<AppState> {
 }
 
 pub fn set_tags(openapi: &mut OpenApi, tags: Vec<String>) {
-    for (_, path_item) in openapi.paths.paths.iter_mut() {
+    for path_item in openapi.paths.paths.values_mut() {
         for method in [
             &mut path_item.get,
             &mut path_item.post,
@@ -36,6 +43,62 @@ pub fn set_tags(openapi: &mut OpenApi, tags: Vec<String>) {
     }
 }
 
+/// Document the CSRF header on every state-changing operation.
+pub fn document_csrf_header(openapi: &mut OpenApi, header_name: &str) {
+    for path_item in openapi.paths.paths.values_mut() {
+        for operation in [
+            &mut path_item.post,
+            &mut path_item.put,
+            &mut path_item.patch,
+            &mut path_item.delete,
+        ]
+        .into_iter()
+        .flatten()
+        {
+            let param = ParameterBuilder::new()
+                .name(header_name)
+                .parameter_in(ParameterIn::Header)
+                .required(Required::False)
+                .description(Some(
+                    "CSRF token from `GET /api/auth/csrf`. Required when \
+                     authenticating with the session cookie; ignored for Bearer tokens.",
+                ))
+                .schema(Some(Object::with_type(Type::String)))
+                .build();
+            operation
+                .parameters
+                .get_or_insert_with(Vec::new)
+                .push(param);
+        }
+    }
+}
+
+/// List the permission recorded on each operation as (method, path, perm).
+///
+/// Operations without a recorded permission are omitted.
+pub fn route_permissions(openapi: &OpenApi) -> Vec<(&'static str, String, Perm)> {
+    let mut perms = Vec::new();
+    for (path, path_item) in openapi.paths.paths.iter() {
+        for (method, operation) in [
+            ("GET", &path_item.get),
+            ("POST", &path_item.post),
+            ("PUT", &path_item.put),
+            ("PATCH", &path_item.patch),
+            ("DELETE", &path_item.delete),
+        ] {
+            let perm = operation
+                .as_ref()
+                .and_then(|op| op.extensions.as_ref())
+                .and_then(|ext| ext.get(PERM_EXTENSION))
+                .and_then(|v| serde_json::from_value(v.clone()).ok());
+            if let Some(perm) = perm {
+                perms.push((method, path.clone(), perm));
+            }
+        }
+    }
+    perms
+}
+
 /// (Endpoint, OpenApiRouter)
 pub struct EndpointRouter<S>(pub &'static str, pub OpenApiRouter<S>);
 
@@ -1,17 +1,23 @@
 use std::fs;
 
-use tracing_appender::rolling::{RollingFileAppender, Rotation};
+use tracing_appender::{
+    non_blocking::WorkerGuard,
+    rolling::{RollingFileAppender, Rotation},
+};
 use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
 
 use crate::config::{LogConfig, Paths};
 
-pub fn init_tracing(cfg: &LogConfig) {
+/// Install the global subscriber.
+///
+/// The file log is written by a background worker; keep the returned guard
+/// alive until shutdown, dropping it flushes any buffered lines.
+#[must_use = "dropping the guard stops the file log writer"]
+pub fn init_tracing(cfg: &LogConfig) -> WorkerGuard {
     let log_dir = Paths::log_dir();
     fs::create_dir_all(&log_dir).ok();
     let file_appender = RollingFileAppender::new(Rotation::DAILY, &log_dir, "access.log");
     let (non_blocking, guard) = tracing_appender::non_blocking(file_appender);
-    // keep worker alive for program lifetime
-    Box::leak(Box::new(guard));
 
     tracing_subscriber::registry()
         .with(
@@ -35,4 +41,6 @@ pub fn init_tracing(cfg: &LogConfig) {
                 .with_writer(non_blocking),
         )
         .init();
+
+    guard
 }
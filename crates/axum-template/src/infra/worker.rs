@@ -0,0 +1,83 @@
+use std::{
+    collections::BTreeMap,
+    sync::{Arc, Mutex},
+};
+
+use serde::Serialize;
+use tokio::task::JoinHandle;
+use tokio_util::{sync::CancellationToken, task::TaskTracker};
+use utoipa::ToSchema;
+
+use crate::error::Result;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, ToSchema)]
+#[serde(rename_all = "snake_case", tag = "state", content = "error")]
+pub enum WorkerState {
+    Running,
+    Finished,
+    Failed(String),
+}
+
+/// Registry of long-running background tasks, reported by the readiness probe.
+///
+/// Tasks get the registry's shutdown token and are expected to return soon
+/// after it is cancelled; [`join`](Self::join) waits for them to do so.
+#[derive(Debug, Clone, Default)]
+pub struct Workers {
+    states: Arc<Mutex<BTreeMap<&'static str, WorkerState>>>,
+    shutdown: CancellationToken,
+    tracker: TaskTracker,
+}
+
+impl Workers {
+    /// Spawn the task made by `task` under `name`, recording whether it is
+    /// still running, has returned, or failed (including by panicking).
+    pub fn spawn<F, Fut>(&self, name: &'static str, task: F) -> JoinHandle<()>
+    where
+        F: FnOnce(CancellationToken) -> Fut,
+        Fut: Future<Output = Result<()>> + Send + 'static,
+    {
+        self.set(name, WorkerState::Running);
+
+        let workers = self.clone();
+        let handle = tokio::spawn(task(self.shutdown.clone()));
+        self.tracker.spawn(async move {
+            let state = match handle.await {
+                Ok(Ok(())) => WorkerState::Finished,
+                Ok(Err(err)) => {
+                    tracing::error!("Worker {} failed: {}", name, err);
+                    WorkerState::Failed(err.message().to_owned())
+                }
+                Err(err) => {
+                    tracing::error!("Worker {} panicked: {}", name, err);
+                    WorkerState::Failed("panicked".to_owned())
+                }
+            };
+            workers.set(name, state);
+        })
+    }
+
+    /// Cancelled when the server shuts down.
+    pub fn shutdown_token(&self) -> &CancellationToken {
+        &self.shutdown
+    }
+
+    /// Wait for every spawned task to return; call once the shutdown token
+    /// is cancelled.
+    pub async fn join(&self) {
+        self.tracker.close();
+        self.tracker.wait().await;
+    }
+
+    pub fn states(&self) -> BTreeMap<&'static str, WorkerState> {
+        self.lock().clone()
+    }
+
+    fn set(&self, name: &'static str, state: WorkerState) {
+        self.lock().insert(name, state);
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, BTreeMap<&'static str, WorkerState>> {
+        self.states.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
@@ -1 +1,3 @@
 pub mod password;
+pub mod token;
+pub mod totp;
@@ -1,41 +1,275 @@
 //! Password hashing utilities using Argon2
 
-use argon2::{Argon2, PasswordHash, PasswordHasher, PasswordVerifier, password_hash::SaltString};
+use std::{fmt, sync::Arc, time::Duration};
+
+use argon2::{
+    Algorithm, Argon2, KeyId, Params, ParamsBuilder, PasswordHash, PasswordHasher,
+    PasswordVerifier, Version, password_hash::SaltString,
+};
 use password_hash::rand_core;
+use sha2::{Digest, Sha256};
+use tokio::sync::Semaphore;
+
+use crate::{
+    error::{ErrorKind, Result},
+    util::token,
+};
+
+/// Hashes and verifies passwords with the configured Argon2 variant, costs
+/// and optional pepper.
+///
+/// The pepper is Argon2's secret input and is never stored. Hashes made with
+/// it carry a `keyid` derived from it, so hashes from before it was
+/// configured still verify (and are flagged by [`needs_rehash`]).
+///
+/// Hashing runs on the blocking thread pool, at most a fixed number at a
+/// time; see [`with_concurrency`].
+///
+/// [`needs_rehash`]: Self::needs_rehash
+/// [`with_concurrency`]: Self::with_concurrency
+#[derive(Clone)]
+pub struct Hasher {
+    algorithm: Algorithm,
+    params: Params,
+    pepper: Option<Arc<[u8]>>,
+    /// Hash of a random password with these parameters, checked when a login
+    /// names no user so it takes as long as a wrong password.
+    dummy_hash: Arc<str>,
+    permits: Arc<Semaphore>,
+    queue_timeout: Duration,
+}
+
+impl Hasher {
+    pub fn new(
+        algorithm: Algorithm,
+        m_cost: u32,
+        t_cost: u32,
+        p_cost: u32,
+        pepper: Option<Vec<u8>>,
+    ) -> Result<Self> {
+        let mut builder = ParamsBuilder::new();
+        builder.m_cost(m_cost).t_cost(t_cost).p_cost(p_cost);
+        if let Some(pepper) = &pepper {
+            builder.keyid(pepper_id(pepper));
+        }
+        let params = builder
+            .build()
+            .map_err(|e| ErrorKind::Config.msg(format!("Invalid Argon2 parameters: {e}")))?;
+
+        let mut hasher = Self {
+            algorithm,
+            params,
+            pepper: pepper.map(Arc::from),
+            dummy_hash: Arc::from(""),
+            permits: Arc::new(Semaphore::new(
+                std::thread::available_parallelism().map_or(1, |n| n.get()),
+            )),
+            queue_timeout: Duration::from_secs(1),
+        };
+        hasher.dummy_hash = hasher.hash_blocking(&token::random_hex(16))?.into();
+        Ok(hasher)
+    }
+
+    /// Compute at most `max_concurrent` hashes at once (default: one per
+    /// CPU). Callers wait up to `queue_timeout` for a free slot and are then
+    /// refused with `Unavailable`.
+    pub fn with_concurrency(mut self, max_concurrent: usize, queue_timeout: Duration) -> Self {
+        self.permits = Arc::new(Semaphore::new(max_concurrent));
+        self.queue_timeout = queue_timeout;
+        self
+    }
+
+    /// Hash a password using Argon2
+    pub async fn hash(&self, password: &str) -> Result<String> {
+        let password = password.to_owned();
+        self.offload(move |hasher| hasher.hash_blocking(&password))
+            .await
+    }
+
+    /// Verify a password against a hash, using the parameters stored in it
+    pub async fn verify(&self, password: &str, hash: &str) -> Result<bool> {
+        let (password, hash) = (password.to_owned(), hash.to_owned());
+        self.offload(move |hasher| hasher.verify_blocking(&password, &hash))
+            .await
+    }
+
+    /// Verify against the dummy hash, which never matches. Same cost as
+    /// [`verify`](Self::verify) against a current hash.
+    pub async fn verify_dummy(&self, password: &str) -> Result<bool> {
+        self.verify(password, &self.dummy_hash).await.map(|_| false)
+    }
+
+    fn hash_blocking(&self, password: &str) -> Result<String> {
+        let salt = SaltString::generate(rand_core::OsRng);
+        let hash = self
+            .argon2(self.pepper.is_some())?
+            .hash_password(password.as_bytes(), &salt)
+            .map_err(|e| ErrorKind::Internal.msg(format!("Password hashing failed: {}", e)))?;
+        Ok(hash.to_string())
+    }
+
+    fn verify_blocking(&self, password: &str, hash: &str) -> Result<bool> {
+        let parsed_hash = parse(hash)?;
+        let keyid = Params::try_from(&parsed_hash)
+            .map_err(|e| ErrorKind::Internal.msg(format!("Invalid password hash: {}", e)))?
+            .keyid()
+            .to_vec();
+
+        let peppered = !keyid.is_empty();
+        if peppered && keyid != self.params.keyid() {
+            // Fail like a wrong password, at the same cost, so the login
+            // response doesn't single the account out.
+            tracing::warn!("Password hash was made with a different pepper");
+            return self
+                .verify_blocking(password, &self.dummy_hash)
+                .map(|_| false);
+        }
+        Ok(self
+            .argon2(peppered)?
+            .verify_password(password.as_bytes(), &parsed_hash)
+            .is_ok())
+    }
+
+    /// Whether `hash` was made with other parameters or pepper than new
+    /// hashes would be, and should be replaced once the password is known.
+    pub fn needs_rehash(&self, hash: &str) -> bool {
+        let Ok(parsed_hash) = PasswordHash::new(hash) else {
+            return true;
+        };
+        let Ok(params) = Params::try_from(&parsed_hash) else {
+            return true;
+        };
+        Algorithm::try_from(parsed_hash.algorithm) != Ok(self.algorithm)
+            || parsed_hash.version != Some(Version::V0x13.into())
+            || params.m_cost() != self.params.m_cost()
+            || params.t_cost() != self.params.t_cost()
+            || params.p_cost() != self.params.p_cost()
+            || params.keyid() != self.params.keyid()
+    }
 
-use crate::error::{ErrorKind, Result};
+    /// Run `f` on the blocking pool once a hashing slot is free, so Argon2
+    /// never stalls the async workers.
+    async fn offload<T: Send + 'static>(
+        &self,
+        f: impl FnOnce(&Self) -> Result<T> + Send + 'static,
+    ) -> Result<T> {
+        let permit = tokio::time::timeout(self.queue_timeout, self.permits.clone().acquire_owned())
+            .await
+            .map_err(|_| {
+                ErrorKind::Unavailable
+                    .msg("Too many password checks in progress; try again shortly")
+                    .with_retry_after(1)
+            })?
+            .expect("the hashing semaphore is never closed");
+        let hasher = self.clone();
+        tokio::task::spawn_blocking(move || {
+            let _permit = permit;
+            f(&hasher)
+        })
+        .await
+        .map_err(|e| ErrorKind::Internal.msg(format!("Password hashing task failed: {e}")))?
+    }
 
-/// Hash a password using Argon2
-pub fn hash(password: &str) -> Result<String> {
-    let salt = SaltString::generate(rand_core::OsRng);
-    let argon2 = Argon2::default();
-    let hash = argon2
-        .hash_password(password.as_bytes(), &salt)
-        .map_err(|e| ErrorKind::Internal.msg(format!("Password hashing failed: {}", e)))?;
-    Ok(hash.to_string())
+    /// The configured context, with the pepper as secret if `peppered`.
+    fn argon2(&self, peppered: bool) -> Result<Argon2<'_>> {
+        match &self.pepper {
+            Some(pepper) if peppered => {
+                Argon2::new_with_secret(pepper, self.algorithm, Version::V0x13, self.params.clone())
+                    .map_err(|e| ErrorKind::Config.msg(format!("Invalid password pepper: {e}")))
+            }
+            _ => Ok(Argon2::new(
+                self.algorithm,
+                Version::V0x13,
+                self.params.clone(),
+            )),
+        }
+    }
 }
 
-/// Verify a password against a hash
-pub fn verify(password: &str, hash: &str) -> Result<bool> {
-    let parsed_hash = PasswordHash::new(hash)
-        .map_err(|e| ErrorKind::Internal.msg(format!("Invalid password hash: {}", e)))?;
+impl fmt::Debug for Hasher {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Hasher")
+            .field("algorithm", &self.algorithm)
+            .field("params", &self.params)
+            .field("peppered", &self.pepper.is_some())
+            .field("queue_timeout", &self.queue_timeout)
+            .finish_non_exhaustive()
+    }
+}
 
-    let argon2 = Argon2::default();
-    Ok(argon2
-        .verify_password(password.as_bytes(), &parsed_hash)
-        .is_ok())
+fn parse(hash: &str) -> Result<PasswordHash<'_>> {
+    PasswordHash::new(hash)
+        .map_err(|e| ErrorKind::Internal.msg(format!("Invalid password hash: {}", e)))
+}
+
+/// Tag stored in peppered hashes: the first 8 bytes of the pepper's SHA-256.
+fn pepper_id(pepper: &[u8]) -> KeyId {
+    KeyId::new(&Sha256::digest(pepper)[..KeyId::MAX_LEN]).expect("8 bytes fit a key id")
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
-    #[test]
-    fn test_hash_and_verify() {
+    /// Cheap parameters so the tests stay fast.
+    fn hasher(m_cost: u32, pepper: Option<&str>) -> Hasher {
+        let pepper = pepper.map(|p| p.as_bytes().to_vec());
+        Hasher::new(Algorithm::Argon2id, m_cost, 1, 1, pepper).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_hash_and_verify() {
+        let hasher = hasher(64, None);
         let password = "test_password_123";
 
-        let hash = hash(password).unwrap();
-        assert!(verify(password, &hash).unwrap());
-        assert!(!verify("wrong_password", &hash).unwrap());
+        let hash = hasher.hash(password).await.unwrap();
+        assert!(hasher.verify(password, &hash).await.unwrap());
+        assert!(!hasher.verify("wrong_password", &hash).await.unwrap());
+        assert!(!hasher.needs_rehash(&hash));
+    }
+
+    #[tokio::test]
+    async fn test_dummy_hash_uses_real_parameters() {
+        let hasher = hasher(64, Some("pepper"));
+        assert!(!hasher.needs_rehash(&hasher.dummy_hash));
+        assert!(!hasher.verify_dummy("x").await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_outdated_parameters_need_rehash() {
+        let hash = hasher(64, None).hash("secret").await.unwrap();
+        let stronger = hasher(128, None);
+        assert!(stronger.verify("secret", &hash).await.unwrap());
+        assert!(stronger.needs_rehash(&hash));
+        assert!(!stronger.needs_rehash(&stronger.hash("secret").await.unwrap()));
+    }
+
+    #[tokio::test]
+    async fn test_pepper() {
+        let plain = hasher(64, None).hash("secret").await.unwrap();
+        let peppered = hasher(64, Some("pepper"));
+
+        // Hashes from before the pepper still verify, and get upgraded.
+        assert!(peppered.verify("secret", &plain).await.unwrap());
+        assert!(peppered.needs_rehash(&plain));
+
+        let hash = peppered.hash("secret").await.unwrap();
+        assert!(peppered.verify("secret", &hash).await.unwrap());
+        assert!(!peppered.needs_rehash(&hash));
+        let other = hasher(64, Some("other"));
+        assert!(!other.verify("secret", &hash).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_overload_fails_fast() {
+        let hasher = hasher(64, None).with_concurrency(1, Duration::from_millis(10));
+        let busy = hasher.permits.clone().acquire_owned().await.unwrap();
+
+        let err = hasher.hash("secret").await.unwrap_err();
+        assert_eq!(*err.kind(), ErrorKind::Unavailable);
+        assert_eq!(err.retry_after(), Some(1));
+
+        drop(busy);
+        assert!(hasher.hash("secret").await.is_ok());
     }
 }
@@ -0,0 +1,25 @@
+//! Random tokens, digests and constant-time comparison
+
+use rand::RngCore;
+use sha2::{Digest, Sha256};
+use subtle::ConstantTimeEq;
+
+/// Hex-encode `bytes` bytes from the OS-seeded CSPRNG.
+pub fn random_hex(bytes: usize) -> String {
+    let mut buf = vec![0u8; bytes];
+    rand::rng().fill_bytes(&mut buf);
+    hex::encode(buf)
+}
+
+/// Hex SHA-256 of a token, the form bearer secrets are stored in.
+///
+/// Tokens carry enough entropy that an unkeyed hash can't be brute-forced,
+/// and the digest of a presented token can be looked up directly.
+pub fn digest(token: &str) -> String {
+    hex::encode(Sha256::digest(token.as_bytes()))
+}
+
+/// Compare secrets without leaking the position of the first mismatch.
+pub fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.ct_eq(b).into()
+}
@@ -0,0 +1,276 @@
+//! Shared harness for integration tests.
+//!
+//! Every [`TestApp`] runs the full router on its own in-memory SQLite
+//! database with the default roles seeded, so tests never depend on the
+//! machine's config or on each other.
+
+#![allow(dead_code)]
+
+use anyhow::{Context, Result};
+use axum::{
+    Router,
+    body::{Body, Bytes},
+    http::{HeaderMap, Method, Request, StatusCode, header},
+};
+use axum_template::{
+    app::{router::create_router, state::AppState},
+    cli::command_impl::init_rbac,
+    config::{AppConfig, RawAppConfig},
+    domain::{Services, model::Perm},
+};
+use serde::{Serialize, de::DeserializeOwned};
+use serde_json::{Value, json};
+use tower::ServiceExt;
+
+pub const PASSWORD: &str = "password123";
+
+/// Credentials attached to a request.
+#[derive(Debug, Clone)]
+pub enum Auth {
+    None,
+    /// The `Cookie` header for a session, and the CSRF token issued for it.
+    Cookie {
+        cookie: String,
+        csrf: String,
+    },
+    /// A JWT access token.
+    Bearer(String),
+}
+
+pub struct TestApp {
+    pub state: AppState,
+    router: Router,
+}
+
+impl TestApp {
+    pub async fn new() -> Result<Self> {
+        Self::with_config(|_| {}).await
+    }
+
+    /// Boot the app after adjusting the default config, e.g. to shorten TTLs.
+    pub async fn with_config(f: impl FnOnce(&mut RawAppConfig)) -> Result<Self> {
+        let mut raw = RawAppConfig::default();
+        raw.database.url = "sqlite::memory:".to_owned();
+        // Production Argon2 costs make every login take most of a second in
+        // debug builds; tests that measure hashing restore them.
+        raw.password.m_cost = 256;
+        raw.password.t_cost = 1;
+        f(&mut raw);
+
+        Self::from_state(AppState::new(AppConfig::new(raw)).await?).await
+    }
+
+    /// Boot the app on a prepared state, e.g. one with replaced services.
+    pub async fn from_state(state: AppState) -> Result<Self> {
+        init_rbac(state.srv()).await?;
+        let router = create_router(state.clone()).await?;
+
+        Ok(Self { state, router })
+    }
+
+    pub fn srv(&self) -> &Services {
+        self.state.srv()
+    }
+
+    /// Create a user holding exactly `perms`, granted through a dedicated role.
+    pub async fn create_user(&self, username: &str, perms: &[Perm]) -> Result<i64> {
+        let user = self
+            .srv()
+            .user
+            .create(username.to_owned(), PASSWORD.to_owned())
+            .await?;
+
+        if !perms.is_empty() {
+            let role = self
+                .srv()
+                .role
+                .create(format!("{username}-role"), None, perms)
+                .await?;
+            self.srv().role.assign_to_user(user.id, role.id).await?;
+        }
+
+        Ok(user.id)
+    }
+
+    /// Log in through `POST /api/auth/login`, then fetch a CSRF token from
+    /// `GET /api/auth/csrf`.
+    pub async fn login_cookie(&self, username: &str) -> Result<Auth> {
+        let resp = self
+            .post("/api/auth/login", &Auth::None, &credentials(username))
+            .await?
+            .expect(StatusCode::OK)?;
+        let session = set_cookie(&resp, &self.state.cfg().auth.session.cookie_name)
+            .context("login did not set the session cookie")?;
+
+        let auth = Auth::Cookie {
+            cookie: session.clone(),
+            csrf: String::new(),
+        };
+        let resp = self
+            .get("/api/auth/csrf", &auth)
+            .await?
+            .expect(StatusCode::OK)?;
+        let body: Value = resp.json()?;
+        let csrf = body["token"]
+            .as_str()
+            .context("no CSRF token returned")?
+            .to_owned();
+
+        // Double-submit mode also needs the token cookie sent back.
+        let cookie = match set_cookie(&resp, &self.state.cfg().auth.csrf.cookie_name) {
+            Some(token_cookie) => format!("{session}; {token_cookie}"),
+            None => session,
+        };
+
+        Ok(Auth::Cookie { cookie, csrf })
+    }
+
+    /// Log in through `POST /api/auth/jwt/login` and return the access token.
+    pub async fn login_jwt(&self, username: &str) -> Result<Auth> {
+        let body = self.login_tokens(username).await?;
+
+        let token = body["access_token"]
+            .as_str()
+            .context("login did not return an access token")?;
+
+        Ok(Auth::Bearer(token.to_owned()))
+    }
+
+    /// The full `POST /api/auth/jwt/login` response, refresh token included.
+    pub async fn login_tokens(&self, username: &str) -> Result<Value> {
+        self.post("/api/auth/jwt/login", &Auth::None, &credentials(username))
+            .await?
+            .expect(StatusCode::OK)?
+            .json()
+    }
+
+    /// Log in through `login_path` as a user with a second factor, returning
+    /// the MFA challenge.
+    pub async fn login_challenge(&self, login_path: &str, username: &str) -> Result<Value> {
+        self.post(login_path, &Auth::None, &credentials(username))
+            .await?
+            .expect(StatusCode::ACCEPTED)?
+            .json()
+    }
+
+    /// Exchange `refresh_token` through `POST /api/auth/jwt/refresh`.
+    pub async fn refresh(&self, refresh_token: &Value) -> Result<TestResponse> {
+        self.post(
+            "/api/auth/jwt/refresh",
+            &Auth::None,
+            &json!({ "refresh_token": refresh_token }),
+        )
+        .await
+    }
+
+    pub async fn get(&self, uri: &str, auth: &Auth) -> Result<TestResponse> {
+        self.send(Method::GET, uri, auth, None::<&()>).await
+    }
+
+    pub async fn delete(&self, uri: &str, auth: &Auth) -> Result<TestResponse> {
+        self.send(Method::DELETE, uri, auth, None::<&()>).await
+    }
+
+    pub async fn post<B: Serialize>(
+        &self,
+        uri: &str,
+        auth: &Auth,
+        body: &B,
+    ) -> Result<TestResponse> {
+        self.send(Method::POST, uri, auth, Some(body)).await
+    }
+
+    pub async fn put<B: Serialize>(
+        &self,
+        uri: &str,
+        auth: &Auth,
+        body: &B,
+    ) -> Result<TestResponse> {
+        self.send(Method::PUT, uri, auth, Some(body)).await
+    }
+
+    pub async fn send<B: Serialize>(
+        &self,
+        method: Method,
+        uri: &str,
+        auth: &Auth,
+        body: Option<&B>,
+    ) -> Result<TestResponse> {
+        let mut builder = Request::builder().method(method).uri(uri);
+        builder = match auth {
+            Auth::None => builder,
+            Auth::Cookie { cookie, csrf } => builder
+                .header(header::COOKIE, cookie)
+                .header(&self.state.cfg().auth.csrf.header_name, csrf),
+            Auth::Bearer(token) => builder.header(header::AUTHORIZATION, format!("Bearer {token}")),
+        };
+
+        let request = match body {
+            Some(body) => builder
+                .header(header::CONTENT_TYPE, "application/json")
+                .body(Body::from(serde_json::to_vec(body)?))?,
+            None => builder.body(Body::empty())?,
+        };
+
+        self.request(request).await
+    }
+
+    /// Send a hand-built request, e.g. one needing extra headers.
+    pub async fn request(&self, request: Request<Body>) -> Result<TestResponse> {
+        let response = self.router.clone().oneshot(request).await?;
+        let status = response.status();
+        let headers = response.headers().clone();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await?;
+
+        Ok(TestResponse {
+            status,
+            headers,
+            body,
+        })
+    }
+}
+
+#[derive(Debug)]
+pub struct TestResponse {
+    pub status: StatusCode,
+    pub headers: HeaderMap,
+    pub body: Bytes,
+}
+
+impl TestResponse {
+    /// Fail with the response body when the status is not `status`.
+    pub fn expect(self, status: StatusCode) -> Result<Self> {
+        anyhow::ensure!(
+            self.status == status,
+            "expected {status}, got {}: {}",
+            self.status,
+            String::from_utf8_lossy(&self.body)
+        );
+        Ok(self)
+    }
+
+    pub fn json<T: DeserializeOwned>(&self) -> Result<T> {
+        Ok(serde_json::from_slice(&self.body)?)
+    }
+
+    /// The `code` field of an error response.
+    pub fn error_code(&self) -> Result<String> {
+        let body: Value = self.json()?;
+        Ok(body["code"].as_str().unwrap_or_default().to_owned())
+    }
+}
+
+/// The `name=value` pair of the `Set-Cookie` header for cookie `name`.
+pub fn set_cookie(resp: &TestResponse, name: &str) -> Option<String> {
+    resp.headers
+        .get_all(header::SET_COOKIE)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .filter_map(|v| v.split(';').next())
+        .find(|pair| pair.starts_with(&format!("{name}=")))
+        .map(str::to_owned)
+}
+
+fn credentials(username: &str) -> Value {
+    json!({ "username": username, "password": PASSWORD })
+}
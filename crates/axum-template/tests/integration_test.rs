@@ -1,55 +1,1995 @@
-use anyhow::Result;
+mod common;
+
+use std::{
+    collections::HashMap,
+    sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering},
+    },
+    time::{Duration, Instant},
+};
+
+use anyhow::{Context, Result};
 use axum::{
-    Router,
     body::Body,
-    http::{Request, StatusCode},
+    http::{Method, Request, StatusCode, header},
 };
 use axum_template::{
-    app::{router::create_router, state::AppState},
-    config::AppConfig,
+    ErrorKind,
+    app::{router::api_router, state::AppState},
+    config::{
+        AppConfig, AppEnv, CookieSameSite, CsrfMode, JwtAlgorithm, JwtKeyConfig, PasswordAlgorithm,
+        PasswordConfig, RawAppConfig,
+    },
+    domain::{
+        ClaimsHook,
+        jwt_keys::generate_pem,
+        model::{LoginThrottle, Perm, RefreshToken, Session, User},
+    },
+    ext::route_permissions,
+    infra::worker::WorkerState,
+    util::{password, totp},
 };
-use serde_json::{Value, json};
-use tower::ServiceExt;
+use common::{Auth, TestApp, TestResponse};
+use serde_json::{Map, Value, json};
+
+#[tokio::test]
+async fn test_root_endpoint() -> Result<()> {
+    let app = TestApp::new().await?;
 
-async fn test_router() -> Result<Router> {
-    let cfg = AppConfig::load()?;
-    let app_state = AppState::new(cfg).await?;
-    Ok(create_router(app_state).await?)
+    app.get("/api", &Auth::None).await?.expect(StatusCode::OK)?;
+    Ok(())
 }
 
 #[tokio::test]
-async fn test_root_endpoint() -> Result<()> {
-    let app = test_router().await?;
+async fn test_hello_endpoint() -> Result<()> {
+    let app = TestApp::new().await?;
 
-    let request = Request::builder()
-        .method("GET")
-        .uri("/api")
-        .body(Body::empty())
-        .unwrap();
+    let body: Value = app
+        .post("/api/hello", &Auth::None, &json!({"name": "World"}))
+        .await?
+        .expect(StatusCode::OK)?
+        .json()?;
+
+    assert_eq!(body["message"], "Hello World");
+    Ok(())
+}
 
-    let response = app.oneshot(request).await.unwrap();
+#[tokio::test]
+async fn test_me_requires_auth() -> Result<()> {
+    let app = TestApp::new().await?;
 
-    assert_eq!(response.status(), StatusCode::OK);
+    let resp = app.get("/api/auth/me", &Auth::None).await?;
+    assert_eq!(resp.status, StatusCode::UNAUTHORIZED);
     Ok(())
 }
 
 #[tokio::test]
-async fn test_hello_endpoint() -> Result<()> {
-    let app = test_router().await?;
+async fn test_login_by_cookie_and_jwt() -> Result<()> {
+    let app = TestApp::new().await?;
+    app.create_user("alice", &[Perm::UserRead]).await?;
 
-    let request = Request::builder()
-        .method("POST")
-        .uri("/api/hello")
-        .header("content-type", "application/json")
-        .body(Body::from(json!({"name": "World"}).to_string()))?;
+    for auth in [
+        app.login_cookie("alice").await?,
+        app.login_jwt("alice").await?,
+    ] {
+        let body: Value = app
+            .get("/api/auth/me", &auth)
+            .await?
+            .expect(StatusCode::OK)?
+            .json()?;
+        assert_eq!(body["user"]["username"], "alice");
+        assert_eq!(body["permissions"], json!(["user:read"]));
+    }
+    Ok(())
+}
 
-    let response = app.oneshot(request).await?;
+#[tokio::test]
+async fn test_login_invalid_credentials() -> Result<()> {
+    let app = TestApp::new().await?;
+    app.create_user("alice", &[]).await?;
 
-    assert_eq!(response.status(), StatusCode::OK);
+    let resp = app
+        .post(
+            "/api/auth/login",
+            &Auth::None,
+            &json!({"username": "alice", "password": "wrong-password"}),
+        )
+        .await?;
+    assert_eq!(resp.status, StatusCode::FORBIDDEN);
+    assert_eq!(resp.error_code()?, "auth.invalid_credentials");
+    Ok(())
+}
 
-    let body = axum::body::to_bytes(response.into_body(), usize::MAX).await?;
-    let body: Value = serde_json::from_slice(&body)?;
+#[tokio::test]
+async fn test_unknown_username_indistinguishable() -> Result<()> {
+    let app = TestApp::with_config(|cfg| {
+        cfg.password = PasswordConfig::default();
+        cfg.auth.throttle.user.free_attempts = 100;
+    })
+    .await?;
+    app.create_user("alice", &[]).await?;
 
-    assert_eq!(body["message"], "Hello World");
+    let login = async |username: &str| {
+        app.post(
+            "/api/auth/login",
+            &Auth::None,
+            &json!({"username": username, "password": "wrong-password"}),
+        )
+        .await
+    };
+    let known = login("alice").await?;
+    let unknown = login("nobody").await?;
+    assert_eq!(known.status, StatusCode::FORBIDDEN);
+    assert_eq!(known.status, unknown.status);
+    assert_eq!(known.body, unknown.body);
+
+    // Both run one Argon2 verification; without the dummy hash the unknown
+    // username would come back in a fraction of the time.
+    let fastest = async |username: &str| -> Result<Duration> {
+        let mut best = Duration::MAX;
+        for _ in 0..5 {
+            let start = Instant::now();
+            let auth = app.srv().auth.authenticate(username, "wrong", None).await?;
+            best = best.min(start.elapsed());
+            assert!(auth.is_none());
+        }
+        Ok(best)
+    };
+    let known = fastest("alice").await?;
+    let unknown = fastest("nobody").await?;
+    let ratio = unknown.as_secs_f64() / known.as_secs_f64();
+    assert!((0.5..2.0).contains(&ratio), "{unknown:?} vs {known:?}");
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_outdated_password_hash_upgraded_on_login() -> Result<()> {
+    let pepper = std::env::temp_dir().join(format!("axum-template-{}", uuid::Uuid::new_v4()));
+    std::fs::write(&pepper, "s3cret-pepper\n")?;
+    let app = TestApp::with_config(|cfg| {
+        cfg.password.m_cost = 1024;
+        cfg.password.t_cost = 1;
+        cfg.password.pepper_file = Some(pepper.clone());
+    })
+    .await?;
+    std::fs::remove_file(&pepper)?;
+    let id = app.create_user("alice", &[]).await?;
+
+    // As if hashed before the costs were changed and the pepper added.
+    let old = password::Hasher::new(PasswordAlgorithm::Argon2i.into(), 64, 2, 1, None)?;
+    let mut db = app.state.db().clone();
+    let mut user = User::get_by_id(&mut db, &id).await?;
+    user.update()
+        .password(old.hash(common::PASSWORD).await?)
+        .exec(&mut db)
+        .await?;
+
+    app.login_jwt("alice").await?;
+    let user = User::get_by_id(&mut db, &id).await?;
+    assert!(
+        user.password
+            .starts_with("$argon2id$v=19$m=1024,t=1,p=1,keyid="),
+        "{}",
+        user.password
+    );
+    app.login_jwt("alice").await?;
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_password_hashing_overload_returns_503() -> Result<()> {
+    // Production costs, so the first login is still hashing when the
+    // second arrives.
+    let app = TestApp::with_config(|cfg| {
+        cfg.password = PasswordConfig::default();
+        cfg.password.max_concurrent = Some(1);
+        cfg.password.queue_timeout_ms = 0;
+    })
+    .await?;
+    app.create_user("alice", &[]).await?;
+    app.create_user("bob", &[]).await?;
+
+    // While one login holds the only hashing slot, the other is refused
+    // at once rather than queued.
+    let login = async |username: &str| {
+        app.post(
+            "/api/auth/jwt/login",
+            &Auth::None,
+            &json!({"username": username, "password": common::PASSWORD}),
+        )
+        .await
+    };
+    let (a, b) = tokio::join!(login("alice"), login("bob"));
+    let (a, b) = (a?, b?);
+    let mut statuses = [a.status, b.status];
+    statuses.sort();
+    assert_eq!(statuses, [StatusCode::OK, StatusCode::SERVICE_UNAVAILABLE]);
+    let refused = if a.status == StatusCode::OK { b } else { a };
+    assert_eq!(refused.error_code()?, ErrorKind::Unavailable.code());
+    assert_eq!(refused.headers[header::RETRY_AFTER], "1");
+
+    app.login_jwt("bob").await?;
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_session_cookie_attributes() -> Result<()> {
+    let app = TestApp::new().await?;
+    app.create_user("alice", &[]).await?;
+
+    let resp = app
+        .post(
+            "/api/auth/login",
+            &Auth::None,
+            &json!({"username": "alice", "password": common::PASSWORD}),
+        )
+        .await?
+        .expect(StatusCode::OK)?;
+    let cookie = resp.headers[header::SET_COOKIE].to_str()?;
+    for attr in [
+        "HttpOnly",
+        "Secure",
+        "SameSite=Lax",
+        "Path=/",
+        "Max-Age=86400",
+    ] {
+        assert!(cookie.contains(attr), "{attr} missing from {cookie}");
+    }
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_session_cookie_refreshed_on_extend() -> Result<()> {
+    let app = TestApp::new().await?;
+    app.create_user("alice", &[]).await?;
+    let auth = app.login_cookie("alice").await?;
+
+    // Not due for extension yet: no cookie is re-issued.
+    let resp = app
+        .get("/api/auth/me", &auth)
+        .await?
+        .expect(StatusCode::OK)?;
+    assert!(!resp.headers.contains_key(header::SET_COOKIE));
+
+    // Last activity older than the touch interval.
+    let Auth::Cookie { cookie: pair, .. } = &auth else {
+        unreachable!()
+    };
+    let mut db = app.state.db().clone();
+    let seen = jiff::Timestamp::now() - jiff::SignedDuration::from_mins(2);
+    session_of(&app, &auth)
+        .await?
+        .update()
+        .last_seen_at(seen)
+        .exec(&mut db)
+        .await?;
+
+    let resp = app
+        .get("/api/auth/me", &auth)
+        .await?
+        .expect(StatusCode::OK)?;
+    let cookie = resp.headers[header::SET_COOKIE].to_str()?;
+    assert!(cookie.starts_with(&format!("{pair};")));
+    assert!(cookie.contains("Max-Age=86400"));
+    assert!(session_of(&app, &auth).await?.last_seen_at > seen);
+
+    let resp = app
+        .post("/api/auth/logout", &auth, &json!({}))
+        .await?
+        .expect(StatusCode::OK)?;
+    let cookie = resp.headers[header::SET_COOKIE].to_str()?;
+    assert!(cookie.contains("Max-Age=0"), "{cookie}");
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_session_cookie_capped_by_absolute_timeout() -> Result<()> {
+    let app = TestApp::new().await?;
+    app.create_user("alice", &[]).await?;
+    let auth = app.login_cookie("alice").await?;
+
+    // An hour of absolute lifetime left, less than the idle timeout.
+    let mut db = app.state.db().clone();
+    let now = jiff::Timestamp::now();
+    session_of(&app, &auth)
+        .await?
+        .update()
+        .created_at(now - jiff::SignedDuration::from_hours(167))
+        .last_seen_at(now - jiff::SignedDuration::from_mins(2))
+        .exec(&mut db)
+        .await?;
+
+    let resp = app
+        .get("/api/auth/me", &auth)
+        .await?
+        .expect(StatusCode::OK)?;
+    let cookie = resp.headers[header::SET_COOKIE].to_str()?;
+    let max_age: i64 = cookie
+        .split("; ")
+        .find_map(|attr| attr.strip_prefix("Max-Age="))
+        .context("no Max-Age")?
+        .parse()?;
+    assert!((3590..=3600).contains(&max_age), "{cookie}");
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_session_ttl_hours_still_honoured() -> Result<()> {
+    let app = TestApp::with_config(|cfg| cfg.auth.session.ttl_hours = Some(1)).await?;
+    app.create_user("alice", &[]).await?;
+
+    let resp = app
+        .post(
+            "/api/auth/login",
+            &Auth::None,
+            &json!({ "username": "alice", "password": common::PASSWORD }),
+        )
+        .await?
+        .expect(StatusCode::OK)?;
+    let cookie = resp.headers[header::SET_COOKIE].to_str()?;
+    assert!(cookie.contains("Max-Age=3600"), "{cookie}");
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_session_absolute_timeout() -> Result<()> {
+    let app = TestApp::new().await?;
+    app.create_user("alice", &[]).await?;
+    let auth = app.login_cookie("alice").await?;
+
+    // Still active, but logged in longer ago than the absolute lifetime.
+    let mut db = app.state.db().clone();
+    session_of(&app, &auth)
+        .await?
+        .update()
+        .created_at(jiff::Timestamp::now() - jiff::SignedDuration::from_hours(8 * 24))
+        .exec(&mut db)
+        .await?;
+
+    let resp = app
+        .get("/api/auth/me", &auth)
+        .await?
+        .expect(StatusCode::UNAUTHORIZED)?;
+    assert_eq!(resp.error_code()?, ErrorKind::Unauthorized.code());
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_session_idle_timeout() -> Result<()> {
+    let app = TestApp::new().await?;
+    app.create_user("alice", &[]).await?;
+    let auth = app.login_cookie("alice").await?;
+
+    let mut db = app.state.db().clone();
+    let past = jiff::Timestamp::now() - jiff::SignedDuration::from_secs(1);
+    session_of(&app, &auth)
+        .await?
+        .update()
+        .expires_at(past)
+        .exec(&mut db)
+        .await?;
+
+    app.get("/api/auth/me", &auth)
+        .await?
+        .expect(StatusCode::UNAUTHORIZED)?;
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_session_ip_behind_trusted_proxy() -> Result<()> {
+    let app = TestApp::with_config(|cfg| {
+        cfg.server.trusted_proxies = vec!["10.0.0.1".parse().unwrap()];
+    })
+    .await?;
+    app.create_user("alice", &[]).await?;
+
+    let mut ips = Vec::new();
+    for peer in ["10.0.0.1", "198.51.100.1"] {
+        let addr: std::net::SocketAddr = format!("{peer}:40000").parse()?;
+        let request = Request::post("/api/auth/login")
+            .header(header::CONTENT_TYPE, "application/json")
+            .header("x-forwarded-for", "203.0.113.7")
+            .extension(axum::extract::ConnectInfo(addr))
+            .body(Body::from(
+                json!({ "username": "alice", "password": common::PASSWORD }).to_string(),
+            ))?;
+        let resp = app.request(request).await?.expect(StatusCode::OK)?;
+        let cookie = common::set_cookie(&resp, &app.state.cfg().auth.session.cookie_name)
+            .context("login did not set the session cookie")?;
+        let auth = Auth::Cookie {
+            cookie,
+            csrf: String::new(),
+        };
+        let sessions: Vec<Value> = app
+            .get("/api/auth/sessions", &auth)
+            .await?
+            .expect(StatusCode::OK)?
+            .json()?;
+        let current = sessions.iter().find(|s| s["current"] == true);
+        ips.push(current.context("no current session")?["ip"].clone());
+    }
+    // Forwarded-for is only believed from the configured proxy.
+    assert_eq!(ips, [json!("203.0.113.7"), json!("198.51.100.1")]);
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_session_management() -> Result<()> {
+    let app = TestApp::new().await?;
+    app.create_user("alice", &[]).await?;
+    app.create_user("bob", &[]).await?;
+    let laptop = app.login_cookie("alice").await?;
+    let phone = app.login_cookie("alice").await?;
+    let bob = app.login_cookie("bob").await?;
+
+    let sessions: Vec<Value> = app
+        .get("/api/auth/sessions", &laptop)
+        .await?
+        .expect(StatusCode::OK)?
+        .json()?;
+    assert_eq!(sessions.len(), 2);
+    let current: Vec<_> = sessions.iter().filter(|s| s["current"] == true).collect();
+    assert_eq!(current.len(), 1);
+    assert_eq!(current[0]["id"], session_of(&app, &laptop).await?.id);
+
+    // Revoke one device; another user's session is not visible.
+    let phone_id = session_of(&app, &phone).await?.id;
+    let bob_id = session_of(&app, &bob).await?.id;
+    app.delete(&format!("/api/auth/sessions/{bob_id}"), &laptop)
+        .await?
+        .expect(StatusCode::NOT_FOUND)?;
+    app.delete(&format!("/api/auth/sessions/{phone_id}"), &laptop)
+        .await?
+        .expect(StatusCode::OK)?;
+    app.get("/api/auth/me", &phone)
+        .await?
+        .expect(StatusCode::UNAUTHORIZED)?;
+
+    // Log out everywhere else.
+    let tablet = app.login_cookie("alice").await?;
+    app.delete("/api/auth/sessions", &laptop)
+        .await?
+        .expect(StatusCode::OK)?;
+    app.get("/api/auth/me", &tablet)
+        .await?
+        .expect(StatusCode::UNAUTHORIZED)?;
+    app.get("/api/auth/me", &laptop)
+        .await?
+        .expect(StatusCode::OK)?;
+
+    // Logout ends only the current session.
+    let desktop = app.login_cookie("alice").await?;
+    app.post("/api/auth/logout", &laptop, &json!({}))
+        .await?
+        .expect(StatusCode::OK)?;
+    app.get("/api/auth/me", &laptop)
+        .await?
+        .expect(StatusCode::UNAUTHORIZED)?;
+    app.get("/api/auth/me", &desktop)
+        .await?
+        .expect(StatusCode::OK)?;
+    app.get("/api/auth/me", &bob)
+        .await?
+        .expect(StatusCode::OK)?;
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_refresh_token_reuse_revokes_family() -> Result<()> {
+    let app = TestApp::new().await?;
+    app.create_user("alice", &[]).await?;
+    let stolen = app.login_tokens("alice").await?;
+    let other = app.login_tokens("alice").await?;
+
+    let rotated: Value = app
+        .refresh(&stolen["refresh_token"])
+        .await?
+        .expect(StatusCode::OK)?
+        .json()?;
+    let rotated: Value = app
+        .refresh(&rotated["refresh_token"])
+        .await?
+        .expect(StatusCode::OK)?
+        .json()?;
+
+    // Replaying a rotated token kills the live end of its chain too.
+    app.refresh(&stolen["refresh_token"])
+        .await?
+        .expect(StatusCode::UNAUTHORIZED)?;
+    app.refresh(&rotated["refresh_token"])
+        .await?
+        .expect(StatusCode::UNAUTHORIZED)?;
+
+    // Other logins are separate families.
+    app.refresh(&other["refresh_token"])
+        .await?
+        .expect(StatusCode::OK)?;
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_concurrent_refresh_is_reuse() -> Result<()> {
+    let app = TestApp::new().await?;
+    app.create_user("alice", &[]).await?;
+    let tokens = app.login_tokens("alice").await?;
+
+    let (a, b) = tokio::join!(
+        app.refresh(&tokens["refresh_token"]),
+        app.refresh(&tokens["refresh_token"])
+    );
+    let responses = [a?, b?];
+    let rotated: Vec<_> = responses
+        .iter()
+        .filter(|r| r.status == StatusCode::OK)
+        .collect();
+    assert_eq!(rotated.len(), 1, "exactly one rotation may succeed");
+
+    // The loser revoked the family, including the winner's new token.
+    let next: Value = rotated[0].json()?;
+    app.refresh(&next["refresh_token"])
+        .await?
+        .expect(StatusCode::UNAUTHORIZED)?;
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_expired_refresh_tokens_purged() -> Result<()> {
+    let app = TestApp::with_config(|cfg| cfg.auth.jwt.refresh_expires_in_seconds = 0).await?;
+    let alice = app.create_user("alice", &[]).await?;
+    app.login_tokens("alice").await?;
+    tokio::time::sleep(Duration::from_millis(10)).await;
+    app.login_tokens("alice").await?;
+
+    let mut db = app.state.db().clone();
+    let tokens = RefreshToken::all()
+        .filter(RefreshToken::fields().user_id().eq(alice))
+        .exec(&mut db)
+        .await?;
+    assert_eq!(tokens.len(), 1);
+
+    tokio::time::sleep(Duration::from_millis(10)).await;
+    app.srv().token.purge_expired_refresh_tokens().await?;
+    assert_eq!(RefreshToken::all().count().exec(&mut db).await?, 0);
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_tokens_hashed_at_rest() -> Result<()> {
+    let app = TestApp::new().await?;
+    app.create_user("alice", &[]).await?;
+    let auth = app.login_cookie("alice").await?;
+    let tokens = app.login_tokens("alice").await?;
+
+    let Auth::Cookie { cookie, .. } = &auth else {
+        unreachable!()
+    };
+    let session_id = cookie.split(';').next().unwrap().split_once('=').unwrap().1;
+    let refresh_token = tokens["refresh_token"].as_str().unwrap();
+    assert!(session_id.starts_with("sess_"));
+    assert!(refresh_token.starts_with("rt_"));
+
+    let mut db = app.state.db().clone();
+    let sessions = Session::all().exec(&mut db).await?;
+    let refresh_tokens = RefreshToken::all().exec(&mut db).await?;
+    assert!(sessions.iter().all(|s| s.session_id_hash != session_id));
+    assert!(
+        refresh_tokens
+            .iter()
+            .all(|t| t.token_hash != refresh_token && t.family_id != refresh_token)
+    );
+
+    // Lookups still work from the raw values.
+    app.get("/api/auth/me", &auth)
+        .await?
+        .expect(StatusCode::OK)?;
+    app.refresh(&tokens["refresh_token"])
+        .await?
+        .expect(StatusCode::OK)?;
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_jwt_key_rotation_and_jwks() -> Result<()> {
+    let dir = std::env::temp_dir().join(format!("axum-template-{}", uuid::Uuid::new_v4()));
+    std::fs::create_dir_all(&dir)?;
+    let key = |kid: &str| -> Result<JwtKeyConfig> {
+        let path = dir.join(format!("{kid}.pem"));
+        std::fs::write(&path, generate_pem(jsonwebtoken::Algorithm::EdDSA)?)?;
+        Ok(JwtKeyConfig {
+            kid: kid.to_owned(),
+            algorithm: JwtAlgorithm::EdDsa,
+            private_key_file: path,
+        })
+    };
+    let (old, new) = (key("old")?, key("new")?);
+
+    // Before rotation: only the old key.
+    let before = TestApp::with_config(|cfg| cfg.auth.jwt.keys = vec![old.clone()]).await?;
+    before.create_user("alice", &[]).await?;
+    let Auth::Bearer(token) = before.login_jwt("alice").await? else {
+        unreachable!()
+    };
+    assert_eq!(
+        jsonwebtoken::decode_header(&token)?.kid.as_deref(),
+        Some("old")
+    );
+
+    // Staged: both keys published, new one signing.
+    let after = TestApp::with_config(|cfg| {
+        cfg.auth.jwt.keys = vec![old, new];
+        cfg.auth.jwt.signing_kid = Some("new".to_owned());
+    })
+    .await?;
+    after.create_user("alice", &[]).await?;
+    let jwks: Value = after
+        .get("/.well-known/jwks.json", &Auth::None)
+        .await?
+        .expect(StatusCode::OK)?
+        .json()?;
+    let kids: Vec<_> = jwks["keys"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|k| k["kid"].as_str().unwrap())
+        .collect();
+    assert_eq!(kids, ["old", "new"]);
+    assert!(jwks["keys"][0].get("d").is_none(), "private key leaked");
+
+    // Tokens signed before rotation still verify.
+    after
+        .get("/api/auth/jwt/echo", &Auth::Bearer(token))
+        .await?
+        .expect(StatusCode::OK)?;
+
+    // HS256 tokens are no longer accepted.
+    let hmac = TestApp::new().await?;
+    hmac.create_user("alice", &[]).await?;
+    let token = hmac.login_jwt("alice").await?;
+    after
+        .get("/api/auth/jwt/echo", &token)
+        .await?
+        .expect(StatusCode::UNAUTHORIZED)?;
+
+    std::fs::remove_dir_all(&dir)?;
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_jwt_rejected_by_other_deployment() -> Result<()> {
+    let staging = TestApp::with_config(|cfg| cfg.auth.jwt.issuer = "staging".to_owned()).await?;
+    staging.create_user("alice", &[]).await?;
+    let staging_token = staging.login_jwt("alice").await?;
+
+    let tenant =
+        TestApp::with_config(|cfg| cfg.auth.jwt.audience = vec!["tenant-a".to_owned()]).await?;
+    tenant.create_user("alice", &[]).await?;
+    let tenant_token = tenant.login_jwt("alice").await?;
+
+    // Same secret, different issuer or audience.
+    let prod = TestApp::new().await?;
+    for token in [&staging_token, &tenant_token] {
+        prod.get("/api/auth/jwt/echo", token)
+            .await?
+            .expect(StatusCode::UNAUTHORIZED)?;
+    }
+    staging
+        .get("/api/auth/jwt/echo", &staging_token)
+        .await?
+        .expect(StatusCode::OK)?;
+    Ok(())
+}
+
+struct TenantClaims;
+
+#[async_trait::async_trait]
+impl ClaimsHook for TenantClaims {
+    async fn claims(&self, user: &User) -> axum_template::Result<Map<String, Value>> {
+        let mut claims = Map::new();
+        claims.insert(
+            "tenant".to_owned(),
+            json!(format!("{}-corp", user.username)),
+        );
+        Ok(claims)
+    }
+}
+
+#[tokio::test]
+async fn test_jwt_custom_claims() -> Result<()> {
+    let mut raw = RawAppConfig::default();
+    raw.database.url = "sqlite::memory:".to_owned();
+    let state =
+        AppState::with_claims_hook(AppConfig::new(raw), Some(Arc::new(TenantClaims))).await?;
+    let app = TestApp::from_state(state).await?;
+    app.create_user("alice", &[]).await?;
+
+    let auth = app.login_jwt("alice").await?;
+    let body: Value = app
+        .get("/api/auth/jwt/echo", &auth)
+        .await?
+        .expect(StatusCode::OK)?
+        .json()?;
+    assert_eq!(body["claims"]["tenant"], "alice-corp");
+    Ok(())
+}
+
+/// Fails while `down` is set, like a claims source that is unreachable.
+#[derive(Default)]
+struct FlakyClaims {
+    down: AtomicBool,
+}
+
+#[async_trait::async_trait]
+impl ClaimsHook for FlakyClaims {
+    async fn claims(&self, _user: &User) -> axum_template::Result<Map<String, Value>> {
+        if self.down.load(Ordering::SeqCst) {
+            return Err(ErrorKind::Unavailable.msg("Claims source down"));
+        }
+        Ok(Map::new())
+    }
+}
+
+#[tokio::test]
+async fn test_failed_claims_hook_keeps_refresh_token() -> Result<()> {
+    let mut raw = RawAppConfig::default();
+    raw.database.url = "sqlite::memory:".to_owned();
+    let hook = Arc::new(FlakyClaims::default());
+    let state = AppState::with_claims_hook(AppConfig::new(raw), Some(hook.clone())).await?;
+    let app = TestApp::from_state(state).await?;
+    app.create_user("alice", &[]).await?;
+    let tokens = app.login_tokens("alice").await?;
+
+    hook.down.store(true, Ordering::SeqCst);
+    let resp = app.refresh(&tokens["refresh_token"]).await?;
+    assert_eq!(resp.status, StatusCode::SERVICE_UNAVAILABLE);
+
+    // The failed attempt neither used up the token nor flagged it as reused.
+    hook.down.store(false, Ordering::SeqCst);
+    app.refresh(&tokens["refresh_token"])
+        .await?
+        .expect(StatusCode::OK)?;
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_password_reset() -> Result<()> {
+    let app = TestApp::new().await?;
+    let alice = app.create_user("alice", &[]).await?;
+    let root = app.create_user("root", &[Perm::All]).await?;
+    app.create_user("admin", &[Perm::UserAll, Perm::RoleAll])
+        .await?;
+    let admin = app.login_jwt("admin").await?;
+
+    // The old password stops working.
+    app.put(
+        &format!("/api/users/{alice}/password/reset"),
+        &admin,
+        &json!({ "new_password": "temporary1" }),
+    )
+    .await?
+    .expect(StatusCode::OK)?;
+    let resp = app
+        .post(
+            "/api/auth/login",
+            &Auth::None,
+            &json!({ "username": "alice", "password": common::PASSWORD }),
+        )
+        .await?;
+    assert_eq!(resp.status, StatusCode::FORBIDDEN);
+
+    // With `force_change`, only changing the password and logging out work
+    // until the user has done so.
+    app.put(
+        &format!("/api/users/{alice}/password/reset"),
+        &admin,
+        &json!({ "new_password": common::PASSWORD, "force_change": true }),
+    )
+    .await?
+    .expect(StatusCode::OK)?;
+    let cookie = app.login_cookie("alice").await?;
+    let token = app.login_jwt("alice").await?;
+    for auth in [&cookie, &token] {
+        let resp = app.get("/api/auth/me", auth).await?;
+        assert_eq!(resp.status, StatusCode::FORBIDDEN);
+        assert_eq!(resp.error_code()?, ErrorKind::PasswordChangeRequired.code());
+    }
+    app.get("/api/auth/jwt/echo", &token)
+        .await?
+        .expect(StatusCode::FORBIDDEN)?;
+    app.post("/api/auth/jwt/logout", &token, &json!({}))
+        .await?
+        .expect(StatusCode::OK)?;
+
+    app.put(
+        &format!("/api/users/{alice}/password"),
+        &cookie,
+        &json!({ "old_password": common::PASSWORD, "new_password": "changed123" }),
+    )
+    .await?
+    .expect(StatusCode::OK)?;
+    app.get("/api/auth/me", &cookie)
+        .await?
+        .expect(StatusCode::OK)?;
+
+    // An admin cannot reset, and so take over, a more privileged account.
+    app.put(
+        &format!("/api/users/{root}/password/reset"),
+        &admin,
+        &json!({ "new_password": "takeover1" }),
+    )
+    .await?
+    .expect(StatusCode::FORBIDDEN)?;
+    app.login_jwt("root").await?;
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_password_change_signs_out_elsewhere() -> Result<()> {
+    let app = TestApp::new().await?;
+    let alice = app.create_user("alice", &[]).await?;
+    let laptop = app.login_cookie("alice").await?;
+    let phone = app.login_cookie("alice").await?;
+    let tokens = app.login_tokens("alice").await?;
+    let access = Auth::Bearer(tokens["access_token"].as_str().unwrap().to_owned());
+
+    app.put(
+        &format!("/api/users/{alice}/password"),
+        &laptop,
+        &json!({ "old_password": common::PASSWORD, "new_password": "changed123" }),
+    )
+    .await?
+    .expect(StatusCode::OK)?;
+
+    // Only the session the change was made from survives.
+    app.get("/api/auth/me", &laptop)
+        .await?
+        .expect(StatusCode::OK)?;
+    for auth in [&phone, &access] {
+        app.get("/api/auth/me", auth)
+            .await?
+            .expect(StatusCode::UNAUTHORIZED)?;
+    }
+    app.refresh(&tokens["refresh_token"])
+        .await?
+        .expect(StatusCode::UNAUTHORIZED)?;
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_access_tokens_revoked_by_token_version() -> Result<()> {
+    let app = TestApp::new().await?;
+    let alice = app.create_user("alice", &[]).await?;
+    app.create_user(
+        "admin",
+        &[
+            Perm::UserWrite,
+            Perm::UserDelete,
+            Perm::RoleWrite,
+            Perm::RoleDelete,
+        ],
+    )
+    .await?;
+    let admin = app.login_jwt("admin").await?;
+    let role = app
+        .srv()
+        .role
+        .create("viewer".to_owned(), None, &[])
+        .await?;
+
+    // Log out everywhere, through either logout endpoint.
+    for path in ["/api/auth/jwt/logout", "/api/auth/logout"] {
+        let token = app.login_jwt("alice").await?;
+        app.post(path, &token, &json!({}))
+            .await?
+            .expect(StatusCode::OK)?;
+        app.get("/api/auth/jwt/echo", &token)
+            .await?
+            .expect(StatusCode::UNAUTHORIZED)?;
+    }
+
+    // Admin password reset.
+    let token = app.login_jwt("alice").await?;
+    app.get("/api/auth/jwt/echo", &token)
+        .await?
+        .expect(StatusCode::OK)?;
+    app.put(
+        &format!("/api/users/{alice}/password/reset"),
+        &admin,
+        &json!({ "new_password": common::PASSWORD }),
+    )
+    .await?
+    .expect(StatusCode::OK)?;
+    app.get("/api/auth/jwt/echo", &token)
+        .await?
+        .expect(StatusCode::UNAUTHORIZED)?;
+
+    // Role change.
+    let token = app.login_jwt("alice").await?;
+    app.post(
+        &format!("/api/users/{alice}/roles"),
+        &admin,
+        &json!({ "role_id": role.id }),
+    )
+    .await?
+    .expect(StatusCode::OK)?;
+    app.get("/api/auth/me", &token)
+        .await?
+        .expect(StatusCode::UNAUTHORIZED)?;
+
+    // Permissions of a role she holds change.
+    let token = app.login_jwt("alice").await?;
+    app.put(
+        &format!("/api/roles/{}/permissions", role.id),
+        &admin,
+        &json!({ "permissions": [] }),
+    )
+    .await?
+    .expect(StatusCode::OK)?;
+    app.get("/api/auth/me", &token)
+        .await?
+        .expect(StatusCode::UNAUTHORIZED)?;
+
+    // The role is deleted.
+    let token = app.login_jwt("alice").await?;
+    app.delete(&format!("/api/roles/{}", role.id), &admin)
+        .await?
+        .expect(StatusCode::OK)?;
+    app.get("/api/auth/me", &token)
+        .await?
+        .expect(StatusCode::UNAUTHORIZED)?;
+
+    // Logging out everywhere else from a browser session.
+    let token = app.login_jwt("alice").await?;
+    let browser = app.login_cookie("alice").await?;
+    app.delete("/api/auth/sessions", &browser)
+        .await?
+        .expect(StatusCode::OK)?;
+    app.get("/api/auth/jwt/echo", &token)
+        .await?
+        .expect(StatusCode::UNAUTHORIZED)?;
+
+    // Deletion.
+    let token = app.login_jwt("alice").await?;
+    app.delete(&format!("/api/users/{alice}"), &admin)
+        .await?
+        .expect(StatusCode::OK)?;
+    app.get("/api/auth/jwt/echo", &token)
+        .await?
+        .expect(StatusCode::UNAUTHORIZED)?;
+    Ok(())
+}
+
+/// The current TOTP time step. Tests take it once, so codes for `step` and
+/// `step + 1` stay distinct and valid even if a step boundary passes.
+fn totp_step() -> i64 {
+    totp::step_at(jiff::Timestamp::now().as_second())
+}
+
+fn totp_code(secret: &Value, step: i64) -> String {
+    totp::code_at(secret.as_str().unwrap(), step).unwrap()
+}
+
+#[tokio::test]
+async fn test_totp_enrollment_and_login() -> Result<()> {
+    let step = totp_step();
+    let app = TestApp::new().await?;
+    app.create_user("alice", &[]).await?;
+    let auth = app.login_jwt("alice").await?;
+
+    let enrollment: Value = app
+        .post("/api/auth/mfa/totp", &auth, &json!({}))
+        .await?
+        .expect(StatusCode::OK)?
+        .json()?;
+    let secret = &enrollment["secret"];
+    assert!(
+        enrollment["otpauth_uri"]
+            .as_str()
+            .unwrap()
+            .starts_with("otpauth://totp/axum-template:alice?secret=")
+    );
+    let recovery_codes = enrollment["recovery_codes"].as_array().unwrap();
+    assert_eq!(recovery_codes.len(), 10);
+
+    // Unconfirmed enrollment changes nothing.
+    app.login_jwt("alice").await?;
+    app.post(
+        "/api/auth/mfa/totp/confirm",
+        &auth,
+        &json!({ "code": totp_code(secret, step) }),
+    )
+    .await?
+    .expect(StatusCode::OK)?;
+
+    // JWT login: the challenge replaces tokens, and a code is single-use.
+    let challenge = app.login_challenge("/api/auth/jwt/login", "alice").await?;
+    assert_eq!(challenge["enrollment_required"], false);
+    assert!(challenge.get("access_token").is_none());
+    let complete =
+        |code: String| json!({ "challenge_token": challenge["challenge_token"], "code": code });
+    let resp = app
+        .post(
+            "/api/auth/jwt/login/mfa",
+            &Auth::None,
+            &complete(totp_code(secret, step)),
+        )
+        .await?;
+    assert_eq!(resp.error_code()?, "auth.invalid_credentials");
+    let tokens: Value = app
+        .post(
+            "/api/auth/jwt/login/mfa",
+            &Auth::None,
+            &complete(totp_code(secret, step + 1)),
+        )
+        .await?
+        .expect(StatusCode::OK)?
+        .json()?;
+    assert!(tokens["access_token"].is_string());
+    app.post(
+        "/api/auth/jwt/login/mfa",
+        &Auth::None,
+        &complete(totp_code(secret, step + 1)),
+    )
+    .await?
+    .expect(StatusCode::UNAUTHORIZED)?;
+
+    // Session login with a recovery code, which then stops working.
+    for expected in [StatusCode::OK, StatusCode::FORBIDDEN] {
+        let challenge = app.login_challenge("/api/auth/login", "alice").await?;
+        let resp = app
+            .post(
+                "/api/auth/login/mfa",
+                &Auth::None,
+                &json!({
+                    "challenge_token": challenge["challenge_token"],
+                    "code": recovery_codes[0].as_str().unwrap().to_uppercase(),
+                }),
+            )
+            .await?
+            .expect(expected)?;
+        assert_eq!(
+            common::set_cookie(&resp, &app.state.cfg().auth.session.cookie_name).is_some(),
+            expected == StatusCode::OK
+        );
+    }
+
+    let status: Value = app
+        .get("/api/auth/mfa", &auth)
+        .await?
+        .expect(StatusCode::OK)?
+        .json()?;
+    assert_eq!(status["enabled"], true);
+    assert_eq!(status["recovery_codes_remaining"], 9);
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_mfa_challenge_attempt_limit() -> Result<()> {
+    let step = totp_step();
+    // Keep the login throttle out of the way of the per-challenge limit.
+    let app = TestApp::with_config(|cfg| cfg.auth.throttle.user.free_attempts = 10).await?;
+    let alice = app.create_user("alice", &[]).await?;
+    let enrollment = app.srv().mfa.enroll(alice).await?;
+    let secret = json!(enrollment.secret);
+    app.srv()
+        .mfa
+        .confirm(alice, &totp_code(&secret, step))
+        .await?;
+
+    let challenge = app.login_challenge("/api/auth/jwt/login", "alice").await?;
+    let attempt =
+        |code: &str| json!({ "challenge_token": challenge["challenge_token"], "code": code });
+    for _ in 0..4 {
+        app.post("/api/auth/jwt/login/mfa", &Auth::None, &attempt("000000"))
+            .await?
+            .expect(StatusCode::FORBIDDEN)?;
+    }
+    app.post("/api/auth/jwt/login/mfa", &Auth::None, &attempt("000000"))
+        .await?
+        .expect(StatusCode::UNAUTHORIZED)?;
+    // The challenge is gone even for the right code.
+    app.post(
+        "/api/auth/jwt/login/mfa",
+        &Auth::None,
+        &attempt(&totp_code(&secret, step + 1)),
+    )
+    .await?
+    .expect(StatusCode::UNAUTHORIZED)?;
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_wrong_mfa_codes_throttled() -> Result<()> {
+    let step = totp_step();
+    let app = TestApp::with_config(|cfg| {
+        cfg.auth.throttle.user.free_attempts = 2;
+        cfg.auth.throttle.user.base_delay_seconds = 60;
+    })
+    .await?;
+    let alice = app.create_user("alice", &[]).await?;
+    let enrollment = app.srv().mfa.enroll(alice).await?;
+    let secret = json!(enrollment.secret);
+    app.srv()
+        .mfa
+        .confirm(alice, &totp_code(&secret, step))
+        .await?;
+
+    // Fresh challenges don't reset the count of wrong codes.
+    for _ in 0..3 {
+        let challenge = app.login_challenge("/api/auth/jwt/login", "alice").await?;
+        app.post(
+            "/api/auth/jwt/login/mfa",
+            &Auth::None,
+            &json!({ "challenge_token": challenge["challenge_token"], "code": "000000" }),
+        )
+        .await?
+        .expect(StatusCode::FORBIDDEN)?;
+    }
+    let resp = app
+        .post(
+            "/api/auth/jwt/login",
+            &Auth::None,
+            &json!({ "username": "alice", "password": common::PASSWORD }),
+        )
+        .await?;
+    assert_eq!(resp.status, StatusCode::TOO_MANY_REQUESTS);
+
+    // Codes checked outside a login count too.
+    let err = app
+        .srv()
+        .mfa
+        .regenerate_recovery_codes(alice, &totp_code(&secret, step + 1))
+        .await
+        .unwrap_err();
+    assert_eq!(*err.kind(), ErrorKind::TooManyAttempts);
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_role_requires_mfa() -> Result<()> {
+    let step = totp_step();
+    let app = TestApp::new().await?;
+    app.create_user("admin", &[Perm::RoleWrite, Perm::UserRead])
+        .await?;
+    app.create_user("bob", &[Perm::UserRead]).await?;
+    app.create_user("root", &[Perm::All]).await?;
+    let admin = app.login_jwt("admin").await?;
+    let role = app.srv().role.find_by_name("bob-role").await?.unwrap();
+
+    // Roles above the caller's own are out of reach.
+    let root_role = app.srv().role.find_by_name("root-role").await?.unwrap();
+    app.put(
+        &format!("/api/roles/{}/mfa", root_role.id),
+        &admin,
+        &json!({ "required": false }),
+    )
+    .await?
+    .expect(StatusCode::FORBIDDEN)?;
+
+    let resp: Value = app
+        .put(
+            &format!("/api/roles/{}/mfa", role.id),
+            &admin,
+            &json!({ "required": true }),
+        )
+        .await?
+        .expect(StatusCode::OK)?
+        .json()?;
+    assert_eq!(resp["require_mfa"], true);
+
+    // Bob has no authenticator, so he enrolls one to finish logging in.
+    let challenge = app.login_challenge("/api/auth/login", "bob").await?;
+    assert_eq!(challenge["enrollment_required"], true);
+    let token = &challenge["challenge_token"];
+    let enrollment: Value = app
+        .post(
+            "/api/auth/mfa/challenge/totp",
+            &Auth::None,
+            &json!({ "challenge_token": token }),
+        )
+        .await?
+        .expect(StatusCode::OK)?
+        .json()?;
+    let secret = &enrollment["secret"];
+    app.post(
+        "/api/auth/login/mfa",
+        &Auth::None,
+        &json!({ "challenge_token": token, "code": totp_code(secret, step) }),
+    )
+    .await?
+    .expect(StatusCode::OK)?;
+
+    // Now enrolled, he can neither re-enroll via a challenge nor opt out.
+    let challenge = app.login_challenge("/api/auth/jwt/login", "bob").await?;
+    assert_eq!(challenge["enrollment_required"], false);
+    app.post(
+        "/api/auth/mfa/challenge/totp",
+        &Auth::None,
+        &json!({ "challenge_token": challenge["challenge_token"] }),
+    )
+    .await?
+    .expect(StatusCode::CONFLICT)?;
+    let tokens: Value = app
+        .post(
+            "/api/auth/jwt/login/mfa",
+            &Auth::None,
+            &json!({
+                "challenge_token": challenge["challenge_token"],
+                "code": totp_code(secret, step + 1),
+            }),
+        )
+        .await?
+        .expect(StatusCode::OK)?
+        .json()?;
+    let bob = Auth::Bearer(tokens["access_token"].as_str().unwrap().to_owned());
+    let resp = app
+        .send(
+            Method::DELETE,
+            "/api/auth/mfa/totp",
+            &bob,
+            Some(&json!({ "code": enrollment["recovery_codes"][0] })),
+        )
+        .await?
+        .expect(StatusCode::FORBIDDEN)?;
+    assert_eq!(resp.error_code()?, "auth.permission_denied");
+    Ok(())
+}
+
+/// A JWT login from `ip`, which the router otherwise has no way to learn in
+/// tests.
+fn login_from(ip: &str, username: &str, password: &str) -> Request<Body> {
+    let addr: std::net::SocketAddr = format!("{ip}:40000").parse().unwrap();
+    Request::post("/api/auth/jwt/login")
+        .header(header::CONTENT_TYPE, "application/json")
+        .extension(axum::extract::ConnectInfo(addr))
+        .body(Body::from(
+            json!({ "username": username, "password": password }).to_string(),
+        ))
+        .unwrap()
+}
+
+#[tokio::test]
+async fn test_login_backoff_and_lockout() -> Result<()> {
+    let app = TestApp::with_config(|cfg| {
+        let user = &mut cfg.auth.throttle.user;
+        user.free_attempts = 1;
+        user.base_delay_seconds = 60;
+        user.lockout_threshold = 3;
+    })
+    .await?;
+    let alice = app.create_user("alice", &[]).await?;
+    app.create_user("admin", &[Perm::UserWrite]).await?;
+    let admin = app.login_jwt("admin").await?;
+    let wrong = || app.request(login_from("10.0.0.1", "alice", "wrong-password"));
+
+    // One free failure, then backoff: even the right password must wait.
+    wrong().await?.expect(StatusCode::FORBIDDEN)?;
+    wrong().await?.expect(StatusCode::FORBIDDEN)?;
+    let resp = app
+        .request(login_from("10.0.0.1", "alice", common::PASSWORD))
+        .await?
+        .expect(StatusCode::TOO_MANY_REQUESTS)?;
+    assert_eq!(resp.error_code()?, "auth.too_many_attempts");
+    let retry_after: u64 = resp.headers[header::RETRY_AFTER].to_str()?.parse()?;
+    assert!((1..=60).contains(&retry_after), "{retry_after}");
+
+    // Reaching the threshold locks the account, from any address.
+    app.srv()
+        .throttle
+        .record_failure("alice", Some("10.0.0.2"))
+        .await?;
+    let resp = app
+        .request(login_from("10.0.0.3", "alice", common::PASSWORD))
+        .await?
+        .expect(StatusCode::LOCKED)?;
+    assert_eq!(resp.error_code()?, "auth.account_locked");
+    assert!(resp.headers.contains_key(header::RETRY_AFTER));
+
+    app.delete(&format!("/api/users/{alice}/lockout"), &admin)
+        .await?
+        .expect(StatusCode::OK)?;
+    app.request(login_from("10.0.0.3", "alice", common::PASSWORD))
+        .await?
+        .expect(StatusCode::OK)?;
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_concurrent_login_failures_all_counted() -> Result<()> {
+    let app = TestApp::with_config(|cfg| cfg.auth.throttle.user.free_attempts = 100).await?;
+    let tasks: Vec<_> = (0..8)
+        .map(|_| {
+            let throttle = app.srv().throttle.clone();
+            tokio::spawn(async move { throttle.record_failure("alice", None).await })
+        })
+        .collect();
+    for task in tasks {
+        task.await??;
+    }
+
+    let mut db = app.state.db().clone();
+    let row = LoginThrottle::filter_by_key("user:alice")
+        .get(&mut db)
+        .await?;
+    assert_eq!(row.failures, 8);
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_concurrent_revocations_all_counted() -> Result<()> {
+    let app = TestApp::new().await?;
+    let alice = app.create_user("alice", &[]).await?;
+    let before = app.srv().user.get_by_id(alice).await?.token_version;
+    let tasks: Vec<_> = (0..8)
+        .map(|_| {
+            let token = app.srv().token.clone();
+            tokio::spawn(async move { token.revoke_access_tokens(alice).await })
+        })
+        .collect();
+    for task in tasks {
+        task.await??;
+    }
+
+    let user = app.srv().user.get_by_id(alice).await?;
+    assert_eq!(user.token_version, before + 8);
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_stale_login_throttles_purged() -> Result<()> {
+    let app = TestApp::with_config(|cfg| {
+        cfg.auth.throttle.reset_after_seconds = 0;
+        cfg.auth.throttle.user.free_attempts = 0;
+    })
+    .await?;
+    let throttle = &app.srv().throttle;
+    // Alice is now blocked; her IP's count is merely old.
+    throttle.record_failure("alice", Some("10.0.0.1")).await?;
+    throttle.record_failure("bob", None).await?;
+
+    let mut db = app.state.db().clone();
+    let mut keys: Vec<_> = LoginThrottle::all()
+        .exec(&mut db)
+        .await?
+        .into_iter()
+        .map(|t| t.key)
+        .collect();
+    keys.sort();
+    assert_eq!(keys, ["user:alice", "user:bob"]);
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_login_throttled_per_ip() -> Result<()> {
+    let app = TestApp::with_config(|cfg| {
+        let ip = &mut cfg.auth.throttle.ip;
+        ip.free_attempts = 2;
+        ip.base_delay_seconds = 60;
+    })
+    .await?;
+    app.create_user("alice", &[]).await?;
+
+    // Spraying different usernames still trips the per-IP limit.
+    for username in ["bob", "carol", "dave"] {
+        app.request(login_from("10.0.0.9", username, "guess"))
+            .await?
+            .expect(StatusCode::FORBIDDEN)?;
+    }
+    app.request(login_from("10.0.0.9", "alice", common::PASSWORD))
+        .await?
+        .expect(StatusCode::TOO_MANY_REQUESTS)?;
+    app.request(login_from("10.0.0.10", "alice", common::PASSWORD))
+        .await?
+        .expect(StatusCode::OK)?;
+
+    assert!(app.srv().throttle.unlock_ip("10.0.0.9").await?);
+    app.request(login_from("10.0.0.9", "alice", common::PASSWORD))
+        .await?
+        .expect(StatusCode::OK)?;
+    Ok(())
+}
+
+async fn session_of(app: &TestApp, auth: &Auth) -> Result<Session> {
+    let Auth::Cookie { cookie, .. } = auth else {
+        anyhow::bail!("not a cookie session")
+    };
+    let session_id = cookie.split(';').next().unwrap().split_once('=').unwrap().1;
+    app.srv()
+        .session
+        .find(session_id)
+        .await?
+        .context("session not found")
+}
+
+#[tokio::test]
+async fn test_csrf_token_required_for_cookie_auth() -> Result<()> {
+    let app = TestApp::new().await?;
+    app.create_user("alice", &[]).await?;
+    let auth = app.login_cookie("alice").await?;
+    let Auth::Cookie { cookie, csrf } = &auth else {
+        unreachable!()
+    };
+
+    for token in [None, Some("wrong")] {
+        let mut req = Request::builder()
+            .method(Method::POST)
+            .uri("/api/auth/logout")
+            .header(header::COOKIE, cookie);
+        if let Some(token) = token {
+            req = req.header("x-csrf-token", token);
+        }
+        let resp = app.request(req.body(Body::empty())?).await?;
+        assert_eq!(resp.status, StatusCode::FORBIDDEN);
+        assert_eq!(resp.error_code()?, "auth.csrf_failed");
+    }
+
+    // A cross-site origin is rejected even with a valid token.
+    let req = Request::builder()
+        .method(Method::POST)
+        .uri("/api/auth/logout")
+        .header(header::COOKIE, cookie)
+        .header("x-csrf-token", csrf)
+        .header(header::ORIGIN, "https://evil.example")
+        .body(Body::empty())?;
+    let resp = app.request(req).await?;
+    assert_eq!(resp.status, StatusCode::FORBIDDEN);
+
+    app.post("/api/auth/logout", &auth, &json!({}))
+        .await?
+        .expect(StatusCode::OK)?;
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_csrf_skips_bearer_auth() -> Result<()> {
+    let app = TestApp::new().await?;
+    app.create_user("alice", &[]).await?;
+    let auth = app.login_jwt("alice").await?;
+
+    app.post("/api/auth/logout", &auth, &json!({}))
+        .await?
+        .expect(StatusCode::OK)?;
+
+    // A Bearer header doesn't let the session cookie through unchecked.
+    let Auth::Cookie { cookie, .. } = app.login_cookie("alice").await? else {
+        unreachable!()
+    };
+    let req = Request::builder()
+        .uri("/api/auth/csrf")
+        .header(header::COOKIE, cookie)
+        .header(header::AUTHORIZATION, "Bearer forged")
+        .body(Body::empty())?;
+    app.request(req).await?.expect(StatusCode::UNAUTHORIZED)?;
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_csrf_secret_required_in_production() -> Result<()> {
+    let mut cfg = RawAppConfig {
+        env: AppEnv::Production,
+        ..Default::default()
+    };
+    cfg.database.url = "sqlite::memory:".to_owned();
+    cfg.auth.csrf.mode = CsrfMode::DoubleSubmit;
+    let err = AppState::new(AppConfig::new(cfg.clone()))
+        .await
+        .unwrap_err();
+    assert_eq!(*err.kind(), ErrorKind::Config);
+
+    cfg.auth.csrf.secret = "a-real-secret".to_owned();
+    cfg.password.m_cost = 256;
+    cfg.password.t_cost = 1;
+    AppState::new(AppConfig::new(cfg)).await?;
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_same_site_none_requires_secure() -> Result<()> {
+    let mut cfg = RawAppConfig::default();
+    cfg.database.url = "sqlite::memory:".to_owned();
+    cfg.auth.session.cookie_same_site = CookieSameSite::None;
+    cfg.auth.session.cookie_secure = false;
+    let err = AppState::new(AppConfig::new(cfg.clone()))
+        .await
+        .unwrap_err();
+    assert_eq!(*err.kind(), ErrorKind::Config);
+
+    cfg.auth.session.cookie_secure = true;
+    cfg.password.m_cost = 256;
+    cfg.password.t_cost = 1;
+    AppState::new(AppConfig::new(cfg)).await?;
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_csrf_double_submit() -> Result<()> {
+    let app = TestApp::with_config(|cfg| cfg.auth.csrf.mode = CsrfMode::DoubleSubmit).await?;
+    app.create_user("alice", &[]).await?;
+    let auth = app.login_cookie("alice").await?;
+    let Auth::Cookie { cookie, csrf } = &auth else {
+        unreachable!()
+    };
+
+    // Header alone is not enough: it must match the token cookie.
+    let session_only = cookie.split("; ").next().unwrap().to_owned();
+    let resp = app
+        .post(
+            "/api/auth/logout",
+            &Auth::Cookie {
+                cookie: session_only,
+                csrf: csrf.clone(),
+            },
+            &json!({}),
+        )
+        .await?;
+    assert_eq!(resp.status, StatusCode::FORBIDDEN);
+
+    app.post("/api/auth/logout", &auth, &json!({}))
+        .await?
+        .expect(StatusCode::OK)?;
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_permission_guard() -> Result<()> {
+    let app = TestApp::new().await?;
+    app.create_user("reader", &[Perm::UserRead]).await?;
+    app.create_user("nobody", &[]).await?;
+
+    let reader = app.login_jwt("reader").await?;
+    let nobody = app.login_cookie("nobody").await?;
+
+    app.get("/api/users", &reader)
+        .await?
+        .expect(StatusCode::OK)?;
+    let resp = app.get("/api/users", &nobody).await?;
+    assert_eq!(resp.status, StatusCode::FORBIDDEN);
+
+    let resp = app
+        .post(
+            "/api/users",
+            &reader,
+            &json!({"username": "carol", "password": "password123"}),
+        )
+        .await?;
+    assert_eq!(resp.status, StatusCode::FORBIDDEN);
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_role_crud() -> Result<()> {
+    let app = TestApp::new().await?;
+    app.create_user("admin", &[Perm::RoleAll]).await?;
+    let admin = app.login_jwt("admin").await?;
+
+    let role = app
+        .post(
+            "/api/roles",
+            &admin,
+            &json!({"name": "viewer", "permissions": ["role:read"]}),
+        )
+        .await?
+        .expect(StatusCode::OK)?
+        .json::<Value>()?;
+    let id = role["id"].as_i64().unwrap();
+    assert_eq!(role["permissions"], json!(["role:read"]));
+    app.post("/api/roles", &admin, &json!({"name": "viewer"}))
+        .await?
+        .expect(StatusCode::CONFLICT)?;
+
+    let role = app
+        .put(
+            &format!("/api/roles/{id}"),
+            &admin,
+            &json!({"name": "reader", "description": "Read only"}),
+        )
+        .await?
+        .expect(StatusCode::OK)?
+        .json::<Value>()?;
+    assert_eq!(role["name"], "reader");
+    assert_eq!(role["description"], "Read only");
+
+    // Omitted fields are kept; a null description is cleared.
+    let role = app
+        .put(
+            &format!("/api/roles/{id}"),
+            &admin,
+            &json!({"name": "readers"}),
+        )
+        .await?
+        .expect(StatusCode::OK)?
+        .json::<Value>()?;
+    assert_eq!(role["name"], "readers");
+    assert_eq!(role["description"], "Read only");
+    let role = app
+        .put(
+            &format!("/api/roles/{id}"),
+            &admin,
+            &json!({"description": null}),
+        )
+        .await?
+        .expect(StatusCode::OK)?
+        .json::<Value>()?;
+    assert_eq!(role["name"], "readers");
+    assert_eq!(role["description"], Value::Null);
+
+    let role = app
+        .put(
+            &format!("/api/roles/{id}/permissions"),
+            &admin,
+            &json!({"permissions": ["role:read", "role:write"]}),
+        )
+        .await?
+        .expect(StatusCode::OK)?
+        .json::<Value>()?;
+    assert_eq!(role["permissions"], json!(["role:read", "role:write"]));
+
+    let list: Value = app
+        .get("/api/roles", &admin)
+        .await?
+        .expect(StatusCode::OK)?
+        .json()?;
+    assert!(
+        list["roles"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .any(|r| r["id"] == id)
+    );
+
+    app.delete(&format!("/api/roles/{id}"), &admin)
+        .await?
+        .expect(StatusCode::OK)?;
+    app.get(&format!("/api/roles/{id}"), &admin)
+        .await?
+        .expect(StatusCode::NOT_FOUND)?;
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_user_role_assignment() -> Result<()> {
+    let app = TestApp::new().await?;
+    let alice = app.create_user("alice", &[]).await?;
+    app.create_user("admin", &[Perm::RoleAll]).await?;
+    let admin = app.login_jwt("admin").await?;
+    let role = app
+        .srv()
+        .role
+        .create("viewer".to_owned(), None, &[Perm::RoleRead])
+        .await?;
+
+    let roles: Vec<Value> = app
+        .post(
+            &format!("/api/users/{alice}/roles"),
+            &admin,
+            &json!({ "role_id": role.id }),
+        )
+        .await?
+        .expect(StatusCode::OK)?
+        .json()?;
+    assert_eq!(roles.len(), 1);
+    assert_eq!(roles[0]["name"], "viewer");
+    app.post(
+        &format!("/api/users/{alice}/roles"),
+        &admin,
+        &json!({ "role_id": role.id }),
+    )
+    .await?
+    .expect(StatusCode::CONFLICT)?;
+
+    let alice_auth = app.login_jwt("alice").await?;
+    app.get("/api/roles", &alice_auth)
+        .await?
+        .expect(StatusCode::OK)?;
+
+    app.delete(&format!("/api/users/{alice}/roles/{}", role.id), &admin)
+        .await?
+        .expect(StatusCode::OK)?;
+    let roles: Vec<Value> = app
+        .get(&format!("/api/users/{alice}/roles"), &admin)
+        .await?
+        .expect(StatusCode::OK)?
+        .json()?;
+    assert!(roles.is_empty());
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_role_escalation_refused() -> Result<()> {
+    let app = TestApp::new().await?;
+    let admin_id = app
+        .create_user("admin", &[Perm::UserAll, Perm::RoleAll])
+        .await?;
+    let root_id = app.create_user("root", &[Perm::All]).await?;
+    let admin = app.login_jwt("admin").await?;
+    let superuser = app
+        .srv()
+        .role
+        .find_by_name("superuser")
+        .await?
+        .context("superuser role is seeded")?;
+    let own_role = app
+        .srv()
+        .role
+        .find_by_name("admin-role")
+        .await?
+        .context("admin has a role")?;
+
+    // Creating a role with a permission the caller lacks.
+    app.post(
+        "/api/roles",
+        &admin,
+        &json!({"name": "root2", "permissions": ["*"]}),
+    )
+    .await?
+    .expect(StatusCode::FORBIDDEN)?;
+
+    // Widening a role, including their own.
+    app.put(
+        &format!("/api/roles/{}/permissions", own_role.id),
+        &admin,
+        &json!({"permissions": ["*"]}),
+    )
+    .await?
+    .expect(StatusCode::FORBIDDEN)?;
+    // Stripping a role above theirs.
+    app.put(
+        &format!("/api/roles/{}/permissions", superuser.id),
+        &admin,
+        &json!({"permissions": []}),
+    )
+    .await?
+    .expect(StatusCode::FORBIDDEN)?;
+
+    // Assigning or removing a role above theirs.
+    app.post(
+        &format!("/api/users/{admin_id}/roles"),
+        &admin,
+        &json!({ "role_id": superuser.id }),
+    )
+    .await?
+    .expect(StatusCode::FORBIDDEN)?;
+    let root_role = app.srv().role.find_by_name("root-role").await?.unwrap();
+    app.delete(
+        &format!("/api/users/{root_id}/roles/{}", root_role.id),
+        &admin,
+    )
+    .await?
+    .expect(StatusCode::FORBIDDEN)?;
+
+    assert_eq!(
+        app.srv().role.get_user_permissions(admin_id).await?,
+        [Perm::UserAll, Perm::RoleAll]
+    );
+
+    // Within their own permissions they may still act.
+    app.post(
+        "/api/roles",
+        &admin,
+        &json!({"name": "helpdesk", "permissions": ["user:read", "role:read"]}),
+    )
+    .await?
+    .expect(StatusCode::OK)?;
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_role_delete_escalation_refused() -> Result<()> {
+    let app = TestApp::new().await?;
+    app.create_user("janitor", &[Perm::RoleDelete]).await?;
+    app.create_user("root", &[Perm::All]).await?;
+    let janitor = app.login_jwt("janitor").await?;
+    let root_role = app.srv().role.find_by_name("root-role").await?.unwrap();
+    let empty = app.srv().role.create("empty".to_owned(), None, &[]).await?;
+
+    app.delete(&format!("/api/roles/{}", root_role.id), &janitor)
+        .await?
+        .expect(StatusCode::FORBIDDEN)?;
+    assert!(app.srv().role.find_by_id(root_role.id).await?.is_some());
+
+    app.delete(&format!("/api/roles/{}", empty.id), &janitor)
+        .await?
+        .expect(StatusCode::OK)?;
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_health_probes() -> Result<()> {
+    let app = TestApp::new().await?;
+
+    let body: Value = app
+        .get("/health/live", &Auth::None)
+        .await?
+        .expect(StatusCode::OK)?
+        .json()?;
+    assert_eq!(body["status"], "up");
+
+    let body: Value = app
+        .get("/health/ready", &Auth::None)
+        .await?
+        .expect(StatusCode::OK)?
+        .json()?;
+    assert_eq!(body["status"], "up");
+    let names: Vec<_> = body["checks"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|c| c["name"].as_str().unwrap())
+        .collect();
+    assert_eq!(names, ["database", "migrations", "workers"]);
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_purge_expired() -> Result<()> {
+    let app = TestApp::with_config(|cfg| {
+        cfg.auth.session.idle_timeout_minutes = 0;
+        cfg.auth.jwt.refresh_expires_in_seconds = 0;
+    })
+    .await?;
+    app.create_user("alice", &[]).await?;
+    app.post(
+        "/api/auth/login",
+        &Auth::None,
+        &json!({ "username": "alice", "password": common::PASSWORD }),
+    )
+    .await?
+    .expect(StatusCode::OK)?;
+    app.login_tokens("alice").await?;
+    tokio::time::sleep(Duration::from_millis(10)).await;
+
+    app.srv().purge_expired().await?;
+    let mut db = app.state.db().clone();
+    assert_eq!(Session::all().count().exec(&mut db).await?, 0);
+    assert_eq!(RefreshToken::all().count().exec(&mut db).await?, 0);
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_ready_reports_failed_worker() -> Result<()> {
+    let app = TestApp::new().await?;
+
+    app.state
+        .workers()
+        .spawn("broken", |_| async { Err(ErrorKind::Internal.msg("boom")) })
+        .await?;
+
+    let resp = app.get("/health/ready", &Auth::None).await?;
+    assert_eq!(resp.status, StatusCode::SERVICE_UNAVAILABLE);
+    let body: Value = resp.json()?;
+    assert_eq!(body["checks"][2]["detail"]["broken"]["state"], "failed");
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_workers_stop_on_shutdown() -> Result<()> {
+    let app = TestApp::new().await?;
+    let workers = app.state.workers();
+    workers.spawn("waiter", |shutdown| async move {
+        shutdown.cancelled().await;
+        Ok(())
+    });
+
+    workers.shutdown_token().cancel();
+    tokio::time::timeout(Duration::from_secs(1), workers.join()).await?;
+    assert_eq!(workers.states()["waiter"], WorkerState::Finished);
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_cors_preflight() -> Result<()> {
+    let app = TestApp::with_config(|cfg| {
+        cfg.env = AppEnv::Production;
+        cfg.cors.allow_origins = Some(vec![
+            "https://app.example.com".to_string(),
+            "https://*.preview.example.com".to_string(),
+        ]);
+    })
+    .await?;
+
+    let preflight = |origin: &str| {
+        Request::builder()
+            .method(Method::OPTIONS)
+            .uri("/api/auth/me")
+            .header(header::ORIGIN, origin)
+            .header(header::ACCESS_CONTROL_REQUEST_METHOD, "POST")
+            .header(header::ACCESS_CONTROL_REQUEST_HEADERS, "x-csrf-token")
+            .body(Body::empty())
+    };
+
+    for origin in [
+        "https://app.example.com",
+        "https://pr-1.preview.example.com",
+    ] {
+        let resp = app.request(preflight(origin)?).await?;
+        assert_eq!(resp.headers[header::ACCESS_CONTROL_ALLOW_ORIGIN], origin);
+        assert_eq!(
+            resp.headers[header::ACCESS_CONTROL_ALLOW_CREDENTIALS],
+            "true"
+        );
+        // Cookie sessions need the CSRF header, whatever `allow-headers` says.
+        let allowed = resp.headers[header::ACCESS_CONTROL_ALLOW_HEADERS].to_str()?;
+        assert!(allowed.contains("x-csrf-token"), "{allowed}");
+    }
+
+    let resp = app.request(preflight("https://evil.com")?).await?;
+    assert!(
+        !resp
+            .headers
+            .contains_key(header::ACCESS_CONTROL_ALLOW_ORIGIN)
+    );
+    Ok(())
+}
+
+#[test]
+fn test_route_permissions_recorded() {
+    let (_, api) = api_router().split_for_parts();
+    let perms = route_permissions(&api);
+
+    let find = |method: &str, path: &str| {
+        perms
+            .iter()
+            .find(|(m, p, _)| *m == method && p == path)
+            .map(|(_, _, perm)| *perm)
+    };
+
+    assert_eq!(find("GET", "/api/users"), Some(Perm::UserRead));
+    assert_eq!(find("POST", "/api/users"), Some(Perm::UserWrite));
+    assert_eq!(find("GET", "/api/users/{id}"), Some(Perm::UserRead));
+    assert_eq!(find("DELETE", "/api/users/{id}"), Some(Perm::UserDelete));
+    assert_eq!(
+        find("PUT", "/api/users/{id}/username"),
+        Some(Perm::UserWrite)
+    );
+    assert_eq!(
+        find("PUT", "/api/roles/{id}/permissions"),
+        Some(Perm::RoleWrite)
+    );
+    assert_eq!(find("DELETE", "/api/roles/{id}"), Some(Perm::RoleDelete));
+    assert_eq!(find("GET", "/api/users/{id}/roles"), Some(Perm::RoleRead));
+    assert_eq!(
+        find("DELETE", "/api/users/{id}/roles/{role_id}"),
+        Some(Perm::RoleWrite)
+    );
+    assert_eq!(find("GET", "/api/auth/me"), None);
+}
+
+#[tokio::test]
+async fn test_route_permissions_enforced() -> Result<()> {
+    let app = TestApp::new().await?;
+    let (_, api) = api_router().split_for_parts();
+    let perms = route_permissions(&api);
+    assert!(!perms.is_empty());
+
+    app.create_user("nobody", &[]).await?;
+    let nobody = app.login_jwt("nobody").await?;
+    let mut holders = HashMap::new();
+    for perm in Perm::all() {
+        let name = format!("holder-{perm:?}");
+        app.create_user(&name, &[perm]).await?;
+        holders.insert(perm, app.login_jwt(&name).await?);
+    }
+    // Only the guard's own refusal counts; the handlers may still reject
+    // the empty body or the missing ids.
+    let denied = |resp: &TestResponse| {
+        let body: Value = serde_json::from_slice(&resp.body).unwrap_or_default();
+        resp.status == StatusCode::FORBIDDEN && body["detail"] == "Insufficient permissions"
+    };
+
+    let empty = json!({});
+    for (method, path, perm) in perms {
+        let uri = path
+            .replace("{id}", "999999")
+            .replace("{role_id}", "999999");
+        let method = Method::from_bytes(method.as_bytes())?;
+        let body = (method != Method::GET).then_some(&empty);
+
+        let resp = app.send(method.clone(), &uri, &nobody, body).await?;
+        assert!(denied(&resp), "{method} {uri} allowed without {perm}");
+        let resp = app
+            .send(method.clone(), &uri, &holders[&perm], body)
+            .await?;
+        assert!(!denied(&resp), "{method} {uri} refused with {perm}");
+    }
     Ok(())
 }
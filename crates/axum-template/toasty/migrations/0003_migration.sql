@@ -0,0 +1,3 @@
+ALTER TABLE "sessions" ADD COLUMN "last_seen_at" TIMESTAMPTZ(6) NOT NULL DEFAULT now();
+-- #[toasty::breakpoint]
+UPDATE "sessions" SET "last_seen_at" = "created_at";
@@ -0,0 +1,7 @@
+ALTER TABLE "refresh_tokens" ADD COLUMN "revoked" BOOLEAN NOT NULL DEFAULT false;
+ALTER TABLE "refresh_tokens" ADD COLUMN "parent_id" BIGINT;
+ALTER TABLE "refresh_tokens" ADD COLUMN "family_id" TEXT NOT NULL DEFAULT '';
+-- #[toasty::breakpoint]
+-- Existing tokens each start their own family.
+UPDATE "refresh_tokens" SET "family_id" = "token";
+CREATE INDEX "index_refresh_tokens_by_family_id" ON "refresh_tokens" ("family_id");
@@ -0,0 +1,13 @@
+ALTER TABLE "refresh_tokens" RENAME COLUMN "token" TO "token_hash";
+DROP INDEX "index_refresh_tokens_by_token";
+CREATE UNIQUE INDEX "index_refresh_tokens_by_token_hash" ON "refresh_tokens" ("token_hash");
+ALTER TABLE "sessions" RENAME COLUMN "session_id" TO "session_id_hash";
+DROP INDEX "index_sessions_by_session_id";
+CREATE UNIQUE INDEX "index_sessions_by_session_id_hash" ON "sessions" ("session_id_hash");
+-- #[toasty::breakpoint]
+-- Existing values are plaintext ids without the `sess_`/`rt_` prefix and
+-- checksum that lookups now require, so hashing them would not keep them
+-- usable; drop them instead, and users log in again.
+DELETE FROM "refresh_tokens";
+-- #[toasty::breakpoint]
+DELETE FROM "sessions";
@@ -0,0 +1,25 @@
+ALTER TABLE "users" ADD COLUMN "totp_enabled" BOOLEAN NOT NULL DEFAULT false;
+ALTER TABLE "users" ADD COLUMN "totp_secret" TEXT;
+ALTER TABLE "users" ADD COLUMN "totp_last_step" BIGINT;
+ALTER TABLE "roles" ADD COLUMN "require_mfa" BOOLEAN NOT NULL DEFAULT false;
+CREATE TABLE "recovery_codes" (
+    "id" BIGINT NOT NULL GENERATED BY DEFAULT AS IDENTITY,
+    "user_id" BIGINT NOT NULL,
+    "code_hash" TEXT NOT NULL,
+    "used_at" TIMESTAMPTZ(6),
+    "created_at" TIMESTAMPTZ(6) NOT NULL,
+    PRIMARY KEY ("id")
+);
+CREATE INDEX "index_recovery_codes_by_user_id" ON "recovery_codes" ("user_id");
+CREATE UNIQUE INDEX "index_recovery_codes_by_code_hash" ON "recovery_codes" ("code_hash");
+CREATE TABLE "mfa_challenges" (
+    "id" BIGINT NOT NULL GENERATED BY DEFAULT AS IDENTITY,
+    "user_id" BIGINT NOT NULL,
+    "token_hash" TEXT NOT NULL,
+    "attempts" BIGINT NOT NULL,
+    "expires_at" TIMESTAMPTZ(6) NOT NULL,
+    "created_at" TIMESTAMPTZ(6) NOT NULL,
+    PRIMARY KEY ("id")
+);
+CREATE INDEX "index_mfa_challenges_by_user_id" ON "mfa_challenges" ("user_id");
+CREATE UNIQUE INDEX "index_mfa_challenges_by_token_hash" ON "mfa_challenges" ("token_hash");
@@ -0,0 +1,9 @@
+CREATE TABLE "login_throttles" (
+    "id" BIGINT NOT NULL GENERATED BY DEFAULT AS IDENTITY,
+    "key" TEXT NOT NULL,
+    "failures" BIGINT NOT NULL,
+    "blocked_until" TIMESTAMPTZ(6),
+    "last_failure_at" TIMESTAMPTZ(6) NOT NULL,
+    PRIMARY KEY ("id")
+);
+CREATE UNIQUE INDEX "index_login_throttles_by_key" ON "login_throttles" ("key");
@@ -0,0 +1,52 @@
+CREATE TABLE "refresh_tokens" (
+    "id" INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+    "user_id" BIGINT NOT NULL,
+    "token" TEXT NOT NULL,
+    "expires_at" TEXT NOT NULL,
+    "created_at" TEXT NOT NULL
+);
+-- #[toasty::breakpoint]
+CREATE UNIQUE INDEX "index_refresh_tokens_by_token" ON "refresh_tokens" ("token");
+-- #[toasty::breakpoint]
+CREATE TABLE "sessions" (
+    "id" INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+    "user_id" BIGINT NOT NULL,
+    "session_id" TEXT NOT NULL,
+    "expires_at" TEXT NOT NULL,
+    "created_at" TEXT NOT NULL
+);
+-- #[toasty::breakpoint]
+CREATE UNIQUE INDEX "index_sessions_by_session_id" ON "sessions" ("session_id");
+-- #[toasty::breakpoint]
+CREATE TABLE "user_roles" (
+    "id" INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+    "user_id" BIGINT NOT NULL,
+    "role_id" BIGINT NOT NULL,
+    "created_at" TEXT NOT NULL
+);
+-- #[toasty::breakpoint]
+CREATE INDEX "index_user_roles_by_user_id" ON "user_roles" ("user_id");
+-- #[toasty::breakpoint]
+CREATE INDEX "index_user_roles_by_role_id" ON "user_roles" ("role_id");
+-- #[toasty::breakpoint]
+CREATE TABLE "users" (
+    "id" INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+    "username" TEXT NOT NULL,
+    "password" TEXT NOT NULL,
+    "must_change_password" BOOLEAN NOT NULL,
+    "created_at" TEXT NOT NULL,
+    "updated_at" TEXT NOT NULL
+);
+-- #[toasty::breakpoint]
+CREATE UNIQUE INDEX "index_users_by_username" ON "users" ("username");
+-- #[toasty::breakpoint]
+CREATE TABLE "roles" (
+    "id" INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+    "name" TEXT NOT NULL,
+    "description" TEXT,
+    "permissions" TEXT NOT NULL,
+    "created_at" TEXT NOT NULL,
+    "updated_at" TEXT NOT NULL
+);
+-- #[toasty::breakpoint]
+CREATE UNIQUE INDEX "index_roles_by_name" ON "roles" ("name");
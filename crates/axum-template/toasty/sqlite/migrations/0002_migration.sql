@@ -0,0 +1,3 @@
+ALTER TABLE "sessions" ADD COLUMN "last_seen_at" TEXT NOT NULL DEFAULT '1970-01-01T00:00:00Z';
+-- #[toasty::breakpoint]
+UPDATE "sessions" SET "last_seen_at" = "created_at";
@@ -0,0 +1,3 @@
+ALTER TABLE "sessions" ADD COLUMN "user_agent" TEXT;
+-- #[toasty::breakpoint]
+ALTER TABLE "sessions" ADD COLUMN "ip" TEXT;
@@ -0,0 +1,10 @@
+ALTER TABLE "refresh_tokens" ADD COLUMN "family_id" TEXT NOT NULL DEFAULT '';
+-- #[toasty::breakpoint]
+ALTER TABLE "refresh_tokens" ADD COLUMN "parent_id" BIGINT;
+-- #[toasty::breakpoint]
+ALTER TABLE "refresh_tokens" ADD COLUMN "revoked" BOOLEAN NOT NULL DEFAULT 0;
+-- #[toasty::breakpoint]
+-- Existing tokens each start their own family.
+UPDATE "refresh_tokens" SET "family_id" = "token";
+-- #[toasty::breakpoint]
+CREATE INDEX "index_refresh_tokens_by_family_id" ON "refresh_tokens" ("family_id");
@@ -0,0 +1,18 @@
+ALTER TABLE "refresh_tokens" RENAME COLUMN "token" TO "token_hash";
+-- #[toasty::breakpoint]
+DROP INDEX "index_refresh_tokens_by_token";
+-- #[toasty::breakpoint]
+CREATE UNIQUE INDEX "index_refresh_tokens_by_token_hash" ON "refresh_tokens" ("token_hash");
+-- #[toasty::breakpoint]
+ALTER TABLE "sessions" RENAME COLUMN "session_id" TO "session_id_hash";
+-- #[toasty::breakpoint]
+DROP INDEX "index_sessions_by_session_id";
+-- #[toasty::breakpoint]
+CREATE UNIQUE INDEX "index_sessions_by_session_id_hash" ON "sessions" ("session_id_hash");
+-- #[toasty::breakpoint]
+-- Existing values are plaintext ids without the `sess_`/`rt_` prefix and
+-- checksum that lookups now require, so hashing them would not keep them
+-- usable; drop them instead, and users log in again.
+DELETE FROM "refresh_tokens";
+-- #[toasty::breakpoint]
+DELETE FROM "sessions";
@@ -0,0 +1,32 @@
+ALTER TABLE "users" ADD COLUMN "totp_last_step" BIGINT;
+-- #[toasty::breakpoint]
+ALTER TABLE "users" ADD COLUMN "totp_enabled" BOOLEAN NOT NULL DEFAULT 0;
+-- #[toasty::breakpoint]
+ALTER TABLE "users" ADD COLUMN "totp_secret" TEXT;
+-- #[toasty::breakpoint]
+ALTER TABLE "roles" ADD COLUMN "require_mfa" BOOLEAN NOT NULL DEFAULT 0;
+-- #[toasty::breakpoint]
+CREATE TABLE "mfa_challenges" (
+    "id" INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+    "user_id" BIGINT NOT NULL,
+    "token_hash" TEXT NOT NULL,
+    "attempts" BIGINT NOT NULL,
+    "expires_at" TEXT NOT NULL,
+    "created_at" TEXT NOT NULL
+);
+-- #[toasty::breakpoint]
+CREATE INDEX "index_mfa_challenges_by_user_id" ON "mfa_challenges" ("user_id");
+-- #[toasty::breakpoint]
+CREATE UNIQUE INDEX "index_mfa_challenges_by_token_hash" ON "mfa_challenges" ("token_hash");
+-- #[toasty::breakpoint]
+CREATE TABLE "recovery_codes" (
+    "id" INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+    "user_id" BIGINT NOT NULL,
+    "code_hash" TEXT NOT NULL,
+    "used_at" TEXT,
+    "created_at" TEXT NOT NULL
+);
+-- #[toasty::breakpoint]
+CREATE INDEX "index_recovery_codes_by_user_id" ON "recovery_codes" ("user_id");
+-- #[toasty::breakpoint]
+CREATE UNIQUE INDEX "index_recovery_codes_by_code_hash" ON "recovery_codes" ("code_hash");
@@ -0,0 +1,9 @@
+CREATE TABLE "login_throttles" (
+    "id" INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+    "key" TEXT NOT NULL,
+    "failures" BIGINT NOT NULL,
+    "blocked_until" TEXT,
+    "last_failure_at" TEXT NOT NULL
+);
+-- #[toasty::breakpoint]
+CREATE UNIQUE INDEX "index_login_throttles_by_key" ON "login_throttles" ("key");